@@ -0,0 +1,298 @@
+use std::collections::BTreeSet;
+use std::io::{stdin, stdout, Write};
+use std::process::exit;
+
+use failure::{Error, ResultExt};
+use oftb::flatanf::Expr;
+use oftb::interpreter::{Control, Interpreter, Kont, State, Store, Value};
+use oftb::intrinsics::Intrinsics;
+use symbol::Symbol;
+
+use interpret::{load_program, main_call};
+use options::DebugOptions;
+
+const HELP: &str = "Commands:
+  break NAME     (b)  Stop when the global NAME is initialized or called.
+  delete NAME    (d)  Remove the breakpoint on NAME.
+  breakpoints         List the breakpoints.
+  step           (s)  Evaluate a single step.
+  next           (n)  Step, without stopping inside calls.
+  finish         (f)  Run until the current function returns.
+  continue       (c)  Run until a breakpoint is hit.
+  print          (p)  Print the current expression.
+  locals [N]     (l)  Print the local variables, or only the local $N.
+  konts          (k)  Print the continuation stack, innermost first.
+  help           (h)  Print this message.
+  quit           (q)  Exit the debugger.
+An empty line repeats the last command.";
+
+pub fn run(options: DebugOptions) -> Result<(), Error> {
+    let program = load_program(&options.file)?;
+    let main = main_call(&options.args);
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    let mut debugger = Debugger::new();
+
+    println!("Loaded {} decls. Type `help' for a list of commands.", program.decls.len());
+    for &(name, ref expr) in &program.decls {
+        let val = debugger
+            .eval(&mut interpreter, expr, Some(name))
+            .with_context(|_| format!("While initializing `{}'", name))?;
+        if let Value::Closure(addr) = val {
+            interpreter.store.mutate_closure_name(addr, name);
+        }
+        interpreter.globals.insert(name, val);
+    }
+
+    let retval = debugger
+        .eval(&mut interpreter, &main, None)
+        .context("While running `main:main'")?;
+    println!("main:main returned {}", retval.display(&interpreter.store, false));
+    Ok(())
+}
+
+/// When the debugger next stops to prompt for a command.
+#[derive(Clone, Copy, Debug)]
+enum Mode {
+    /// Stop before the next step.
+    Step,
+
+    /// Stop once the continuation stack is no deeper than the given depth.
+    Depth(usize),
+
+    /// Stop only at breakpoints.
+    Continue,
+}
+
+/// The state of the debugger.
+struct Debugger {
+    breakpoints: BTreeSet<String>,
+    decl: Option<Symbol>,
+    last_command: String,
+    mode: Mode,
+}
+
+impl Mode {
+    /// Returns whether to stop before the next step.
+    fn stops(self, interpreter: &Interpreter) -> bool {
+        match *interpreter.state() {
+            State::Running(..) => {}
+            _ => return false,
+        }
+        match self {
+            Mode::Step => true,
+            Mode::Depth(stop) => interpreter.depth() <= stop,
+            Mode::Continue => false,
+        }
+    }
+}
+
+impl Debugger {
+    fn new() -> Debugger {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            decl: None,
+            last_command: String::new(),
+            mode: Mode::Step,
+        }
+    }
+
+    /// Evaluates an expression, prompting for commands whenever execution
+    /// stops. `decl` is the name of the global being initialized, if any.
+    fn eval<'program>(
+        &mut self,
+        interpreter: &mut Interpreter<'program>,
+        expr: &'program Expr,
+        decl: Option<Symbol>,
+    ) -> Result<Value, Error> {
+        interpreter.load_expr(expr);
+        self.decl = decl;
+        if let Some(name) = decl.and_then(|name| self.breakpoint(name)) {
+            println!("Breakpoint at `{}'", name);
+            self.prompt(interpreter)?;
+        } else if self.mode.stops(interpreter) {
+            self.prompt(interpreter)?;
+        }
+
+        loop {
+            let result = {
+                let mode = self.mode;
+                let breakpoints = &self.breakpoints;
+                interpreter.step_until(|interpreter| {
+                    match interpreter.entered_function() {
+                        Some(name) if breakpoints.contains(name.as_str()) => true,
+                        _ => mode.stops(interpreter),
+                    }
+                })
+            };
+            match result {
+                Some(Ok(val)) => return Ok(val),
+                Some(Err(err)) => {
+                    if let Some(trace) = interpreter.stack_trace() {
+                        if !trace.0.is_empty() {
+                            println!("Stack trace (innermost first):\n{}", trace);
+                        }
+                    }
+                    return Err(err.into());
+                }
+                None => {
+                    if let Some(name) = interpreter
+                        .entered_function()
+                        .and_then(|name| self.breakpoint(name))
+                    {
+                        println!("Breakpoint at `{}'", name);
+                    }
+                    self.prompt(interpreter)?;
+                }
+            }
+        }
+    }
+
+    /// Returns the name if there is a breakpoint on it.
+    fn breakpoint(&self, name: Symbol) -> Option<Symbol> {
+        if self.breakpoints.contains(name.as_str()) {
+            Some(name)
+        } else {
+            None
+        }
+    }
+
+    /// Prints the current expression, then reads and runs commands until one
+    /// resumes execution.
+    fn prompt(&mut self, interpreter: &Interpreter) -> Result<(), Error> {
+        let (expr, env, konts) = match *interpreter.state() {
+            State::Running(Control::Normal(expr), ref env, ref konts) => (expr, env, konts),
+            _ => return Ok(()),
+        };
+        let frame = konts
+            .iter()
+            .rev()
+            .filter_map(|kont| match *kont {
+                Kont::Frame(addr) => Some(interpreter.store.get_closure(addr).2),
+                _ => None,
+            })
+            .next();
+        match frame {
+            Some(Some(name)) => println!("In {}: {}", name, first_line(expr)),
+            Some(None) => println!("In <lambda>: {}", first_line(expr)),
+            None => match self.decl {
+                Some(name) => println!("Initializing {}: {}", name, first_line(expr)),
+                None => println!("{}", first_line(expr)),
+            },
+        }
+
+        loop {
+            print!("(oftb) ");
+            stdout().flush()?;
+            let mut line = String::new();
+            if stdin().read_line(&mut line)? == 0 {
+                println!();
+                exit(0);
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                if self.last_command.is_empty() {
+                    continue;
+                }
+            } else {
+                self.last_command = line.to_string();
+            }
+
+            let command = self.last_command.clone();
+            let mut words = command.split_whitespace();
+            let name = words.next().unwrap_or("");
+            let arg = words.next();
+            match (name, arg) {
+                ("b", Some(arg)) | ("break", Some(arg)) => {
+                    self.breakpoints.insert(arg.to_string());
+                    println!("Breakpoint set on `{}'", arg);
+                }
+                ("d", Some(arg)) | ("delete", Some(arg)) => {
+                    if !self.breakpoints.remove(arg) {
+                        println!("No breakpoint on `{}'", arg);
+                    }
+                }
+                ("breakpoints", None) => for name in &self.breakpoints {
+                    println!("{}", name);
+                },
+                ("s", None) | ("step", None) => {
+                    self.mode = Mode::Step;
+                    return Ok(());
+                }
+                ("n", None) | ("next", None) => {
+                    self.mode = Mode::Depth(interpreter.depth());
+                    return Ok(());
+                }
+                ("f", None) | ("finish", None) => {
+                    self.mode = match interpreter.return_depth() {
+                        Some(i) => Mode::Depth(i),
+                        None => Mode::Continue,
+                    };
+                    return Ok(());
+                }
+                ("c", None) | ("continue", None) => {
+                    self.mode = Mode::Continue;
+                    return Ok(());
+                }
+                ("p", None) | ("print", None) => println!("{}", expr),
+                ("l", None) | ("locals", None) => {
+                    for (i, val) in env.locals().into_iter().enumerate() {
+                        println!("${} = {}", i, val.display(&interpreter.store, false));
+                    }
+                }
+                ("l", Some(arg)) | ("locals", Some(arg)) => {
+                    let locals = env.locals();
+                    let index = arg.strip_prefix('$').unwrap_or(arg);
+                    match index.parse::<usize>() {
+                        Ok(i) if i < locals.len() => {
+                            println!("${} = {}", i, locals[i].display(&interpreter.store, false))
+                        }
+                        Ok(i) => println!("No local ${}; there are {}", i, locals.len()),
+                        Err(_) => println!("Not a local index: {}", arg),
+                    }
+                }
+                ("k", None) | ("konts", None) => {
+                    for (i, kont) in konts.iter().rev().enumerate() {
+                        println!("#{} {}", i, describe_kont(kont, &interpreter.store));
+                    }
+                }
+                ("h", None) | ("help", None) => println!("{}", HELP),
+                ("q", None) | ("quit", None) => exit(0),
+                _ => println!("Unknown command `{}'. Type `help' for a list of commands.", command),
+            }
+        }
+    }
+}
+
+/// Describes an entry on the continuation stack.
+fn describe_kont(kont: &Kont, store: &Store) -> String {
+    match *kont {
+        Kont::Escape(_) => "escape continuation marker".to_string(),
+        Kont::Frame(addr) => match store.get_closure(addr).2 {
+            Some(name) => format!("frame {}", name),
+            None => "frame <lambda>".to_string(),
+        },
+        Kont::Handler(handler) => format!("handler {}", handler.display(store, false)),
+        Kont::Let(expr, _) => format!("let, then {}", first_line(expr)),
+        Kont::MakeVector(cur, last, func, _) => format!(
+            "make-vector, element {} of {} from {}",
+            cur,
+            last + 1,
+            func.display(store, false)
+        ),
+        Kont::Seq(expr, _) => format!("seq, then {}", first_line(expr)),
+    }
+}
+
+/// Displays the first line of an expression, marking it if it is truncated.
+fn first_line(expr: &Expr) -> String {
+    let s = expr.to_string();
+    let mut lines = s.lines();
+    let first = lines.next().unwrap_or("");
+    if lines.next().is_some() {
+        format!("{} ...", first)
+    } else {
+        first.to_string()
+    }
+}
@@ -1,17 +1,67 @@
 use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
 
-use failure::Error;
+use failure::{Error, ResultExt};
 use oftb::flatanf::{AExpr, CExpr, Expr, Program};
 use oftb::interpreter::{Interpreter, Value};
 use oftb::intrinsics::Intrinsics;
 use oftb::Literal;
 
+use eval;
 use options::InterpretOptions;
 
 pub fn run(options: InterpretOptions) -> Result<(), Error> {
-    // Load the bytecode file.
+    let program = load_program(&options.file)?;
+    let main = main_call(&options.args);
+
+    // Create the interpreter.
+    let mut interpreter = options.interpreter.build();
+    let max_steps = options.interpreter.max_steps;
+    interpreter
+        .store
+        .set_program_name(Some(options.file.display().to_string()));
+
+    if let Some(ref path) = options.image {
+        // Load the globals from the image.
+        debug!("Loading image...");
+        let mut f = BufReader::new(File::open(path)?);
+        interpreter
+            .load_image::<Intrinsics, _>(&program, &mut f)
+            .with_context(|_| format!("While loading the image {}", path.display()))?;
+    } else {
+        // Start interpreting global decls.
+        init_globals(&mut interpreter, &program, max_steps)?;
+    }
+
+    if let Some(ref path) = options.save_image {
+        debug!("Saving image...");
+        let mut f = BufWriter::new(File::create(path)?);
+        interpreter
+            .save_image::<Intrinsics, _>(&program, &mut f)
+            .with_context(|_| format!("While saving the image {}", path.display()))?;
+        f.flush()?;
+    }
+
+    // Call main.
+    debug!("Running program...");
+    let retval = eval(&mut interpreter, &main, "main:main".into(), max_steps)
+        .context("While running `main:main'")?;
+    if retval != Value::Nil {
+        println!("{}", retval.display(&interpreter.store, false));
+    }
+    options.interpreter.write_profile(&mut interpreter)?;
+    options
+        .interpreter
+        .write_coverage(&mut interpreter, &program)?;
+
+    Ok(())
+}
+
+/// Loads a bytecode file, checking that it has a `main:main` function.
+pub fn load_program(path: &Path) -> Result<Program, Error> {
     let program = {
-        let mut f = File::open(options.file)?;
+        let mut f = File::open(path)?;
         Program::deserialize_from(&mut f)?
     };
     trace!("{:#?}", program);
@@ -23,32 +73,32 @@ pub fn run(options: InterpretOptions) -> Result<(), Error> {
     {
         bail!("Missing main:main function.")
     }
+    Ok(program)
+}
 
-    // Create the expression for the call to main.
-    let args = options.args.into_iter().map(Literal::String).collect();
-    let args = AExpr::Literal(Literal::list(args));
-    let main = Expr::CExpr(CExpr::Call(AExpr::Global("main:main".into()), vec![args]));
-
-    // Create the interpreter.
-    let mut interpreter = Interpreter::new();
-    interpreter.add_builtins::<Intrinsics>();
-
-    // Start interpreting global decls.
+/// Initializes the globals of a program, naming the closures they're bound to
+/// after them, so they show up by name in stack traces and profiles.
+pub fn init_globals<'program>(
+    interpreter: &mut Interpreter<'program>,
+    program: &'program Program,
+    max_steps: Option<usize>,
+) -> Result<(), Error> {
     debug!("Initializing program...");
     for &(name, ref expr) in &program.decls {
-        let val = interpreter.eval(expr);
+        let val = eval(interpreter, expr, name, max_steps)
+            .with_context(|_| format!("While initializing `{}'", name))?;
         if let Value::Closure(addr) = val {
             interpreter.store.mutate_closure_name(addr, name);
         }
         interpreter.globals.insert(name, val);
     }
-
-    // Call main.
-    debug!("Running program...");
-    let retval = interpreter.eval(&main);
-    if retval != Value::Nil {
-        println!("{}", retval.display(&interpreter.store, false));
-    }
-
     Ok(())
 }
+
+/// Creates the expression for the call to `main:main` with the given
+/// arguments.
+pub fn main_call(args: &[String]) -> Expr {
+    let args = args.iter().cloned().map(Literal::String).collect();
+    let args = AExpr::Literal(Literal::list(args));
+    Expr::CExpr(CExpr::Call(AExpr::Global("main:main".into()), vec![args]))
+}
@@ -1,3 +1,4 @@
+extern crate ctrlc;
 #[macro_use]
 extern crate failure;
 #[macro_use]
@@ -6,15 +7,22 @@ extern crate oftb;
 extern crate stderrlog;
 #[macro_use]
 extern crate structopt;
+extern crate symbol;
 
 mod compile;
+mod debug;
 mod interpret;
 mod options;
 mod run;
 
+use std::io::{stdout, Write};
 use std::process::exit;
 
+use failure::Error;
+use oftb::flatanf::Expr;
+use oftb::interpreter::{Evaluation, Interpreter, Pause, RuntimeError, Value};
 use structopt::StructOpt;
+use symbol::Symbol;
 
 use options::{Options, Subcommand};
 
@@ -24,11 +32,20 @@ fn main() {
 
     let result = match options.subcommand {
         Subcommand::Compile(options) => compile::run(options),
+        Subcommand::Debug(options) => debug::run(options),
         Subcommand::Interpret(options) => interpret::run(options),
         Subcommand::Run(options) => run::run(options),
     };
 
     if let Err(err) = result {
+        // A program that exits isn't an error, but is reported as one so that
+        // it stops evaluation.
+        let code = err.causes().filter_map(|cause| cause.downcast_ref()).next();
+        if let Some(&RuntimeError::Exit(code)) = code {
+            stdout().flush().ok();
+            exit(code);
+        }
+
         let mut first = true;
         let num_errs = err.causes().count();
         if num_errs <= 1 {
@@ -47,3 +64,40 @@ fn main() {
         exit(1);
     }
 }
+
+/// Evaluates an expression, logging the stack trace if evaluation is stopped
+/// by a runtime error. If a maximum number of steps is given, evaluation
+/// fails once the interpreter has run for that many steps in total. If
+/// profiling is enabled, steps are attributed to the given name.
+fn eval<'program>(
+    interpreter: &mut Interpreter<'program>,
+    expr: &'program Expr,
+    name: Symbol,
+    max_steps: Option<usize>,
+) -> Result<Value, Error> {
+    if let Some(profile) = interpreter.profile_mut() {
+        profile.set_root(Some(name));
+    }
+
+    let fuel = match max_steps {
+        Some(max_steps) => max_steps.saturating_sub(interpreter.steps()),
+        None => usize::MAX,
+    };
+    let result = interpreter.eval_with_fuel(expr, fuel);
+    if let Some(trace) = interpreter.stack_trace() {
+        if !trace.0.is_empty() {
+            error!("Stack trace (innermost first):");
+            for line in trace.to_string().lines() {
+                error!("{}", line);
+            }
+        }
+    }
+    match result? {
+        Evaluation::Done(val) => Ok(val),
+        Evaluation::Paused(Pause::Interrupted) => bail!("Interrupted"),
+        Evaluation::Paused(Pause::OutOfFuel) => {
+            bail!("Ran for more than {} steps", max_steps.unwrap_or(fuel))
+        }
+        Evaluation::Paused(Pause::TimedOut) => bail!("Timed out"),
+    }
+}
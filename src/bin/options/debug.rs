@@ -0,0 +1,12 @@
+use std::path::PathBuf;
+
+/// The `debug` subcommand.
+#[derive(Debug, StructOpt)]
+pub struct DebugOptions {
+    /// The bytecode to debug.
+    #[structopt(name = "FILE", parse(from_os_str))]
+    pub file: PathBuf,
+
+    /// Any options to pass to the program being debugged.
+    pub args: Vec<String>,
+}
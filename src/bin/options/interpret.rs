@@ -1,5 +1,9 @@
 use std::path::PathBuf;
 
+use structopt::StructOpt;
+
+use options::InterpreterOptions;
+
 /// The `interpret` subcommand.
 #[derive(Debug, StructOpt)]
 pub struct InterpretOptions {
@@ -9,4 +13,16 @@ pub struct InterpretOptions {
 
     /// Any options to pass to the program being run.
     pub args: Vec<String>,
+
+    /// An image to load instead of initializing the program. The image must
+    /// have been saved from the same bytecode with `--save-image`.
+    #[structopt(long = "image", name = "IMAGE-PATH", parse(from_os_str))]
+    pub image: Option<PathBuf>,
+
+    /// A path to save an image to after initializing the program.
+    #[structopt(long = "save-image", name = "SAVE-IMAGE-PATH", parse(from_os_str))]
+    pub save_image: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    pub interpreter: InterpreterOptions,
 }
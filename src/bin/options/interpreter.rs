@@ -0,0 +1,126 @@
+use std::fs::File;
+use std::io::stderr;
+use std::path::PathBuf;
+use std::process::exit;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use ctrlc;
+use failure::{Error, ResultExt};
+use oftb::flatanf::Program;
+use oftb::interpreter::{Coverage, Interpreter, Profile, Rng};
+use oftb::intrinsics::Intrinsics;
+
+/// Options for the interpreter, shared between the `interpret` and `run`
+/// subcommands.
+#[derive(Debug, StructOpt)]
+pub struct InterpreterOptions {
+    /// The heap size, in megabytes, at which garbage is first collected. If
+    /// zero, garbage collection is disabled.
+    #[structopt(long = "gc-threshold", name = "MEGABYTES", default_value = "64")]
+    pub gc_threshold: usize,
+
+    /// The maximum number of steps to evaluate before stopping the program.
+    #[structopt(long = "max-steps", name = "STEPS")]
+    pub max_steps: Option<usize>,
+
+    /// The maximum number of seconds to run for before stopping the program.
+    #[structopt(long = "timeout", name = "SECONDS")]
+    pub timeout: Option<u64>,
+
+    /// A path to write a profile of the program to, in the folded stack format
+    /// used by flamegraph tools. A summary is also printed to stderr.
+    #[structopt(long = "profile", name = "PROFILE-PATH", parse(from_os_str))]
+    pub profile: Option<PathBuf>,
+
+    /// A path to write coverage of the program to, as an lcov tracefile. A
+    /// summary is also printed to stderr.
+    #[structopt(long = "coverage", name = "COVERAGE-PATH", parse(from_os_str))]
+    pub coverage: Option<PathBuf>,
+
+    /// The seed for the random number generator. If not given, a seed is
+    /// chosen from the current time, and logged at the debug level so the run
+    /// can be reproduced.
+    #[structopt(long = "seed", name = "SEED")]
+    pub seed: Option<u64>,
+}
+
+impl InterpreterOptions {
+    /// Creates an interpreter with the builtins loaded, configured as
+    /// specified by the options. The timeout starts when this is called.
+    ///
+    /// This also installs a Ctrl-C handler that interrupts the interpreter. A
+    /// second Ctrl-C before the interpreter notices the first exits, so a
+    /// program blocked on input can still be killed.
+    pub fn build<'program>(&self) -> Interpreter<'program> {
+        let mut interpreter = Interpreter::new();
+        interpreter.add_builtins::<Intrinsics>();
+        interpreter.set_gc_threshold(self.gc_threshold());
+        interpreter.set_deadline(
+            self.timeout
+                .map(|secs| Instant::now() + Duration::from_secs(secs)),
+        );
+        if self.profile.is_some() {
+            interpreter.set_profile(Some(Profile::new()));
+        }
+        if self.coverage.is_some() {
+            interpreter.set_coverage(Some(Coverage::new()));
+        }
+        let seed = self.seed.unwrap_or_else(Rng::random_seed);
+        debug!("Random seed: {}", seed);
+        interpreter.store.set_rng(Rng::new(seed));
+        let interrupt = interpreter.interrupt_flag();
+        let result = ctrlc::set_handler(move || {
+            if interrupt.swap(true, Ordering::SeqCst) {
+                exit(130);
+            }
+        });
+        if let Err(err) = result {
+            warn!("Couldn't install the Ctrl-C handler: {}", err);
+        }
+        interpreter
+    }
+
+    /// Returns the garbage collection threshold in bytes, or `None` if garbage
+    /// collection is disabled.
+    pub fn gc_threshold(&self) -> Option<usize> {
+        if self.gc_threshold == 0 {
+            None
+        } else {
+            Some(self.gc_threshold * 1024 * 1024)
+        }
+    }
+
+    /// Writes the coverage of the program recorded by the interpreter, if
+    /// coverage was enabled.
+    pub fn write_coverage(
+        &self,
+        interpreter: &mut Interpreter,
+        program: &Program,
+    ) -> Result<(), Error> {
+        let (path, coverage) = match (self.coverage.as_ref(), interpreter.take_coverage()) {
+            (Some(path), Some(coverage)) => (path, coverage),
+            _ => return Ok(()),
+        };
+        let report = coverage.report(program);
+        let mut f = File::create(path)
+            .with_context(|_| format!("Couldn't create {}", path.display()))?;
+        report.write_lcov(&mut f)?;
+        report.write_summary(&mut stderr())?;
+        Ok(())
+    }
+
+    /// Writes the profile recorded by the interpreter, if profiling was
+    /// enabled.
+    pub fn write_profile(&self, interpreter: &mut Interpreter) -> Result<(), Error> {
+        let (path, profile) = match (self.profile.as_ref(), interpreter.take_profile()) {
+            (Some(path), Some(profile)) => (path, profile),
+            _ => return Ok(()),
+        };
+        let mut f = File::create(path)
+            .with_context(|_| format!("Couldn't create {}", path.display()))?;
+        profile.write_folded(&mut f)?;
+        profile.write_summary(&mut stderr())?;
+        Ok(())
+    }
+}
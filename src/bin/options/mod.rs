@@ -1,9 +1,13 @@
 mod compile;
+mod debug;
 mod interpret;
+mod interpreter;
 mod run;
 
 pub use options::compile::CompileOptions;
+pub use options::debug::DebugOptions;
 pub use options::interpret::InterpretOptions;
+pub use options::interpreter::InterpreterOptions;
 pub use options::run::RunOptions;
 
 #[derive(Debug, StructOpt)]
@@ -40,6 +44,10 @@ pub enum Subcommand {
     #[structopt(name = "compile")]
     Compile(CompileOptions),
 
+    /// Interactively debugs a precompiled program.
+    #[structopt(name = "debug")]
+    Debug(DebugOptions),
+
     /// Interprets a precompiled program.
     #[structopt(name = "interpret")]
     Interpret(InterpretOptions),
@@ -2,6 +2,10 @@ use std::env::var_os;
 use std::path::PathBuf;
 use std::process::exit;
 
+use structopt::StructOpt;
+
+use options::InterpreterOptions;
+
 /// The `run` subcommand.
 #[derive(Debug, StructOpt)]
 pub struct RunOptions {
@@ -20,6 +24,9 @@ pub struct RunOptions {
     /// `$OFTLISP_ROOT/std`.
     #[structopt(long = "std", name = "PATH", parse(from_os_str))]
     pub std_path: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    pub interpreter: InterpreterOptions,
 }
 
 impl RunOptions {
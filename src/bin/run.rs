@@ -1,10 +1,10 @@
-use failure::Error;
-use oftb::flatanf::{AExpr, CExpr, Expr};
-use oftb::interpreter::{Interpreter, Value};
+use failure::{Error, ResultExt};
+use oftb::interpreter::Value;
 use oftb::intrinsics::Intrinsics;
 use oftb::modules::Packages;
-use oftb::Literal;
 
+use eval;
+use interpret::{init_globals, main_call};
 use options::RunOptions;
 
 pub fn run(options: RunOptions) -> Result<(), Error> {
@@ -24,27 +24,29 @@ pub fn run(options: RunOptions) -> Result<(), Error> {
     let program = pkgs.compile(name, &options.binary_name)?;
 
     // Create the expression for the call to main.
-    let args = options.args.into_iter().map(Literal::String).collect();
-    let args = AExpr::Literal(Literal::list(args));
-    let main = Expr::CExpr(CExpr::Call(AExpr::Global("main:main".into()), vec![args]));
+    let main = main_call(&options.args);
 
     // Create the interpreter.
-    let mut interpreter = Interpreter::new();
-    interpreter.add_builtins::<Intrinsics>();
+    let mut interpreter = options.interpreter.build();
+    let max_steps = options.interpreter.max_steps;
+    interpreter
+        .store
+        .set_program_name(Some(options.binary_name.clone()));
 
     // Start interpreting global decls.
-    debug!("Initializing program...");
-    for &(name, ref expr) in &program.decls {
-        let val = interpreter.eval(expr);
-        interpreter.globals.insert(name, val);
-    }
+    init_globals(&mut interpreter, &program, max_steps)?;
 
     // Call main.
     debug!("Running program...");
-    let retval = interpreter.eval(&main);
+    let retval = eval(&mut interpreter, &main, "main:main".into(), max_steps)
+        .context("While running `main:main'")?;
     if retval != Value::Nil {
         println!("{}", retval.display(&interpreter.store, false));
     }
+    options.interpreter.write_profile(&mut interpreter)?;
+    options
+        .interpreter
+        .write_coverage(&mut interpreter, &program)?;
 
     // Done!
     Ok(())
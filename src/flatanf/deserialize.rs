@@ -2,6 +2,7 @@ use std::collections::HashSet;
 use std::io::Read;
 
 use failure::Error;
+use num::BigInt;
 use podio::{LittleEndian, ReadPodExt};
 
 use flatanf::{AExpr, CExpr, Expr, Literal, Program};
@@ -195,6 +196,16 @@ impl Literal {
                 }
                 Ok(Literal::Vector(vals))
             }
+            0x08 => {
+                let len = deserialize_u64_as_usize(r)?;
+                let mut bs = vec![0; len];
+                r.read_exact(&mut bs)?;
+                Ok(Literal::Bignum(BigInt::from_signed_bytes_le(&bs)))
+            }
+            0x09 => {
+                let n = r.read_f64::<LittleEndian>()?;
+                Ok(Literal::Float(n))
+            }
             _ => bail!("Unknown discriminant for Literal: {}", discrim),
         }
     }
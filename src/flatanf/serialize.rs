@@ -167,6 +167,16 @@ impl Literal {
                 }
                 Ok(())
             }
+            Literal::Bignum(ref n) => {
+                let bs = n.to_signed_bytes_le();
+                w.write_u8(0x08)?;
+                serialize_usize_as_u64(bs.len(), w)?;
+                w.write_all(&bs)
+            }
+            Literal::Float(n) => {
+                w.write_u8(0x09)?;
+                w.write_f64::<LittleEndian>(n)
+            }
         }
     }
 }
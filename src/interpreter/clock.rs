@@ -0,0 +1,78 @@
+//! The source of time for the time intrinsics, which can be replaced by a fake
+//! clock to make a program's behavior deterministic.
+
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A clock, which gives monotonic and wall-clock time.
+#[derive(Clone, Copy, Debug)]
+pub enum Clock {
+    /// A fake clock, which only advances when the program sleeps. Sleeping
+    /// returns immediately.
+    Fake {
+        /// The time since the clock was created.
+        monotonic: Duration,
+
+        /// The time since the Unix epoch.
+        unix: Duration,
+    },
+
+    /// The system's clocks. Monotonic time is measured from the given instant.
+    System(Instant),
+}
+
+impl Clock {
+    /// Creates a fake clock, starting at the given time since the Unix epoch.
+    pub fn fake(unix: Duration) -> Clock {
+        Clock::Fake {
+            monotonic: Duration::from_secs(0),
+            unix,
+        }
+    }
+
+    /// Creates a clock that uses the system's clocks, with monotonic time
+    /// starting from now.
+    pub fn system() -> Clock {
+        Clock::System(Instant::now())
+    }
+
+    /// Returns the time since the clock was created. This never decreases.
+    pub fn monotonic(&self) -> Duration {
+        match *self {
+            Clock::Fake { monotonic, .. } => monotonic,
+            Clock::System(start) => start.elapsed(),
+        }
+    }
+
+    /// Sleeps for the given duration, or advances a fake clock by it.
+    pub fn sleep(&mut self, duration: Duration) {
+        match *self {
+            Clock::Fake {
+                ref mut monotonic,
+                ref mut unix,
+            } => {
+                *monotonic += duration;
+                *unix += duration;
+            }
+            Clock::System(_) => sleep(duration),
+        }
+    }
+
+    /// Returns the time since the Unix epoch. For the system clock, this may
+    /// go backwards if the system's time is changed, and is zero if the
+    /// system's time is before the epoch.
+    pub fn unix(&self) -> Duration {
+        match *self {
+            Clock::Fake { unix, .. } => unix,
+            Clock::System(_) => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_else(|_| Duration::from_secs(0)),
+        }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock::system()
+    }
+}
@@ -0,0 +1,286 @@
+//! Coverage of a program, recording which decls were initialized, which
+//! functions were entered, and which branches of each conditional were taken.
+
+use std::collections::HashMap;
+use std::io::{Result as IoResult, Write};
+
+use symbol::Symbol;
+
+use flatanf::{AExpr, CExpr, Expr, Program};
+
+/// The number of times each expression of a program was evaluated.
+///
+/// Expressions are identified by their address, so a `Coverage` may only be
+/// reported against the `Program` that was being evaluated.
+#[derive(Clone, Debug, Default)]
+pub struct Coverage {
+    hits: HashMap<usize, usize>,
+}
+
+impl Coverage {
+    /// Creates a new, empty coverage record.
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    /// Returns the number of times the given expression was evaluated.
+    pub fn hits(&self, expr: &Expr) -> usize {
+        self.hits
+            .get(&(expr as *const Expr as usize))
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// Records that the given expression is being evaluated.
+    pub fn record(&mut self, expr: &Expr) {
+        *self.hits.entry(expr as *const Expr as usize).or_insert(0) += 1;
+    }
+
+    /// Computes the coverage of each decl in the program.
+    pub fn report(&self, program: &Program) -> CoverageReport {
+        let decls = program
+            .decls
+            .iter()
+            .map(|&(name, ref expr)| {
+                let mut decl = DeclCoverage {
+                    name,
+                    hits: self.hits(expr),
+                    functions: Vec::new(),
+                    branches: Vec::new(),
+                };
+                self.walk(expr, &mut decl);
+                decl
+            })
+            .collect();
+        CoverageReport { decls }
+    }
+
+    fn walk(&self, expr: &Expr, decl: &mut DeclCoverage) {
+        match *expr {
+            Expr::AExpr(ref a) => self.walk_atomic(a, decl),
+            Expr::CExpr(CExpr::Call(ref func, ref args)) => {
+                self.walk_atomic(func, decl);
+                for arg in args {
+                    self.walk_atomic(arg, decl);
+                }
+            }
+            Expr::CExpr(CExpr::If(ref c, ref t, ref e)) => {
+                self.walk_atomic(c, decl);
+                decl.branches.push(BranchCoverage {
+                    hits: self.hits(expr),
+                    then_hits: self.hits(t),
+                    else_hits: self.hits(e),
+                });
+                self.walk(t, decl);
+                self.walk(e, decl);
+            }
+            Expr::CExpr(CExpr::LetRec(ref bound, ref body)) => {
+                for &(name, _, ref body) in bound {
+                    decl.functions.push((name.to_string(), self.hits(body)));
+                    self.walk(body, decl);
+                }
+                self.walk(body, decl);
+            }
+            Expr::Let(ref a, ref b) | Expr::Seq(ref a, ref b) => {
+                self.walk(a, decl);
+                self.walk(b, decl);
+            }
+        }
+    }
+
+    fn walk_atomic(&self, expr: &AExpr, decl: &mut DeclCoverage) {
+        match *expr {
+            AExpr::GetMethod(ref type_, _) => self.walk_atomic(type_, decl),
+            AExpr::Lambda(name, _, ref body) => {
+                let name = match name {
+                    Some(name) => name.to_string(),
+                    None => format!("{}/lambda{}", decl.name, decl.functions.len()),
+                };
+                decl.functions.push((name, self.hits(body)));
+                self.walk(body, decl);
+            }
+            AExpr::Vector(ref exprs) => for expr in exprs {
+                self.walk_atomic(expr, decl);
+            },
+            AExpr::Global(_) | AExpr::Literal(_) | AExpr::Local(_) => {}
+        }
+    }
+}
+
+/// The coverage of a conditional.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BranchCoverage {
+    /// The number of times the conditional was evaluated.
+    pub hits: usize,
+
+    /// The number of times the then branch was taken.
+    pub then_hits: usize,
+
+    /// The number of times the else branch was taken.
+    pub else_hits: usize,
+}
+
+/// The coverage of a single decl.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DeclCoverage {
+    /// The name of the decl.
+    pub name: Symbol,
+
+    /// The number of times the decl was initialized.
+    pub hits: usize,
+
+    /// The name of each function defined in the decl, with the number of
+    /// times it was entered. Anonymous functions are named after the decl.
+    pub functions: Vec<(String, usize)>,
+
+    /// The conditionals in the decl.
+    pub branches: Vec<BranchCoverage>,
+}
+
+impl DeclCoverage {
+    /// Returns the name of the module the decl is in.
+    pub fn module(&self) -> &str {
+        let name = self.name.as_str();
+        match name.find(':') {
+            Some(i) => &name[..i],
+            None => name,
+        }
+    }
+}
+
+/// The coverage of each decl in a program, as computed by
+/// `Coverage::report`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CoverageReport {
+    /// The decls, in the order they appear in the program.
+    pub decls: Vec<DeclCoverage>,
+}
+
+impl CoverageReport {
+    /// Groups the decls by module, in the order modules first appear in the
+    /// program.
+    fn modules(&self) -> Vec<(&str, Vec<&DeclCoverage>)> {
+        let mut modules: Vec<(&str, Vec<&DeclCoverage>)> = Vec::new();
+        for decl in &self.decls {
+            let module = decl.module();
+            match modules.iter().position(|&(name, _)| name == module) {
+                Some(i) => modules[i].1.push(decl),
+                None => modules.push((module, vec![decl])),
+            }
+        }
+        modules
+    }
+
+    /// Writes the report as an lcov tracefile, with one record per module.
+    /// Since the flat-ANF IR doesn't carry source spans, the "line" of each
+    /// decl is its position in its module, starting at 1.
+    pub fn write_lcov<W: Write>(&self, w: &mut W) -> IoResult<()> {
+        for (module, decls) in self.modules() {
+            writeln!(w, "TN:")?;
+            writeln!(w, "SF:{}", module)?;
+            for (i, decl) in decls.iter().enumerate() {
+                for function in &decl.functions {
+                    writeln!(w, "FN:{},{}", i + 1, function.0)?;
+                }
+            }
+            for decl in &decls {
+                for &(ref name, hits) in &decl.functions {
+                    writeln!(w, "FNDA:{},{}", hits, name)?;
+                }
+            }
+            let (fnf, fnh) = count(decls.iter().flat_map(|decl| &decl.functions), |f| f.1);
+            writeln!(w, "FNF:{}", fnf)?;
+            writeln!(w, "FNH:{}", fnh)?;
+
+            for (i, decl) in decls.iter().enumerate() {
+                for (j, branch) in decl.branches.iter().enumerate() {
+                    for &(k, hits) in &[(0, branch.then_hits), (1, branch.else_hits)] {
+                        if branch.hits == 0 {
+                            writeln!(w, "BRDA:{},{},{},-", i + 1, j, k)?;
+                        } else {
+                            writeln!(w, "BRDA:{},{},{},{}", i + 1, j, k, hits)?;
+                        }
+                    }
+                }
+            }
+            let branches = decls.iter().flat_map(|decl| &decl.branches);
+            let (brf, brh) = count(branches.clone(), |b| b.then_hits);
+            let (_, brh_else) = count(branches, |b| b.else_hits);
+            writeln!(w, "BRF:{}", brf * 2)?;
+            writeln!(w, "BRH:{}", brh + brh_else)?;
+
+            for (i, decl) in decls.iter().enumerate() {
+                writeln!(w, "DA:{},{}", i + 1, decl.hits)?;
+            }
+            let (lf, lh) = count(decls.iter(), |decl| decl.hits);
+            writeln!(w, "LF:{}", lf)?;
+            writeln!(w, "LH:{}", lh)?;
+            writeln!(w, "end_of_record")?;
+        }
+        Ok(())
+    }
+
+    /// Writes a table of the coverage of each module.
+    pub fn write_summary<W: Write>(&self, w: &mut W) -> IoResult<()> {
+        fn percent(hit: usize, total: usize) -> String {
+            if total == 0 {
+                "-".to_string()
+            } else {
+                format!("{:.1}%", 100.0 * hit as f64 / total as f64)
+            }
+        }
+
+        writeln!(
+            w,
+            "{:>9} {:>9} {:>9}  Module",
+            "Decls", "Functions", "Branches"
+        )?;
+        let mut totals = [(0, 0); 3];
+        for (module, decls) in self.modules() {
+            let branches = decls.iter().flat_map(|decl| &decl.branches);
+            let (brf, then_hit) = count(branches.clone(), |b| b.then_hits);
+            let (_, else_hit) = count(branches, |b| b.else_hits);
+            let counts = [
+                count(decls.iter(), |decl| decl.hits),
+                count(decls.iter().flat_map(|decl| &decl.functions), |f| f.1),
+                (brf * 2, then_hit + else_hit),
+            ];
+            writeln!(
+                w,
+                "{:>9} {:>9} {:>9}  {}",
+                percent(counts[0].1, counts[0].0),
+                percent(counts[1].1, counts[1].0),
+                percent(counts[2].1, counts[2].0),
+                module
+            )?;
+            for (total, &(found, hit)) in totals.iter_mut().zip(counts.iter()) {
+                total.0 += found;
+                total.1 += hit;
+            }
+        }
+        writeln!(
+            w,
+            "{:>9} {:>9} {:>9}  Total",
+            percent(totals[0].1, totals[0].0),
+            percent(totals[1].1, totals[1].0),
+            percent(totals[2].1, totals[2].0)
+        )
+    }
+}
+
+/// Returns the number of items, and the number of them with nonzero hits.
+fn count<I, F>(iter: I, mut hits: F) -> (usize, usize)
+where
+    I: Iterator,
+    F: FnMut(I::Item) -> usize,
+{
+    let mut found = 0;
+    let mut hit = 0;
+    for item in iter {
+        found += 1;
+        if hits(item) != 0 {
+            hit += 1;
+        }
+    }
+    (found, hit)
+}
@@ -1,3 +1,4 @@
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
 use interpreter::Value;
@@ -14,6 +15,18 @@ enum EnvInner {
     Nil,
 }
 
+/// A table of environment cells, used to serialize environments while
+/// preserving the sharing between them.
+#[derive(Debug, Default)]
+pub struct EnvTable {
+    /// The cells, each with its value and the ID of the rest of the
+    /// environment. The empty environment has the ID 0, and every other
+    /// environment has the ID one more than the index of its first cell.
+    pub cells: Vec<(Value, usize)>,
+
+    ids: HashMap<usize, usize>,
+}
+
 impl Env {
     /// Creates a new, empty environment.
     pub fn new() -> Env {
@@ -22,6 +35,32 @@ impl Env {
         }
     }
 
+    /// Adds the cells of the environment to the table, returning its ID.
+    /// Cells already in the table are reused.
+    pub fn add_to_table(&self, table: &mut EnvTable) -> usize {
+        let mut pending = Vec::new();
+        let mut e = &self.inner;
+        let mut id = loop {
+            let key = &**e as *const EnvInner as usize;
+            if let Some(&id) = table.ids.get(&key) {
+                break id;
+            }
+            match **e {
+                EnvInner::Cons(h, ref t) => {
+                    pending.push((key, h));
+                    e = t;
+                }
+                EnvInner::Nil => break 0,
+            }
+        };
+        while let Some((key, h)) = pending.pop() {
+            table.cells.push((h, id));
+            id = table.cells.len();
+            table.ids.insert(key, id);
+        }
+        id
+    }
+
     /// Gets a local variable.
     pub fn local(&self, mut depth: usize) -> Value {
         let mut e = &*self.inner;
@@ -41,4 +80,66 @@ impl Env {
             inner: Rc::new(EnvInner::Cons(val, self.inner)),
         }
     }
+
+    /// Returns the values in the environment, ordered by their De Bruijn
+    /// index.
+    pub fn locals(&self) -> Vec<Value> {
+        let mut locals = Vec::new();
+        let mut e = &*self.inner;
+        while let EnvInner::Cons(h, ref t) = *e {
+            locals.push(h);
+            e = t;
+        }
+        locals
+    }
+
+    /// Rebuilds the environment, passing each value through the given
+    /// function. Cells that have already been rebuilt (as recorded in `memo`)
+    /// are reused, so environments that shared a tail before still share it
+    /// afterwards.
+    pub fn relocate<F: FnMut(Value) -> Value>(
+        &self,
+        memo: &mut HashMap<usize, Env>,
+        mut f: F,
+    ) -> Env {
+        let mut pending = Vec::new();
+        let mut e = &self.inner;
+        let mut env = loop {
+            let key = &**e as *const EnvInner as usize;
+            if let Some(env) = memo.get(&key) {
+                break env.clone();
+            }
+            match **e {
+                EnvInner::Cons(h, ref t) => {
+                    pending.push((key, h));
+                    e = t;
+                }
+                EnvInner::Nil => break Env::new(),
+            }
+        };
+        while let Some((key, h)) = pending.pop() {
+            env = env.push(f(h));
+            memo.insert(key, env.clone());
+        }
+        env
+    }
+
+    /// Calls the given function with each value in the environment, stopping
+    /// at the first cell that has already been seen.
+    pub fn trace<F: FnMut(Value)>(&self, seen: &mut HashSet<usize>, mut f: F) {
+        let mut e = &self.inner;
+        while let EnvInner::Cons(h, ref t) = **e {
+            if !seen.insert(&**e as *const EnvInner as usize) {
+                break;
+            }
+            f(h);
+            e = t;
+        }
+    }
+}
+
+impl Default for Env {
+    fn default() -> Env {
+        Env::new()
+    }
 }
@@ -0,0 +1,113 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use symbol::Symbol;
+
+/// An error encountered while evaluating a program.
+#[derive(Clone, Debug, Fail)]
+pub enum RuntimeError {
+    /// A function was called with the wrong number of arguments.
+    #[fail(display = "`{}' expects {} arguments, but was called with {}", _0, _1, _2)]
+    ArgnMismatch(String, usize, usize),
+
+    /// An integer was divided by zero.
+    #[fail(display = "Division by zero in `{}'", _0)]
+    DivideByZero(&'static str),
+
+    /// An escape continuation was called after the call to `call/ec` that
+    /// created it returned, or after it was already called.
+    #[fail(display = "An escape continuation was called outside of its extent")]
+    EscapeOutOfExtent,
+
+    /// The program exited. Unlike other errors, this can't be caught by a
+    /// handler.
+    #[fail(display = "The program exited with code {}", _0)]
+    Exit(i32),
+
+    /// An intrinsic failed to perform an operation on the outside world.
+    #[fail(display = "Error in `{}': {}", _0, _1)]
+    IntrinsicFailed(&'static str, String),
+
+    /// A method was looked up on a value that isn't a type name.
+    #[fail(display = "Can't get a method from {}, which isn't a type", _0)]
+    InvalidMethodType(String),
+
+    /// A value that isn't a function was called.
+    #[fail(display = "Not callable: {}", _0)]
+    NotCallable(String),
+
+    /// A method was looked up that doesn't exist for the given type.
+    #[fail(display = "No such method `{}' for type `{}'", _1, _0)]
+    NoSuchMethod(Symbol, Symbol),
+
+    /// An index or range was out of bounds.
+    #[fail(display = "Out of bounds in `{}': {}", _0, _1)]
+    OutOfBounds(&'static str, String),
+
+    /// The program panicked.
+    #[fail(display = "Panic: {}", _0)]
+    Panic(String),
+
+    /// A variadic function was called with too few arguments.
+    #[fail(display = "`{}' expects at least {} arguments, but was called with {}", _0, _1, _2)]
+    TooFewArgs(String, usize, usize),
+
+    /// An intrinsic was called with a value of the wrong type.
+    #[fail(display = "Type error in `{}': expected {}, found {}", _0, _1, _2)]
+    TypeError(&'static str, &'static str, String),
+
+    /// A value was raised, but there was no handler to catch it.
+    #[fail(display = "Uncaught error: {}", _0)]
+    Uncaught(String),
+
+    /// A global was referenced that doesn't exist.
+    #[fail(display = "Unknown global: `{}'", _0)]
+    UnknownGlobal(Symbol),
+}
+
+impl RuntimeError {
+    /// Returns the name of the kind of the error, as passed to handlers.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            RuntimeError::ArgnMismatch(..) | RuntimeError::TooFewArgs(..) => "argn-mismatch",
+            RuntimeError::DivideByZero(..) => "divide-by-zero",
+            RuntimeError::EscapeOutOfExtent => "escape-out-of-extent",
+            RuntimeError::Exit(..) => "exit",
+            RuntimeError::IntrinsicFailed(..) => "intrinsic-failed",
+            RuntimeError::InvalidMethodType(..) => "invalid-method-type",
+            RuntimeError::NotCallable(..) => "not-callable",
+            RuntimeError::NoSuchMethod(..) => "no-such-method",
+            RuntimeError::OutOfBounds(..) => "out-of-bounds",
+            RuntimeError::Panic(..) => "panic",
+            RuntimeError::TypeError(..) => "type-error",
+            RuntimeError::Uncaught(..) => "uncaught",
+            RuntimeError::UnknownGlobal(..) => "unknown-global",
+        }
+    }
+}
+
+/// The functions that were being evaluated when a runtime error occurred,
+/// innermost first. Anonymous functions have no name. Compiled programs don't
+/// keep source locations, so functions are only identified by name.
+///
+/// Since tail calls replace the frame of their caller, functions that made a
+/// tail call will not appear.
+#[derive(Clone, Debug, Default)]
+pub struct StackTrace(pub Vec<Option<Symbol>>);
+
+impl Display for StackTrace {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        let mut first = true;
+        for name in &self.0 {
+            if first {
+                first = false;
+            } else {
+                writeln!(fmt)?;
+            }
+            match *name {
+                Some(name) => write!(fmt, "  at {}", name)?,
+                None => write!(fmt, "  at <lambda>")?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -5,7 +5,20 @@ use std::collections::HashMap;
 use symbol::Symbol;
 
 use flatanf::{AExpr, CExpr, Expr};
-use interpreter::{Control, Env, Intrinsic, Kont, State, Store, Value};
+use Literal;
+use interpreter::{
+    Captured, Control, Env, Intrinsic, Kont, RuntimeError, StackTrace, State, Store, Value,
+};
+
+/// Unwraps a `Result`, returning an errored state if it is an error.
+macro_rules! try_state {
+    ($e:expr, $store:expr, $konts:expr) => {
+        match $e {
+            Ok(val) => val,
+            Err(err) => return error(err, $store, $konts),
+        }
+    };
+}
 
 /// Evaluates by a single step.
 pub fn step<'program>(
@@ -19,19 +32,23 @@ pub fn step<'program>(
     match control {
         Control::Normal(expr) => match *expr {
             Expr::AExpr(ref expr) => {
-                let val = atomic(expr, &env, globals, store);
+                let val = try_state!(atomic(expr, &env, globals, store), store, konts);
                 kontinue(val, store, konts)
             }
             Expr::CExpr(ref expr) => match *expr {
                 CExpr::Call(ref func, ref args) => {
-                    let func = atomic(func, &env, globals, store);
-                    let args = args.iter()
-                        .map(|arg| atomic(arg, &env, globals, store))
-                        .collect();
+                    let func = try_state!(atomic(func, &env, globals, store), store, konts);
+                    let args = try_state!(
+                        args.iter()
+                            .map(|arg| atomic(arg, &env, globals, store))
+                            .collect(),
+                        store,
+                        konts
+                    );
                     apply(func, args, store, konts)
                 }
                 CExpr::If(ref c, ref t, ref e) => {
-                    let c = atomic(c, &env, globals, store);
+                    let c = try_state!(atomic(c, &env, globals, store), store, konts);
                     let expr = if let Value::Nil = c { e } else { t };
                     State::Running(Control::Normal(expr), env, konts)
                 }
@@ -65,25 +82,57 @@ pub fn apply<'program>(
     func: Value,
     args: Vec<Value>,
     store: &mut Store<'program>,
-    konts: Vec<Kont<'program>>,
+    mut konts: Vec<Kont<'program>>,
 ) -> State<'program> {
     match func {
         Value::Closure(clo_addr) => {
             let (argn, body, name, mut env) = store.get_closure(clo_addr);
             if argn != args.len() {
-                if let Some(name) = name {
-                    unimplemented!("Bad argn in call to `{}', {} vs {}", name, argn, args.len());
-                } else {
-                    unimplemented!("Bad argn in call to a closure, {} vs {}", argn, args.len());
-                }
+                let name = name.map(|name| name.to_string())
+                    .unwrap_or_else(|| "<lambda>".to_string());
+                let err = RuntimeError::ArgnMismatch(name, argn, args.len());
+                return error(err, store, konts);
             }
             for arg in args {
                 env = env.push(arg);
             }
+            if let Some(&Kont::Frame(_)) = konts.last() {
+                konts.pop();
+            }
+            konts.push(Kont::Frame(clo_addr));
             State::Running(Control::Normal(body), env, konts)
         }
+        Value::Continuation(cont_addr) => {
+            if args.len() != 1 {
+                let err = RuntimeError::ArgnMismatch("<continuation>".to_string(), 1, args.len());
+                return error(err, store, konts);
+            }
+            let captured = match *store.get_continuation(cont_addr) {
+                Captured::Full(ref captured) => Some(captured.clone()),
+                Captured::Escape => None,
+            };
+            let konts = if let Some(captured) = captured {
+                captured
+            } else {
+                let marker = konts.iter().rposition(|kont| match *kont {
+                    Kont::Escape(addr) => addr == cont_addr,
+                    _ => false,
+                });
+                match marker {
+                    Some(i) => {
+                        konts.truncate(i);
+                        konts
+                    }
+                    None => return error(RuntimeError::EscapeOutOfExtent, store, konts),
+                }
+            };
+            kontinue(args[0], store, konts)
+        }
         Value::Intrinsic(Intrinsic(f)) => f(args, store, konts),
-        _ => unimplemented!("Not callable: {}", func.display(store, false)),
+        _ => {
+            let err = RuntimeError::NotCallable(func.display(store, false).to_string());
+            error(err, store, konts)
+        }
     }
 }
 
@@ -93,38 +142,94 @@ pub fn atomic<'program>(
     env: &Env,
     globals: &HashMap<Symbol, Value>,
     store: &mut Store<'program>,
-) -> Value {
+) -> Result<Value, RuntimeError> {
     match *expr {
-        AExpr::GetMethod(ref type_, name) => match atomic(type_, env, globals, store) {
+        AExpr::GetMethod(ref type_, name) => match atomic(type_, env, globals, store)? {
             Value::Symbol(type_) => globals
                 .get(&format!("{}#{}", type_, name).into())
                 .cloned()
-                .unwrap_or_else(|| panic!("No such method {} for type {}", name, type_)),
-            value => panic!("Invalid type to get-method {}", value.display(store, false)),
+                .ok_or(RuntimeError::NoSuchMethod(type_, name)),
+            value => Err(RuntimeError::InvalidMethodType(
+                value.display(store, false).to_string(),
+            )),
         },
         AExpr::Global(name) => globals
             .get(&name)
             .cloned()
-            .unwrap_or_else(|| panic!("Unknown global: {}", name)),
-        AExpr::Lambda(name, argn, ref body) => {
-            Value::Closure(store.store_closure(argn, body, name, env.clone()))
-        }
-        AExpr::Literal(ref lit) => store.store_literal(lit),
-        AExpr::Local(n) => env.local(n),
+            .ok_or(RuntimeError::UnknownGlobal(name)),
+        AExpr::Lambda(name, argn, ref body) => Ok(Value::Closure(store.store_closure(
+            argn,
+            body,
+            name,
+            env.clone(),
+        ))),
+        AExpr::Literal(ref lit) => Ok(store.store_literal(lit)),
+        AExpr::Local(n) => Ok(env.local(n)),
         AExpr::Vector(ref exprs) => {
-            let vals = exprs
-                .iter()
-                .map(|expr| {
-                    let val = atomic(expr, env, globals, store);
-                    store.store(val)
-                })
-                .collect::<Vec<_>>();
+            let mut vals = Vec::with_capacity(exprs.len());
+            for expr in exprs {
+                let val = atomic(expr, env, globals, store)?;
+                vals.push(store.store(val));
+            }
             let (a, l) = store.store_vec(&vals);
-            Value::Vector(a, l)
+            Ok(Value::Vector(a, l))
         }
     }
 }
 
+/// Signals a runtime error. If there is a handler on the continuation stack,
+/// it is called with a list of a symbol naming the kind of error and the
+/// error message. Otherwise, evaluation stops. `RuntimeError::Exit` always
+/// stops evaluation, without a stack trace.
+pub fn error<'program>(
+    err: RuntimeError,
+    store: &mut Store<'program>,
+    konts: Vec<Kont<'program>>,
+) -> State<'program> {
+    if let RuntimeError::Exit(_) = err {
+        return State::Errored(err, StackTrace::default());
+    }
+    let val = store.store_literal(&Literal::list(vec![
+        Literal::Symbol(err.kind().into()),
+        Literal::String(err.to_string()),
+    ]));
+    raise(val, err, store, konts)
+}
+
+/// Raises a value, unwinding the continuation stack to the nearest handler
+/// and calling the handler with the value. If there is no handler, evaluation
+/// stops with the given error, and a stack trace is recorded.
+pub fn raise<'program>(
+    val: Value,
+    uncaught: RuntimeError,
+    store: &mut Store<'program>,
+    mut konts: Vec<Kont<'program>>,
+) -> State<'program> {
+    let handler = konts
+        .iter()
+        .enumerate()
+        .rev()
+        .filter_map(|(i, kont)| match *kont {
+            Kont::Handler(handler) => Some((i, handler)),
+            _ => None,
+        })
+        .next();
+    if let Some((i, handler)) = handler {
+        konts.truncate(i);
+        apply(handler, vec![val], store, konts)
+    } else {
+        let trace = konts
+            .iter()
+            .rev()
+            .filter_map(|kont| match *kont {
+                Kont::Frame(addr) => Some(store.get_closure(addr).2),
+                _ => None,
+            })
+            .collect();
+        State::Errored(uncaught, StackTrace(trace))
+    }
+}
+
 /// Applies a value onto the top continuation of the continuation stack,
 /// returning the new state.
 pub fn kontinue<'program>(
@@ -133,6 +238,9 @@ pub fn kontinue<'program>(
     mut konts: Vec<Kont<'program>>,
 ) -> State<'program> {
     match konts.pop() {
+        Some(Kont::Escape(_)) | Some(Kont::Frame(_)) | Some(Kont::Handler(_)) => {
+            kontinue(val, store, konts)
+        }
         Some(Kont::Let(expr, env)) => {
             let env = env.push(val.clone());
             State::Running(Control::Normal(expr), env, konts)
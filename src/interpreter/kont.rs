@@ -1,11 +1,24 @@
 use flatanf::Expr;
 use interpreter::env::Env;
-use interpreter::store::Addr;
+use interpreter::store::{Addr, Closure, Continuation};
 use interpreter::value::Value;
 
 /// A continuation on the continuation stack.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Kont<'program> {
+    /// A marker for an escape continuation, which unwinds the stack to this
+    /// point when called. This does nothing when returned to.
+    Escape(Addr<Continuation>),
+
+    /// A call frame, recording the closure being evaluated. This does nothing
+    /// when returned to, and exists only for stack traces. A tail call
+    /// replaces the frame on top of the stack instead of pushing a new one.
+    Frame(Addr<Closure>),
+
+    /// A handler installed by `with-handler`, which is called with raised
+    /// values and runtime errors. This does nothing when returned to.
+    Handler(Value),
+
     /// A continuation for let evaluation.
     Let(&'program Expr, Env),
 
@@ -15,3 +28,17 @@ pub enum Kont<'program> {
     /// A continuation for seq evaluation.
     Seq(&'program Expr, Env),
 }
+
+/// A continuation captured by `call/cc` or `call/ec`, as stored in the
+/// continuation heap.
+#[derive(Clone, Debug)]
+pub enum Captured<'program> {
+    /// A full continuation. When called, the continuation stack is replaced
+    /// with a copy of this one.
+    Full(Vec<Kont<'program>>),
+
+    /// A one-shot escape continuation. When called, the stack is unwound to
+    /// its `Kont::Escape` marker, which is only possible once, and only while
+    /// the call to `call/ec` that created it hasn't returned.
+    Escape,
+}
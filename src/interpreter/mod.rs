@@ -1,26 +1,80 @@
 //! Interpretation for the `flatanf` AST.
 
+mod clock;
 mod control;
+mod coverage;
 mod env;
+mod error;
 pub mod eval;
 mod kont;
+mod profile;
+mod rng;
 mod state;
+mod stdin;
 mod store;
+#[cfg(test)]
+mod tests;
 mod value;
 
+use std::cmp::max;
 use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::mem::take;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 
+use failure::Error;
 use symbol::Symbol;
 
-use flatanf::Expr;
+use flatanf::{Expr, Program};
+pub use interpreter::clock::Clock;
 pub use interpreter::control::Control;
-use interpreter::env::Env;
-pub use interpreter::kont::Kont;
+pub use interpreter::coverage::{BranchCoverage, Coverage, CoverageReport, DeclCoverage};
+pub use interpreter::env::Env;
+pub use interpreter::error::{RuntimeError, StackTrace};
+pub use interpreter::kont::{Captured, Kont};
+pub use interpreter::profile::{Cost, FunctionCost, Profile};
+pub use interpreter::rng::Rng;
 pub use interpreter::state::State;
-pub use interpreter::store::{Addr, Bytes, Closure, Store, Vector};
+pub use interpreter::stdin::Stdin;
+pub use interpreter::store::{
+    Addr, Bignum, Bytes, Closure, Continuation, Handle, HandleState, Map, Store, Vector,
+};
 pub use interpreter::value::{Intrinsic, Value};
 use BuiltinPackage;
 
+/// The default heap size at which the garbage collector first runs, in bytes.
+pub const DEFAULT_GC_THRESHOLD: usize = 64 * 1024 * 1024;
+
+/// The number of steps between checks of the deadline and interrupt flag.
+const PAUSE_CHECK_INTERVAL: usize = 1024;
+
+/// The result of an evaluation that may be paused before it finishes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Evaluation {
+    /// Evaluation halted with a value.
+    Done(Value),
+
+    /// Evaluation was paused for the given reason. It can be continued with
+    /// `Interpreter::resume`.
+    Paused(Pause),
+}
+
+/// The reason evaluation was paused.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Pause {
+    /// The interrupt flag was set.
+    Interrupted,
+
+    /// The step budget ran out.
+    OutOfFuel,
+
+    /// The deadline passed.
+    TimedOut,
+}
+
 /// The interpreter.
 #[derive(Debug)]
 pub struct Interpreter<'program> {
@@ -32,6 +86,30 @@ pub struct Interpreter<'program> {
 
     /// The state of the interpreter. Uses an Option to allow moving out.
     state: Option<State<'program>>,
+
+    /// The minimum heap size at which to collect garbage, or `None` if garbage
+    /// collection is disabled.
+    gc_threshold: Option<usize>,
+
+    /// The heap size at which to next collect garbage.
+    next_gc: usize,
+
+    /// The number of steps evaluated so far.
+    steps: usize,
+
+    /// The time at which evaluation started with `eval_with_fuel` or `resume`
+    /// will be paused.
+    deadline: Option<Instant>,
+
+    /// A flag that pauses evaluation started with `eval_with_fuel` or
+    /// `resume` when set.
+    interrupt: Arc<AtomicBool>,
+
+    /// The profile being recorded, if profiling is enabled.
+    profile: Option<Profile>,
+
+    /// The coverage being recorded, if coverage is enabled.
+    coverage: Option<Coverage>,
 }
 
 impl<'program> Interpreter<'program> {
@@ -52,6 +130,13 @@ impl<'program> Interpreter<'program> {
             store,
             globals,
             state,
+            gc_threshold: Some(DEFAULT_GC_THRESHOLD),
+            next_gc: DEFAULT_GC_THRESHOLD,
+            steps: 0,
+            deadline: None,
+            interrupt: Arc::new(AtomicBool::new(false)),
+            profile: None,
+            coverage: None,
         }
     }
 
@@ -62,32 +147,137 @@ impl<'program> Interpreter<'program> {
         }
     }
 
-    /// Evaluates an expression to a value.
-    pub fn eval(&mut self, expr: &'program Expr) -> Value {
+    /// Collects garbage, treating the globals and the current state as roots.
+    pub fn collect_garbage(&mut self) {
+        let before = self.store.size();
+        self.store
+            .collect(&mut self.globals, self.state.as_mut().unwrap());
+        let after = self.store.size();
+        debug!("Collected garbage: {} bytes -> {} bytes", before, after);
+
+        if let Some(threshold) = self.gc_threshold {
+            self.next_gc = max(threshold, after * 2);
+        }
+    }
+
+    /// Returns the depth of the continuation stack, or 0 if evaluation is not
+    /// running.
+    pub fn depth(&self) -> usize {
+        match *self.state() {
+            State::Running(_, _, ref konts) => konts.len(),
+            _ => 0,
+        }
+    }
+
+    /// Returns the name of the function whose body is about to be evaluated,
+    /// if the last step was a call to a named function.
+    pub fn entered_function(&self) -> Option<Symbol> {
+        if let State::Running(Control::Normal(expr), _, ref konts) = *self.state() {
+            if let Some(&Kont::Frame(addr)) = konts.last() {
+                let (_, body, name, _) = self.store.get_closure(addr);
+                if ptr::eq(expr, body) {
+                    return name;
+                }
+            }
+        }
+        None
+    }
+
+    /// Evaluates an expression to a value. This runs until evaluation halts,
+    /// regardless of the deadline and interrupt flag; use `eval_with_fuel` to
+    /// limit evaluation.
+    pub fn eval(&mut self, expr: &'program Expr) -> Result<Value, RuntimeError> {
         self.load_expr(expr);
         loop {
-            if let Some(value) = self.eval_step() {
-                return value;
+            if let Some(result) = self.eval_step() {
+                return result;
             }
         }
     }
 
-    /// Makes an evaluation step, returning a value if evaluation halted.
-    pub fn eval_step(&mut self) -> Option<Value> {
+    /// Makes an evaluation step, returning a value if evaluation halted, or
+    /// an error if evaluation was stopped by a runtime error.
+    pub fn eval_step(&mut self) -> Option<Result<Value, RuntimeError>> {
         let state = self.state.take().unwrap();
         let next = match state {
-            State::Running(c, e, k) => eval::step(c, e, &self.globals, &mut self.store, k),
-            State::Halted(val) => State::Halted(val),
+            State::Running(c, e, k) => {
+                self.steps += 1;
+                if let Some(ref mut coverage) = self.coverage {
+                    match c {
+                        Control::Normal(expr) => coverage.record(expr),
+                    }
+                }
+                if let Some(ref mut profile) = self.profile {
+                    let stack = profile.stack(&k, &self.store);
+                    let before = self.store.size();
+                    let next = eval::step(c, e, &self.globals, &mut self.store, k);
+                    let cost = Cost {
+                        steps: 1,
+                        bytes: self.store.size().saturating_sub(before),
+                    };
+                    profile.record(stack, cost);
+                    next
+                } else {
+                    eval::step(c, e, &self.globals, &mut self.store, k)
+                }
+            }
+            state => state,
         };
         self.state = Some(next);
 
-        if let Some(State::Halted(val)) = self.state {
-            Some(val)
-        } else {
-            None
+        if self.gc_threshold.is_some() && self.store.size() >= self.next_gc {
+            self.collect_garbage();
+        }
+
+        match self.state {
+            Some(State::Halted(val)) => Some(Ok(val)),
+            Some(State::Errored(ref err, _)) => Some(Err(err.clone())),
+            _ => None,
         }
     }
 
+    /// Evaluates an expression for at most the given number of steps. If
+    /// evaluation doesn't halt in that many steps, it is paused, and can be
+    /// continued with `resume`. Evaluation is also paused if the deadline
+    /// passes or the interrupt flag is set.
+    pub fn eval_with_fuel(
+        &mut self,
+        expr: &'program Expr,
+        fuel: usize,
+    ) -> Result<Evaluation, RuntimeError> {
+        self.load_expr(expr);
+        self.resume(fuel)
+    }
+
+    /// Returns the interrupt flag. Setting it from any thread pauses
+    /// evaluation started with `eval_with_fuel` or `resume`; the flag is
+    /// cleared when this happens.
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        self.interrupt.clone()
+    }
+
+    /// Replaces the globals and store with ones loaded from an image saved by
+    /// `save_image`, erasing any previous evaluation state. Intrinsics are
+    /// looked up in the builtin package `P`. The clock, program name, random
+    /// number generator, and stdin of the old store are kept.
+    pub fn load_image<P: BuiltinPackage, R: Read>(
+        &mut self,
+        program: &'program Program,
+        r: &mut R,
+    ) -> Result<(), Error> {
+        let (mut store, globals) = Store::load_image::<P, R>(program, r)?;
+        store.set_clock(*self.store.clock());
+        store.set_program_name(self.store.program_name().map(|name| name.to_string()));
+        store.set_rng(self.store.rng().clone());
+        store.set_stdin(take(self.store.stdin()));
+        self.globals = globals;
+        self.store = store;
+        self.state = Some(State::Halted(Value::Nil));
+        let threshold = self.gc_threshold;
+        self.set_gc_threshold(threshold);
+        Ok(())
+    }
+
     /// Loads an expression into the interpreter, erasing any previous
     /// evaluation state.
     pub fn load_expr(&mut self, expr: &'program Expr) {
@@ -97,4 +287,128 @@ impl<'program> Interpreter<'program> {
             Vec::new(),
         ));
     }
+
+    /// Returns the stack trace of the runtime error that stopped evaluation,
+    /// if evaluation was stopped by one.
+    pub fn stack_trace(&self) -> Option<&StackTrace> {
+        match self.state {
+            Some(State::Errored(_, ref trace)) => Some(trace),
+            _ => None,
+        }
+    }
+
+    /// Returns the current state of the interpreter.
+    pub fn state(&self) -> &State<'program> {
+        self.state.as_ref().unwrap()
+    }
+
+    /// Returns the profile being recorded, if profiling is enabled.
+    pub fn profile_mut(&mut self) -> Option<&mut Profile> {
+        self.profile.as_mut()
+    }
+
+    /// Continues evaluation for at most the given number of steps, as with
+    /// `eval_with_fuel`.
+    pub fn resume(&mut self, fuel: usize) -> Result<Evaluation, RuntimeError> {
+        for i in 0..fuel {
+            if i % PAUSE_CHECK_INTERVAL == 0 {
+                if self.interrupt.swap(false, Ordering::SeqCst) {
+                    return Ok(Evaluation::Paused(Pause::Interrupted));
+                }
+                if let Some(deadline) = self.deadline {
+                    if Instant::now() >= deadline {
+                        return Ok(Evaluation::Paused(Pause::TimedOut));
+                    }
+                }
+            }
+            if let Some(result) = self.eval_step() {
+                return result.map(Evaluation::Done);
+            }
+        }
+        Ok(Evaluation::Paused(Pause::OutOfFuel))
+    }
+
+    /// Returns the depth the continuation stack will have once the innermost
+    /// function call returns, or `None` if no function is being called.
+    pub fn return_depth(&self) -> Option<usize> {
+        match *self.state() {
+            State::Running(_, _, ref konts) => {
+                konts.iter().rposition(|kont| matches!(*kont, Kont::Frame(_)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Collects garbage, then writes the globals and store to an image, which
+    /// can be loaded with `load_image` to skip initializing the program. This
+    /// should be called between evaluations, since the current evaluation
+    /// state isn't saved. Intrinsics are saved by their names in the builtin
+    /// package `P`.
+    pub fn save_image<P: BuiltinPackage, W: Write>(
+        &mut self,
+        program: &'program Program,
+        w: &mut W,
+    ) -> Result<(), Error> {
+        self.collect_garbage();
+        self.store.save_image::<P, W>(&self.globals, program, w)
+    }
+
+    /// Sets the coverage to record evaluated expressions into. If `None`,
+    /// coverage is disabled.
+    pub fn set_coverage(&mut self, coverage: Option<Coverage>) {
+        self.coverage = coverage;
+    }
+
+    /// Sets the time at which evaluation started with `eval_with_fuel` or
+    /// `resume` will be paused. If `None`, there is no deadline.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Sets the profile to record steps into. If `None`, profiling is
+    /// disabled.
+    pub fn set_profile(&mut self, profile: Option<Profile>) {
+        self.profile = profile;
+    }
+
+    /// Sets the heap size at which garbage is collected. If `None`, garbage
+    /// will only be collected by explicit calls to `collect_garbage`.
+    pub fn set_gc_threshold(&mut self, threshold: Option<usize>) {
+        self.gc_threshold = threshold;
+        if let Some(threshold) = threshold {
+            self.next_gc = max(threshold, self.store.size());
+        }
+    }
+
+    /// Returns the number of steps evaluated so far.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Makes evaluation steps until `stop` returns true after one of them,
+    /// returning `None`, or until evaluation halts or errors, returning the
+    /// result as with `eval_step`. At least one step is always made.
+    pub fn step_until<F: FnMut(&Interpreter<'program>) -> bool>(
+        &mut self,
+        mut stop: F,
+    ) -> Option<Result<Value, RuntimeError>> {
+        loop {
+            if let Some(result) = self.eval_step() {
+                return Some(result);
+            }
+            if stop(self) {
+                return None;
+            }
+        }
+    }
+
+    /// Stops recording coverage, returning the recorded coverage.
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    /// Stops profiling, returning the recorded profile.
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
 }
@@ -0,0 +1,171 @@
+//! A profiler, which counts the steps evaluated and bytes allocated in each
+//! call stack.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Result as IoResult, Write};
+
+use symbol::Symbol;
+
+use interpreter::{Kont, Store};
+
+/// The steps evaluated and bytes allocated by some part of a program.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Cost {
+    /// The number of steps evaluated.
+    pub steps: usize,
+
+    /// The number of bytes allocated.
+    pub bytes: usize,
+}
+
+impl Cost {
+    fn add(&mut self, other: Cost) {
+        self.steps += other.steps;
+        self.bytes += other.bytes;
+    }
+}
+
+/// The cost of a single function, as computed by `Profile::functions`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FunctionCost {
+    /// The name of the function.
+    pub name: Symbol,
+
+    /// The cost of steps evaluated in the function itself.
+    pub self_cost: Cost,
+
+    /// The cost of steps evaluated in the function or anything it called.
+    pub total_cost: Cost,
+}
+
+/// A profile of a program, mapping call stacks to their costs.
+///
+/// Each step is attributed to the named closures on the continuation stack
+/// when it was evaluated, under the root set with `set_root` (usually the
+/// global being initialized). Anonymous closures and intrinsics are
+/// attributed to the named closure that encloses them. Since tail calls
+/// replace the frame of their caller, functions that made a tail call will
+/// not appear.
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    root: Option<Symbol>,
+    stacks: HashMap<Vec<Symbol>, Cost>,
+}
+
+impl Profile {
+    /// Creates a new, empty profile.
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    /// Returns the cost of each function, sorted by total steps, most first.
+    pub fn functions(&self) -> Vec<FunctionCost> {
+        let mut funcs: HashMap<Symbol, (Cost, Cost)> = HashMap::new();
+        for (stack, &cost) in &self.stacks {
+            if let Some(&name) = stack.last() {
+                funcs.entry(name).or_default().0.add(cost);
+            }
+            let mut seen = HashSet::new();
+            for &name in stack {
+                if seen.insert(name) {
+                    funcs.entry(name).or_default().1.add(cost);
+                }
+            }
+        }
+
+        let mut funcs = funcs
+            .into_iter()
+            .map(|(name, (self_cost, total_cost))| FunctionCost {
+                name,
+                self_cost,
+                total_cost,
+            })
+            .collect::<Vec<_>>();
+        funcs.sort_by(|a, b| {
+            b.total_cost
+                .steps
+                .cmp(&a.total_cost.steps)
+                .then_with(|| a.name.as_str().cmp(b.name.as_str()))
+        });
+        funcs
+    }
+
+    /// Records the cost of a step evaluated with the given call stack.
+    pub fn record(&mut self, stack: Vec<Symbol>, cost: Cost) {
+        self.stacks.entry(stack).or_default().add(cost);
+    }
+
+    /// Sets the name that steps recorded from now on are attributed to when
+    /// no named closure is being evaluated.
+    pub fn set_root(&mut self, root: Option<Symbol>) {
+        self.root = root;
+    }
+
+    /// Returns the call stack for the given continuation stack, outermost
+    /// function first. If the outermost function has the same name as the
+    /// root, it is only included once.
+    pub fn stack(&self, konts: &[Kont], store: &Store) -> Vec<Symbol> {
+        let mut stack: Vec<Symbol> = self.root.into_iter().collect();
+        let names = konts.iter().filter_map(|kont| match *kont {
+            Kont::Frame(addr) => store.get_closure(addr).2,
+            _ => None,
+        });
+        for (i, name) in names.enumerate() {
+            if i != 0 || self.root != Some(name) {
+                stack.push(name);
+            }
+        }
+        stack
+    }
+
+    /// Returns the cost of each call stack, outermost function first.
+    pub fn stacks(&self) -> &HashMap<Vec<Symbol>, Cost> {
+        &self.stacks
+    }
+
+    /// Writes the profile in the folded stack format used by flamegraph
+    /// tools, weighted by steps.
+    pub fn write_folded<W: Write>(&self, w: &mut W) -> IoResult<()> {
+        let mut lines = self.stacks
+            .iter()
+            .map(|(stack, cost)| {
+                let names = if stack.is_empty() {
+                    "<toplevel>".to_string()
+                } else {
+                    stack
+                        .iter()
+                        .map(|name| name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(";")
+                };
+                (names, cost.steps)
+            })
+            .collect::<Vec<_>>();
+        lines.sort();
+        for (names, steps) in lines {
+            writeln!(w, "{} {}", names, steps)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a table of the cost of each function.
+    pub fn write_summary<W: Write>(&self, w: &mut W) -> IoResult<()> {
+        writeln!(
+            w,
+            "{:>12} {:>12} {:>14} {:>14}  Function",
+            "Steps", "Self steps", "Bytes", "Self bytes"
+        )?;
+        for func in self.functions() {
+            writeln!(
+                w,
+                "{:>12} {:>12} {:>14} {:>14}  {}",
+                func.total_cost.steps,
+                func.self_cost.steps,
+                func.total_cost.bytes,
+                func.self_cost.bytes,
+                func.name
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+//! The pseudo-random number generator used by the random intrinsics.
+//!
+//! This is xoshiro256**, seeded by expanding a 64-bit seed with splitmix64.
+//! It's fast and has good statistical properties, but isn't cryptographically
+//! secure.
+
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A seedable pseudo-random number generator.
+#[derive(Clone, Debug)]
+pub struct Rng([u64; 4]);
+
+impl Rng {
+    /// Creates a generator from a seed. The same seed always gives the same
+    /// sequence of numbers.
+    pub fn new(seed: u64) -> Rng {
+        let mut seed = seed;
+        let mut state = [0; 4];
+        for n in &mut state {
+            *n = splitmix64(&mut seed);
+        }
+        Rng(state)
+    }
+
+    /// Chooses a seed that's unlikely to be chosen again, from the current
+    /// time and the process ID.
+    pub fn random_seed() -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() ^ (u64::from(d.subsec_nanos()) << 32))
+            .unwrap_or(0);
+        let mut seed = now ^ u64::from(process::id()).rotate_left(17);
+        splitmix64(&mut seed)
+    }
+
+    /// Returns a number in `0..n`, with every number equally likely. `n` must
+    /// not be zero.
+    pub fn below(&mut self, n: u64) -> u64 {
+        assert_ne!(n, 0, "Rng::below called with zero");
+
+        // Numbers at or above the largest multiple of n would make the low
+        // numbers more likely, so they're rejected.
+        let limit = u64::MAX - u64::MAX % n;
+        loop {
+            let x = self.next_u64();
+            if x < limit {
+                return x % n;
+            }
+        }
+    }
+
+    /// Fills a buffer with random bytes.
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let mut x = self.next_u64();
+            for b in chunk {
+                *b = x as u8;
+                x >>= 8;
+            }
+        }
+    }
+
+    /// Returns the next number from the generator.
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.0;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Rng {
+        Rng::new(Rng::random_seed())
+    }
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
@@ -1,5 +1,5 @@
 use interpreter::env::Env;
-use interpreter::{Control, Kont, Value};
+use interpreter::{Control, Kont, RuntimeError, StackTrace, Value};
 
 /// The state of the interpreter.
 #[derive(Debug)]
@@ -9,4 +9,7 @@ pub enum State<'program> {
 
     /// A halted state, where the evaluation has proceeded to the value.
     Halted(Value),
+
+    /// An errored state, where evaluation was stopped by a runtime error.
+    Errored(RuntimeError, StackTrace),
 }
@@ -0,0 +1,74 @@
+//! The source of input for the io intrinsics, which can be replaced by fake
+//! input to test a program that reads from stdin.
+
+use std::io::{stdin, BufRead, Cursor, Read, Result as IoResult};
+
+/// An input stream.
+#[derive(Debug)]
+pub enum Stdin {
+    /// Fake input, read from memory.
+    Fake(Cursor<Vec<u8>>),
+
+    /// The process's stdin. This is read through the buffer Rust keeps for it,
+    /// so input that's been buffered by one read is seen by the next.
+    System,
+}
+
+impl Stdin {
+    /// Creates fake input, which reads the given bytes and then reaches the end
+    /// of input.
+    pub fn fake<B: Into<Vec<u8>>>(input: B) -> Stdin {
+        Stdin::Fake(Cursor::new(input.into()))
+    }
+
+    /// Creates input that reads from the process's stdin.
+    pub fn system() -> Stdin {
+        Stdin::System
+    }
+
+    /// Returns whether the end of input has been reached, waiting for input if
+    /// none is buffered.
+    pub fn is_eof(&mut self) -> IoResult<bool> {
+        self.with_buf_read(|r| r.fill_buf().map(|buf| buf.is_empty()))
+    }
+
+    /// Reads until the end of input.
+    pub fn read_all(&mut self) -> IoResult<String> {
+        let mut s = String::new();
+        self.with_buf_read(|r| r.read_to_string(&mut s))?;
+        Ok(s)
+    }
+
+    /// Reads up to `n` bytes, returning fewer only if the end of input is
+    /// reached.
+    pub fn read_bytes(&mut self, n: u64) -> IoResult<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.with_buf_read(|r| r.take(n).read_to_end(&mut buf))?;
+        Ok(buf)
+    }
+
+    /// Reads a line, including its newline, if it has one. Returns `None` at
+    /// the end of input.
+    pub fn read_line(&mut self) -> IoResult<Option<String>> {
+        let mut line = String::new();
+        let n = self.with_buf_read(|r| r.read_line(&mut line))?;
+        Ok(if n == 0 { None } else { Some(line) })
+    }
+
+    fn with_buf_read<T, F: FnOnce(&mut dyn BufRead) -> T>(&mut self, f: F) -> T {
+        match *self {
+            Stdin::Fake(ref mut cursor) => f(cursor),
+            Stdin::System => {
+                let stdin = stdin();
+                let mut lock = stdin.lock();
+                f(&mut lock)
+            }
+        }
+    }
+}
+
+impl Default for Stdin {
+    fn default() -> Stdin {
+        Stdin::system()
+    }
+}
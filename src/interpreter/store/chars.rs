@@ -0,0 +1,111 @@
+//! An index of the character boundaries in strings, so that strings can be
+//! indexed by character without being scanned from the start.
+//!
+//! Only every `STRIDE`th boundary is recorded, so finding a character scans at
+//! most `STRIDE - 1` characters. The index is only a cache; it's keyed by the
+//! address and length of a string, and is cleared by the garbage collector
+//! rather than relocated. Its size counts towards the size of the store, so
+//! indexing many strings eventually triggers a collection.
+
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use interpreter::store::Addr;
+use interpreter::Store;
+
+/// The number of characters between recorded boundaries.
+const STRIDE: usize = 32;
+
+/// The character boundaries of the strings that have been indexed.
+#[derive(Debug, Default)]
+pub struct CharIndex(HashMap<(usize, usize), Boundaries>);
+
+impl CharIndex {
+    /// Removes every string from the index.
+    pub fn clear(&mut self) {
+        self.0.clear()
+    }
+
+    /// Returns the approximate size of the index, in bytes.
+    pub fn size(&self) -> usize {
+        let entry = size_of::<((usize, usize), Boundaries)>();
+        self.0
+            .values()
+            .map(|b| entry + b.offsets.len() * size_of::<usize>())
+            .sum()
+    }
+}
+
+#[derive(Debug)]
+struct Boundaries {
+    chars: usize,
+
+    /// The byte offsets of every `STRIDE`th character. This is empty if the
+    /// string is ASCII, since then the offset of a character is its index.
+    offsets: Vec<usize>,
+}
+
+impl Boundaries {
+    fn new(s: &str) -> Boundaries {
+        if s.is_ascii() {
+            Boundaries {
+                chars: s.len(),
+                offsets: Vec::new(),
+            }
+        } else {
+            let mut chars = 0;
+            let mut offsets = Vec::new();
+            for (i, _) in s.char_indices() {
+                if chars % STRIDE == 0 {
+                    offsets.push(i);
+                }
+                chars += 1;
+            }
+            Boundaries { chars, offsets }
+        }
+    }
+
+    fn offset(&self, s: &str, n: usize) -> Option<usize> {
+        if n > self.chars {
+            None
+        } else if n == self.chars {
+            Some(s.len())
+        } else if self.offsets.is_empty() {
+            Some(n)
+        } else {
+            let base = self.offsets[n / STRIDE];
+            s[base..].char_indices().nth(n % STRIDE).map(|(i, _)| base + i)
+        }
+    }
+}
+
+impl<'program> Store<'program> {
+    /// Returns the number of characters in a string.
+    pub fn str_char_count(&mut self, addr: Addr<String>, len: usize) -> usize {
+        self.str_boundaries(addr, len, |_, b| b.chars)
+    }
+
+    /// Returns the byte offset of the `n`th character in a string, or the
+    /// length of the string in bytes if `n` is its length in characters.
+    pub fn str_char_offset(&mut self, addr: Addr<String>, len: usize, n: usize) -> Option<usize> {
+        self.str_boundaries(addr, len, |s, b| b.offset(s, n))
+    }
+
+    fn str_boundaries<F, T>(&mut self, addr: Addr<String>, len: usize, f: F) -> T
+    where
+        F: FnOnce(&str, &Boundaries) -> T,
+    {
+        let s = &self.strs[addr.0..addr.0 + len];
+        if len <= STRIDE {
+            // Short strings are cheap enough to scan that indexing them would
+            // mostly waste memory.
+            f(s, &Boundaries::new(s))
+        } else {
+            let boundaries = self.char_index
+                .0
+                .entry((addr.0, len))
+                .or_insert_with(|| Boundaries::new(s));
+            f(s, boundaries)
+        }
+    }
+}
@@ -0,0 +1,508 @@
+//! A mark-compact garbage collector for the store.
+//!
+//! The bignum, value, closure, continuation, and map heaps are compacted
+//! cell-by-cell. The bytes, string,
+//! and vector heaps are different, since slices (e.g. from `vector/slice`) may
+//! point into the middle of, or overlap with, other values. For these heaps,
+//! the live ranges are merged into intervals, and each interval is moved as a
+//! whole, so slices keep their layout relative to each other.
+//!
+//! Keys that are hashed by their addresses (e.g. references) hash differently
+//! once they're moved, so afterwards, any map with such keys that can no longer
+//! find one of them is rebuilt. Roots of maps are never subnodes of other maps,
+//! so each root is rebuilt in place, and the values that point to it stay
+//! valid.
+
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+
+use symbol::Symbol;
+
+use interpreter::store::handle::close;
+use interpreter::store::map::{MapChild, MapNode};
+use interpreter::store::{Addr, Bignum, Handle, Map};
+use interpreter::{Captured, Env, Kont, State, Store, Value};
+
+impl<'program> Store<'program> {
+    /// Frees every value that is not reachable from the given globals or
+    /// state. The addresses in the globals and state are updated to point to
+    /// the new locations of the values.
+    pub fn collect(&mut self, globals: &mut HashMap<Symbol, Value>, state: &mut State<'program>) {
+        let (reloc, map_roots) = {
+            let mut marker = Marker::new(self);
+            for &val in globals.values() {
+                marker.mark(val);
+            }
+            marker.mark_state(state);
+            let mut map_roots = marker.map_roots.drain().collect::<Vec<_>>();
+            map_roots.sort();
+            (marker.finish(), map_roots)
+        };
+
+        let mut memo = HashMap::new();
+        let bigs = self.bigs
+            .drain(..)
+            .enumerate()
+            .filter(|&(i, _)| reloc.bigs[i].is_some())
+            .map(|(_, n)| n)
+            .collect();
+        let mut handles = Vec::new();
+        for (i, h) in self.handles.drain(..).enumerate() {
+            if reloc.handles[i].is_some() {
+                handles.push(h);
+            } else {
+                close(h);
+            }
+        }
+        let vals = self.vals
+            .drain(..)
+            .enumerate()
+            .filter(|&(i, _)| reloc.vals[i].is_some())
+            .map(|(_, val)| reloc.value(val))
+            .collect();
+        let clos = self.clos
+            .drain(..)
+            .enumerate()
+            .filter(|&(i, _)| reloc.clos[i].is_some())
+            .map(|(_, (argn, body, name, env))| {
+                let env = env.relocate(&mut memo, |val| reloc.value(val));
+                (argn, body, name, env)
+            })
+            .collect();
+        let conts = self.conts
+            .drain(..)
+            .enumerate()
+            .filter(|&(i, _)| reloc.conts[i].is_some())
+            .map(|(_, mut cont)| {
+                if let Captured::Full(ref mut konts) = cont {
+                    for kont in konts {
+                        reloc.kont(kont, &mut memo);
+                    }
+                }
+                cont
+            })
+            .collect();
+        let maps = self.maps
+            .drain(..)
+            .enumerate()
+            .filter(|&(i, _)| reloc.maps[i].is_some())
+            .map(|(_, node)| reloc.map_node(node))
+            .collect();
+        let vecs = reloc
+            .vecs
+            .copy(&self.vecs)
+            .into_iter()
+            .map(|addr| reloc.addr(addr))
+            .collect();
+        let bytes = reloc.bytes.copy(&self.bytes);
+        let strs = reloc
+            .strs
+            .spans
+            .iter()
+            .map(|&(start, end, _)| &self.strs[start..end])
+            .collect();
+
+        self.bigs = bigs;
+        self.handles = handles;
+        self.vals = vals;
+        self.clos = clos;
+        self.conts = conts;
+        self.maps = maps;
+        self.vecs = vecs;
+        self.bytes = bytes;
+        self.strs = strs;
+        self.char_index.clear();
+
+        for val in globals.values_mut() {
+            *val = reloc.value(*val);
+        }
+        match *state {
+            State::Running(_, ref mut env, ref mut konts) => {
+                *env = env.relocate(&mut memo, |val| reloc.value(val));
+                for kont in konts {
+                    reloc.kont(kont, &mut memo);
+                }
+            }
+            State::Halted(ref mut val) => *val = reloc.value(*val),
+            State::Errored(_, _) => {}
+        }
+
+        let map_roots = map_roots
+            .into_iter()
+            .map(|root| reloc.map(Addr(root, PhantomData)))
+            .collect::<Vec<_>>();
+        self.address_keyed_maps = map_roots.iter().map(|root| root.0).collect();
+        for root in map_roots {
+            self.rehash_map(root);
+        }
+    }
+
+    /// Rebuilds the map at the given address if any of its keys can't be
+    /// found, which happens when keys hashed by their addresses are moved.
+    fn rehash_map(&mut self, addr: Addr<Map>) {
+        let entries = self.get_map(addr);
+        if entries
+            .iter()
+            .all(|&(k, _)| self.map_get(addr, k).is_some())
+        {
+            return;
+        }
+
+        let mut map = self.store_map();
+        for (k, v) in entries {
+            map = self.map_insert(map, k, v).0;
+        }
+        self.maps[addr.0] = self.maps[map.0].clone();
+    }
+}
+
+/// The state of the mark phase.
+struct Marker<'store, 'program: 'store> {
+    store: &'store Store<'program>,
+    work: Vec<Value>,
+    envs: HashSet<usize>,
+    map_roots: HashSet<usize>,
+
+    bigs: Vec<bool>,
+    handles: Vec<bool>,
+    vals: Vec<bool>,
+    clos: Vec<bool>,
+    conts: Vec<bool>,
+    maps: Vec<bool>,
+    vec_cells: Vec<bool>,
+
+    bytes: Vec<(usize, usize)>,
+    strs: Vec<(usize, usize)>,
+    vecs: Vec<(usize, usize)>,
+}
+
+impl<'store, 'program: 'store> Marker<'store, 'program> {
+    fn new(store: &'store Store<'program>) -> Marker<'store, 'program> {
+        let mut vals = vec![false; store.vals.len()];
+        vals[0] = true;
+        Marker {
+            store,
+            work: Vec::new(),
+            envs: HashSet::new(),
+            map_roots: HashSet::new(),
+            bigs: vec![false; store.bigs.len()],
+            handles: vec![false; store.handles.len()],
+            vals,
+            clos: vec![false; store.clos.len()],
+            conts: vec![false; store.conts.len()],
+            maps: vec![false; store.maps.len()],
+            vec_cells: vec![false; store.vecs.len()],
+            bytes: Vec::new(),
+            strs: Vec::new(),
+            vecs: Vec::new(),
+        }
+    }
+
+    /// Marks everything reachable from the given value.
+    fn mark(&mut self, val: Value) {
+        self.work.push(val);
+        while let Some(val) = self.work.pop() {
+            self.mark_one(val);
+        }
+    }
+
+    fn mark_addr(&mut self, addr: Addr<Value>) {
+        if !self.vals[addr.0] {
+            self.vals[addr.0] = true;
+            self.work.push(self.store.vals[addr.0]);
+        }
+    }
+
+    fn mark_env(&mut self, env: &Env) {
+        let work = &mut self.work;
+        env.trace(&mut self.envs, |val| work.push(val));
+    }
+
+    fn mark_one(&mut self, val: Value) {
+        match val {
+            Value::Bignum(a) => self.bigs[a.0] = true,
+            Value::Handle(a) => self.handles[a.0] = true,
+            Value::Bytes(a, l) => self.bytes.push((a.0, a.0 + l)),
+            Value::Closure(a) => if !self.clos[a.0] {
+                self.clos[a.0] = true;
+                let store = self.store;
+                self.mark_env(&store.clos[a.0].3);
+            },
+            Value::Cons(h, t) => {
+                self.mark_addr(h);
+                self.mark_addr(t);
+            }
+            Value::Continuation(a) => if !self.conts[a.0] {
+                self.conts[a.0] = true;
+                let store = self.store;
+                if let Captured::Full(ref konts) = store.conts[a.0] {
+                    for kont in konts {
+                        self.mark_kont(kont);
+                    }
+                }
+            },
+            Value::Map(a, l) => if !self.maps[a.0] {
+                // Subnodes are marked with a length of zero, as are empty
+                // maps, which never need to be rebuilt.
+                if l > 0 && self.store.address_keyed_maps.contains(&a.0) {
+                    self.map_roots.insert(a.0);
+                }
+                self.maps[a.0] = true;
+                let store = self.store;
+                match store.maps[a.0] {
+                    MapNode::Branch(_, ref children) => for &child in children {
+                        match child {
+                            MapChild::Entry(k, v) => {
+                                self.mark_addr(k);
+                                self.mark_addr(v);
+                            }
+                            MapChild::Node(node) => self.work.push(Value::Map(node, 0)),
+                        }
+                    },
+                    MapNode::Collision(ref pairs) => for &(k, v) in pairs {
+                        self.mark_addr(k);
+                        self.mark_addr(v);
+                    },
+                }
+            },
+            Value::Object(_, v) | Value::Ref(v) => self.mark_addr(v),
+            Value::String(a, l) => self.strs.push((a.0, a.0 + l)),
+            Value::Vector(a, l) => {
+                self.vecs.push((a.0, a.0 + l));
+                for i in a.0..a.0 + l {
+                    if !self.vec_cells[i] {
+                        self.vec_cells[i] = true;
+                        let addr = self.store.vecs[i];
+                        self.mark_addr(addr);
+                    }
+                }
+            }
+            Value::Byte(_)
+            | Value::Fixnum(_)
+            | Value::Float(_)
+            | Value::Intrinsic(_)
+            | Value::Nil
+            | Value::Symbol(_) => {}
+        }
+    }
+
+    fn mark_kont(&mut self, kont: &Kont<'program>) {
+        match *kont {
+            Kont::Escape(addr) => self.work.push(Value::Continuation(addr)),
+            Kont::Frame(addr) => self.work.push(Value::Closure(addr)),
+            Kont::Handler(handler) => self.work.push(handler),
+            Kont::Let(_, ref env) | Kont::Seq(_, ref env) => self.mark_env(env),
+            Kont::MakeVector(_, _, func, ref acc) => {
+                self.work.push(func);
+                for &addr in acc {
+                    self.mark_addr(addr);
+                }
+            }
+        }
+    }
+
+    fn mark_state(&mut self, state: &State<'program>) {
+        match *state {
+            State::Running(_, ref env, ref konts) => {
+                self.mark_env(env);
+                for kont in konts {
+                    self.mark_kont(kont);
+                }
+            }
+            State::Halted(val) => self.work.push(val),
+            State::Errored(_, _) => {}
+        }
+        while let Some(val) = self.work.pop() {
+            self.mark_one(val);
+        }
+    }
+
+    /// Computes where each live value will be moved to.
+    fn finish(self) -> Relocation {
+        fn forwarding(marks: Vec<bool>) -> Vec<Option<usize>> {
+            let mut n = 0;
+            marks
+                .into_iter()
+                .map(|live| {
+                    if live {
+                        n += 1;
+                        Some(n - 1)
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        }
+
+        Relocation {
+            bigs: forwarding(self.bigs),
+            handles: forwarding(self.handles),
+            vals: forwarding(self.vals),
+            clos: forwarding(self.clos),
+            conts: forwarding(self.conts),
+            maps: forwarding(self.maps),
+            bytes: Intervals::new(self.bytes),
+            strs: Intervals::new(self.strs),
+            vecs: Intervals::new(self.vecs),
+        }
+    }
+}
+
+/// The new locations of every live value.
+struct Relocation {
+    bigs: Vec<Option<usize>>,
+    handles: Vec<Option<usize>>,
+    vals: Vec<Option<usize>>,
+    clos: Vec<Option<usize>>,
+    conts: Vec<Option<usize>>,
+    maps: Vec<Option<usize>>,
+    bytes: Intervals,
+    strs: Intervals,
+    vecs: Intervals,
+}
+
+impl Relocation {
+    fn addr(&self, addr: Addr<Value>) -> Addr<Value> {
+        let n = self.vals[addr.0].expect("Dangling pointer found after marking");
+        Addr(n, PhantomData)
+    }
+
+    fn kont<'program>(&self, kont: &mut Kont<'program>, memo: &mut HashMap<usize, Env>) {
+        match *kont {
+            Kont::Escape(ref mut addr) => {
+                let n = self.conts[addr.0].expect("Dangling continuation found after marking");
+                *addr = Addr(n, PhantomData);
+            }
+            Kont::Frame(ref mut addr) => {
+                let n = self.clos[addr.0].expect("Dangling closure found after marking");
+                *addr = Addr(n, PhantomData);
+            }
+            Kont::Handler(ref mut handler) => *handler = self.value(*handler),
+            Kont::Let(_, ref mut env) | Kont::Seq(_, ref mut env) => {
+                *env = env.relocate(memo, |val| self.value(val));
+            }
+            Kont::MakeVector(_, _, ref mut func, ref mut acc) => {
+                *func = self.value(*func);
+                for addr in acc {
+                    *addr = self.addr(*addr);
+                }
+            }
+        }
+    }
+
+    fn map(&self, addr: Addr<Map>) -> Addr<Map> {
+        let n = self.maps[addr.0].expect("Dangling map found after marking");
+        Addr(n, PhantomData)
+    }
+
+    fn map_node(&self, node: MapNode) -> MapNode {
+        match node {
+            MapNode::Branch(bitmap, children) => {
+                let children = children
+                    .into_iter()
+                    .map(|child| match child {
+                        MapChild::Entry(k, v) => MapChild::Entry(self.addr(k), self.addr(v)),
+                        MapChild::Node(node) => MapChild::Node(self.map(node)),
+                    })
+                    .collect();
+                MapNode::Branch(bitmap, children)
+            }
+            MapNode::Collision(pairs) => MapNode::Collision(
+                pairs
+                    .into_iter()
+                    .map(|(k, v)| (self.addr(k), self.addr(v)))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn value(&self, val: Value) -> Value {
+        match val {
+            Value::Bignum(a) => {
+                let n = self.bigs[a.0].expect("Dangling bignum found after marking");
+                Value::Bignum(Addr::<Bignum>(n, PhantomData))
+            }
+            Value::Bytes(a, l) => Value::Bytes(self.bytes.relocate(a, l), l),
+            Value::Handle(a) => {
+                let n = self.handles[a.0].expect("Dangling handle found after marking");
+                Value::Handle(Addr::<Handle>(n, PhantomData))
+            }
+            Value::Closure(a) => {
+                let n = self.clos[a.0].expect("Dangling closure found after marking");
+                Value::Closure(Addr(n, PhantomData))
+            }
+            Value::Cons(h, t) => Value::Cons(self.addr(h), self.addr(t)),
+            Value::Continuation(a) => {
+                let n = self.conts[a.0].expect("Dangling continuation found after marking");
+                Value::Continuation(Addr(n, PhantomData))
+            }
+            Value::Map(a, l) => Value::Map(self.map(a), l),
+            Value::Object(s, v) => Value::Object(s, self.addr(v)),
+            Value::Ref(v) => Value::Ref(self.addr(v)),
+            Value::String(a, l) => Value::String(self.strs.relocate(a, l), l),
+            Value::Vector(a, l) => Value::Vector(self.vecs.relocate(a, l), l),
+            Value::Byte(_)
+            | Value::Fixnum(_)
+            | Value::Float(_)
+            | Value::Intrinsic(_)
+            | Value::Nil
+            | Value::Symbol(_) => val,
+        }
+    }
+}
+
+/// A set of disjoint, sorted intervals of a heap, along with the address each
+/// interval will start at after compaction.
+struct Intervals {
+    spans: Vec<(usize, usize, usize)>,
+}
+
+impl Intervals {
+    fn new(mut ranges: Vec<(usize, usize)>) -> Intervals {
+        ranges.retain(|&(start, end)| start < end);
+        ranges.sort();
+
+        let mut spans: Vec<(usize, usize, usize)> = Vec::new();
+        let mut next = 0;
+        for (start, end) in ranges {
+            if let Some(last) = spans.last_mut() {
+                if start <= last.1 {
+                    if end > last.1 {
+                        next += end - last.1;
+                        last.1 = end;
+                    }
+                    continue;
+                }
+            }
+            spans.push((start, end, next));
+            next += end - start;
+        }
+        Intervals { spans }
+    }
+
+    /// Copies the live intervals out of the given heap.
+    fn copy<T: Clone>(&self, heap: &[T]) -> Vec<T> {
+        let mut out = Vec::new();
+        for &(start, end, _) in &self.spans {
+            out.extend_from_slice(&heap[start..end]);
+        }
+        out
+    }
+
+    /// Returns the new address of a slice. Empty slices that don't fall in any
+    /// interval are moved to the start of the heap.
+    fn relocate<T>(&self, addr: Addr<T>, len: usize) -> Addr<T> {
+        let i = match self.spans.binary_search_by_key(&addr.0, |&(start, _, _)| start) {
+            Ok(i) => i,
+            Err(0) => return Addr(0, PhantomData),
+            Err(i) => i - 1,
+        };
+        let (start, end, new) = self.spans[i];
+        if addr.0 < end || (len == 0 && addr.0 == end) {
+            Addr(new + addr.0 - start, PhantomData)
+        } else {
+            debug_assert_eq!(len, 0);
+            Addr(0, PhantomData)
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! The handles a program holds to open files, directories, and child
+//! processes.
+//!
+//! A handle owns its file or directory, so dropping the handle closes it. This
+//! happens either when the program closes the handle, which leaves it in the
+//! `Closed` state, or when the garbage collector finds it unreachable. Closing
+//! a handle to a child process doesn't kill it, but does close its pipes, and
+//! waits for it on another thread so it's reaped once it exits.
+
+use std::fs::{File, ReadDir};
+use std::marker::PhantomData;
+use std::mem::replace;
+use std::process::Child;
+use std::thread;
+
+use interpreter::store::{Addr, Handle};
+use interpreter::{Store, Value};
+
+/// The state of an open (or formerly open) file or directory.
+#[derive(Debug)]
+pub enum HandleState {
+    /// A handle that has been closed.
+    Closed,
+
+    /// A handle to a directory that is being iterated over.
+    Dir(ReadDir),
+
+    /// A handle to an open file.
+    File(File),
+
+    /// A handle to a child process that hasn't been waited for.
+    Process(Child),
+}
+
+/// Closes the file, directory, or child process of a handle.
+pub fn close(state: HandleState) {
+    if let HandleState::Process(mut child) = state {
+        drop(child.stdout.take());
+        drop(child.stderr.take());
+        thread::spawn(move || child.wait());
+    }
+}
+
+impl<'program> Store<'program> {
+    /// Closes a handle. Closing a handle that's already closed does nothing.
+    pub fn close_handle(&mut self, addr: Addr<Handle>) {
+        close(replace(&mut self.handles[addr.0], HandleState::Closed));
+    }
+
+    /// Gets a handle out of the handle heap.
+    pub fn get_handle(&mut self, addr: Addr<Handle>) -> &mut HandleState {
+        &mut self.handles[addr.0]
+    }
+
+    /// Returns the number of handles that are currently open.
+    pub fn open_handles(&self) -> usize {
+        self.handles
+            .iter()
+            .filter(|h| !matches!(**h, HandleState::Closed))
+            .count()
+    }
+
+    /// Stores a handle into the handle heap.
+    pub fn store_handle(&mut self, handle: HandleState) -> Value {
+        let n = self.handles.len();
+        self.handles.push(handle);
+        Value::Handle(Addr(n, PhantomData))
+    }
+}
@@ -0,0 +1,772 @@
+//! Heap images, which save the store and globals of an initialized program so
+//! that it can be started again without reinitializing it.
+//!
+//! Closures and captured continuations refer to expressions in the program,
+//! which are saved as paths into the program: the index of the decl the
+//! expression is in, and its position in a preorder traversal of the decl.
+//! Intrinsics are saved by their name in a builtin package. An image records
+//! a fingerprint of the program, and can only be loaded with the same
+//! program.
+
+use std::cmp::min;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Result as FmtResult, Write as FmtWrite};
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+
+use failure::Error;
+use num::BigInt;
+use podio::{LittleEndian, ReadPodExt, WritePodExt};
+use symbol::Symbol;
+
+use flatanf::{AExpr, CExpr, Expr, Program};
+use interpreter::env::EnvTable;
+use interpreter::store::chars::CharIndex;
+use interpreter::store::map::{MapChild, MapNode, BITS};
+use interpreter::store::{Addr, Map};
+use interpreter::{Captured, Clock, Env, Intrinsic, Kont, Rng, Stdin, Store, Value};
+use BuiltinPackage;
+
+type Result<T> = ::std::result::Result<T, Error>;
+
+/// The version of the image format, which is incremented whenever it changes.
+const VERSION: u8 = 4;
+
+/// The most elements preallocated for a length read from an image. A corrupt
+/// image can claim any length, so larger vectors grow as their elements are
+/// actually read, and a bad length fails with a short read instead of an
+/// allocation failure.
+const MAX_PREALLOC: usize = 4096;
+
+fn with_capacity<T>(len: usize) -> Vec<T> {
+    Vec::with_capacity(min(len, MAX_PREALLOC))
+}
+
+fn write_usize<W: Write>(n: usize, w: &mut W) -> Result<()> {
+    w.write_u64::<LittleEndian>(n as u64)?;
+    Ok(())
+}
+
+fn write_str<W: Write>(s: &str, w: &mut W) -> Result<()> {
+    write_usize(s.len(), w)?;
+    w.write_all(s.as_bytes())?;
+    Ok(())
+}
+
+fn read_usize<R: Read>(r: &mut R) -> Result<usize> {
+    let n = r.read_u64::<LittleEndian>()?;
+    if n > usize::MAX as u64 {
+        bail!("Overflow in deserializing usize from {}", n)
+    }
+    Ok(n as usize)
+}
+
+fn read_addr<R: Read, T>(r: &mut R, len: usize) -> Result<Addr<T>> {
+    let n = read_usize(r)?;
+    if n >= len {
+        bail!("Invalid address in image: {} (heap has length {})", n, len)
+    }
+    Ok(Addr(n, PhantomData))
+}
+
+fn read_bytes<R: Read>(r: &mut R) -> Result<Vec<u8>> {
+    let len = read_usize(r)?;
+    let mut buf = with_capacity(len);
+    r.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        bail!("Invalid image: expected {} bytes, but it ended after {}", len, buf.len())
+    }
+    Ok(buf)
+}
+
+fn read_string<R: Read>(r: &mut R) -> Result<String> {
+    String::from_utf8(read_bytes(r)?).map_err(Error::from)
+}
+
+/// The expressions of a program, indexed by their paths.
+struct ExprPaths<'program> {
+    exprs: Vec<Vec<&'program Expr>>,
+    paths: HashMap<usize, (usize, usize)>,
+}
+
+impl<'program> ExprPaths<'program> {
+    fn new(program: &'program Program) -> ExprPaths<'program> {
+        fn walk<'program>(expr: &'program Expr, out: &mut Vec<&'program Expr>) {
+            out.push(expr);
+            match *expr {
+                Expr::AExpr(ref a) => walk_atomic(a, out),
+                Expr::CExpr(CExpr::Call(ref func, ref args)) => {
+                    walk_atomic(func, out);
+                    for arg in args {
+                        walk_atomic(arg, out);
+                    }
+                }
+                Expr::CExpr(CExpr::If(ref c, ref t, ref e)) => {
+                    walk_atomic(c, out);
+                    walk(t, out);
+                    walk(e, out);
+                }
+                Expr::CExpr(CExpr::LetRec(ref bound, ref body)) => {
+                    for (_, _, body) in bound {
+                        walk(body, out);
+                    }
+                    walk(body, out);
+                }
+                Expr::Let(ref a, ref b) | Expr::Seq(ref a, ref b) => {
+                    walk(a, out);
+                    walk(b, out);
+                }
+            }
+        }
+
+        fn walk_atomic<'program>(expr: &'program AExpr, out: &mut Vec<&'program Expr>) {
+            match *expr {
+                AExpr::GetMethod(ref type_, _) => walk_atomic(type_, out),
+                AExpr::Lambda(_, _, ref body) => walk(body, out),
+                AExpr::Vector(ref exprs) => for expr in exprs {
+                    walk_atomic(expr, out);
+                },
+                AExpr::Global(_) | AExpr::Literal(_) | AExpr::Local(_) => {}
+            }
+        }
+
+        let mut exprs = Vec::with_capacity(program.decls.len());
+        let mut paths = HashMap::new();
+        for (i, (_, expr)) in program.decls.iter().enumerate() {
+            let mut decl = Vec::new();
+            walk(expr, &mut decl);
+            for (j, &expr) in decl.iter().enumerate() {
+                paths.insert(expr as *const Expr as usize, (i, j));
+            }
+            exprs.push(decl);
+        }
+        ExprPaths { exprs, paths }
+    }
+
+    fn read<R: Read>(&self, r: &mut R) -> Result<&'program Expr> {
+        let i = read_usize(r)?;
+        let j = read_usize(r)?;
+        match self.exprs.get(i).and_then(|decl| decl.get(j)) {
+            Some(&expr) => Ok(expr),
+            None => bail!("Invalid expression path in image: {}/{}", i, j),
+        }
+    }
+
+    fn write<W: Write>(&self, expr: &Expr, w: &mut W) -> Result<()> {
+        match self.paths.get(&(expr as *const Expr as usize)) {
+            Some(&(i, j)) => {
+                write_usize(i, w)?;
+                write_usize(j, w)
+            }
+            None => bail!("Can't save an expression that isn't part of the program"),
+        }
+    }
+}
+
+/// A 64-bit FNV-1a hasher, used to fingerprint programs. Unlike the hashers
+/// in `std`, this is guaranteed to be stable.
+struct Fingerprint(u64);
+
+impl Fingerprint {
+    fn of(program: &Program) -> u64 {
+        let mut hasher = Fingerprint(0xcbf2_9ce4_8422_2325);
+        for &(name, ref expr) in &program.decls {
+            writeln!(hasher, "{} = {}", name, expr).unwrap();
+        }
+        hasher.0
+    }
+}
+
+impl FmtWrite for Fingerprint {
+    fn write_str(&mut self, s: &str) -> FmtResult {
+        for &b in s.as_bytes() {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+        Ok(())
+    }
+}
+
+impl<'program> Store<'program> {
+    /// Reads a store and globals from an image. Intrinsics are looked up in
+    /// the builtin package `P`.
+    pub fn load_image<P: BuiltinPackage, R: Read>(
+        program: &'program Program,
+        r: &mut R,
+    ) -> Result<(Store<'program>, HashMap<Symbol, Value>)> {
+        let mut sig = [0; 4];
+        r.read_exact(&mut sig)?;
+        if &sig != b"ofti" {
+            bail!("Invalid signature: {:?}", sig)
+        }
+        let version = r.read_u8()?;
+        if version != VERSION {
+            bail!("Unsupported image version: {}", version)
+        }
+        if r.read_u64::<LittleEndian>()? != Fingerprint::of(program) {
+            bail!("The image was saved from a different program")
+        }
+
+        let paths = ExprPaths::new(program);
+        let mut lens = [0; 8];
+        for len in lens.iter_mut() {
+            *len = read_usize(r)?;
+        }
+
+        let bytes = read_bytes(r)?;
+        let strs = read_string(r)?;
+        if bytes.len() != lens[0] || strs.len() != lens[4] {
+            bail!("Invalid image: heap lengths don't match")
+        }
+        let mut reader = ImageReader {
+            intrinsics: P::values(),
+            r,
+            lens,
+            strs,
+            maps: Vec::new(),
+        };
+
+        let mut bigs = with_capacity(reader.lens[7]);
+        for _ in 0..reader.lens[7] {
+            bigs.push(BigInt::from_signed_bytes_le(&read_bytes(reader.r)?));
+        }
+
+        let mut vals = with_capacity(reader.lens[5]);
+        for _ in 0..reader.lens[5] {
+            vals.push(reader.value()?);
+        }
+        let mut vecs = with_capacity(reader.lens[3]);
+        for _ in 0..reader.lens[3] {
+            vecs.push(read_addr(reader.r, reader.lens[5])?);
+        }
+
+        let mut maps = with_capacity(reader.lens[6]);
+        for _ in 0..reader.lens[6] {
+            maps.push(reader.map_node()?);
+        }
+
+        let mut address_keyed_maps = HashSet::new();
+        for _ in 0..read_usize(reader.r)? {
+            address_keyed_maps.insert(read_addr::<_, Map>(reader.r, reader.lens[6])?.0);
+        }
+
+        let mut envs = vec![Env::new()];
+        for _ in 0..read_usize(reader.r)? {
+            let val = reader.value()?;
+            let id = read_usize(reader.r)?;
+            let env = match envs.get(id) {
+                Some(env) => env.clone().push(val),
+                None => bail!("Invalid environment in image: {}", id),
+            };
+            envs.push(env);
+        }
+        let read_env = |r: &mut ImageReader<R>| -> Result<Env> {
+            let id = read_usize(r.r)?;
+            match envs.get(id) {
+                Some(env) => Ok(env.clone()),
+                None => bail!("Invalid environment in image: {}", id),
+            }
+        };
+
+        let mut clos = with_capacity(reader.lens[1]);
+        for _ in 0..reader.lens[1] {
+            let argn = read_usize(reader.r)?;
+            let body = paths.read(reader.r)?;
+            let name = read_string(reader.r)?;
+            let name = if name.is_empty() {
+                None
+            } else {
+                Some(name.into())
+            };
+            let env = read_env(&mut reader)?;
+            clos.push((argn, body, name, env));
+        }
+
+        let mut conts = with_capacity(reader.lens[2]);
+        for _ in 0..reader.lens[2] {
+            let cont = match reader.r.read_u8()? {
+                0x00 => Captured::Escape,
+                0x01 => {
+                    let len = read_usize(reader.r)?;
+                    let mut konts = with_capacity(len);
+                    for _ in 0..len {
+                        let kont = match reader.r.read_u8()? {
+                            0x00 => Kont::Escape(read_addr(reader.r, reader.lens[2])?),
+                            0x01 => Kont::Frame(read_addr(reader.r, reader.lens[1])?),
+                            0x02 => Kont::Handler(reader.value()?),
+                            0x03 => {
+                                let expr = paths.read(reader.r)?;
+                                Kont::Let(expr, read_env(&mut reader)?)
+                            }
+                            0x04 => {
+                                let cur = read_usize(reader.r)?;
+                                let last = read_usize(reader.r)?;
+                                let func = reader.value()?;
+                                let len = read_usize(reader.r)?;
+                                if len != cur || cur > last {
+                                    bail!("Invalid vector construction in image: {}/{}", cur, last)
+                                }
+                                let mut acc = with_capacity(len);
+                                for _ in 0..len {
+                                    acc.push(read_addr(reader.r, reader.lens[5])?);
+                                }
+                                Kont::MakeVector(cur, last, func, acc)
+                            }
+                            0x05 => {
+                                let expr = paths.read(reader.r)?;
+                                Kont::Seq(expr, read_env(&mut reader)?)
+                            }
+                            discrim => bail!("Unknown discriminant for Kont: {}", discrim),
+                        };
+                        konts.push(kont);
+                    }
+                    Captured::Full(konts)
+                }
+                discrim => bail!("Unknown discriminant for Captured: {}", discrim),
+            };
+            conts.push(cont);
+        }
+
+        let mut globals = HashMap::new();
+        for _ in 0..read_usize(reader.r)? {
+            let name = read_string(reader.r)?;
+            let val = reader.value()?;
+            globals.insert(name.into(), val);
+        }
+
+        check_maps(&maps, &reader.maps)?;
+
+        let store = Store {
+            bigs,
+            bytes,
+            clos,
+            conts,
+            handles: Vec::new(),
+            maps,
+            strs: reader.strs,
+            vecs,
+            vals,
+            address_keyed_maps,
+            char_index: CharIndex::default(),
+            clock: Clock::default(),
+            program_name: None,
+            rng: Rng::default(),
+            stdin: Stdin::default(),
+        };
+        Ok((store, globals))
+    }
+
+    /// Writes the store and the given globals to an image. Intrinsics are
+    /// saved by their names in the builtin package `P`.
+    pub fn save_image<P: BuiltinPackage, W: Write>(
+        &self,
+        globals: &HashMap<Symbol, Value>,
+        program: &'program Program,
+        w: &mut W,
+    ) -> Result<()> {
+        let paths = ExprPaths::new(program);
+        let intrinsics = P::values()
+            .into_iter()
+            .filter_map(|(name, val)| match val {
+                Value::Intrinsic(i) => Some((i, name)),
+                _ => None,
+            })
+            .collect::<BTreeMap<_, _>>();
+        let value = |val: Value, w: &mut Vec<u8>| write_value(val, &intrinsics, w);
+
+        // Environments are shared between closures and continuations, so
+        // they're written as a table, which is only complete once every
+        // closure and continuation has been visited.
+        let mut table = EnvTable::default();
+        let mut clos = Vec::new();
+        for &(argn, body, name, ref env) in &self.clos {
+            write_usize(argn, &mut clos)?;
+            paths.write(body, &mut clos)?;
+            write_str(name.as_ref().map(|name| name.as_str()).unwrap_or(""), &mut clos)?;
+            write_usize(env.add_to_table(&mut table), &mut clos)?;
+        }
+        let mut conts = Vec::new();
+        for cont in &self.conts {
+            match *cont {
+                Captured::Escape => conts.write_u8(0x00)?,
+                Captured::Full(ref konts) => {
+                    conts.write_u8(0x01)?;
+                    write_usize(konts.len(), &mut conts)?;
+                    for kont in konts {
+                        match *kont {
+                            Kont::Escape(addr) => {
+                                conts.write_u8(0x00)?;
+                                write_usize(addr.0, &mut conts)?;
+                            }
+                            Kont::Frame(addr) => {
+                                conts.write_u8(0x01)?;
+                                write_usize(addr.0, &mut conts)?;
+                            }
+                            Kont::Handler(handler) => {
+                                conts.write_u8(0x02)?;
+                                value(handler, &mut conts)?;
+                            }
+                            Kont::Let(expr, ref env) => {
+                                conts.write_u8(0x03)?;
+                                paths.write(expr, &mut conts)?;
+                                write_usize(env.add_to_table(&mut table), &mut conts)?;
+                            }
+                            Kont::MakeVector(cur, last, func, ref acc) => {
+                                conts.write_u8(0x04)?;
+                                write_usize(cur, &mut conts)?;
+                                write_usize(last, &mut conts)?;
+                                value(func, &mut conts)?;
+                                write_usize(acc.len(), &mut conts)?;
+                                for addr in acc {
+                                    write_usize(addr.0, &mut conts)?;
+                                }
+                            }
+                            Kont::Seq(expr, ref env) => {
+                                conts.write_u8(0x05)?;
+                                paths.write(expr, &mut conts)?;
+                                write_usize(env.add_to_table(&mut table), &mut conts)?;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut out = Vec::new();
+        out.write_all(b"ofti")?;
+        out.write_u8(VERSION)?;
+        out.write_u64::<LittleEndian>(Fingerprint::of(program))?;
+        for &len in &[
+            self.bytes.len(),
+            self.clos.len(),
+            self.conts.len(),
+            self.vecs.len(),
+            self.strs.len(),
+            self.vals.len(),
+            self.maps.len(),
+            self.bigs.len(),
+        ] {
+            write_usize(len, &mut out)?;
+        }
+        write_usize(self.bytes.len(), &mut out)?;
+        out.write_all(&self.bytes)?;
+        write_str(&self.strs, &mut out)?;
+        for n in &self.bigs {
+            let bs = n.to_signed_bytes_le();
+            write_usize(bs.len(), &mut out)?;
+            out.write_all(&bs)?;
+        }
+        for &val in &self.vals {
+            value(val, &mut out)?;
+        }
+        for addr in &self.vecs {
+            write_usize(addr.0, &mut out)?;
+        }
+        for node in &self.maps {
+            write_map_node(node, &mut out)?;
+        }
+        let mut address_keyed_maps = self.address_keyed_maps.iter().collect::<Vec<_>>();
+        address_keyed_maps.sort();
+        write_usize(address_keyed_maps.len(), &mut out)?;
+        for &addr in address_keyed_maps {
+            write_usize(addr, &mut out)?;
+        }
+        write_usize(table.cells.len(), &mut out)?;
+        for &(val, id) in &table.cells {
+            value(val, &mut out)?;
+            write_usize(id, &mut out)?;
+        }
+        out.write_all(&clos)?;
+        out.write_all(&conts)?;
+
+        let mut globals = globals.iter().collect::<Vec<_>>();
+        globals.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+        write_usize(globals.len(), &mut out)?;
+        for (name, &val) in globals {
+            write_str(name.as_str(), &mut out)?;
+            value(val, &mut out)?;
+        }
+
+        w.write_all(&out)?;
+        Ok(())
+    }
+}
+
+fn write_value<W: Write>(
+    val: Value,
+    intrinsics: &BTreeMap<Intrinsic, Symbol>,
+    w: &mut W,
+) -> Result<()> {
+    match val {
+        Value::Byte(n) => {
+            w.write_u8(0x00)?;
+            w.write_u8(n)?;
+        }
+        Value::Bytes(addr, len) => {
+            w.write_u8(0x01)?;
+            write_usize(addr.0, w)?;
+            write_usize(len, w)?;
+        }
+        Value::Closure(addr) => {
+            w.write_u8(0x02)?;
+            write_usize(addr.0, w)?;
+        }
+        Value::Cons(hd, tl) => {
+            w.write_u8(0x03)?;
+            write_usize(hd.0, w)?;
+            write_usize(tl.0, w)?;
+        }
+        Value::Continuation(addr) => {
+            w.write_u8(0x04)?;
+            write_usize(addr.0, w)?;
+        }
+        Value::Fixnum(n) => {
+            w.write_u8(0x05)?;
+            write_usize(n as usize, w)?;
+        }
+        Value::Intrinsic(i) => match intrinsics.get(&i) {
+            Some(name) => {
+                w.write_u8(0x06)?;
+                write_str(name.as_str(), w)?;
+            }
+            None => bail!("Can't save an intrinsic that isn't a builtin: {}", i),
+        },
+        Value::Nil => w.write_u8(0x07)?,
+        Value::Object(type_, addr) => {
+            w.write_u8(0x08)?;
+            write_str(type_.as_str(), w)?;
+            write_usize(addr.0, w)?;
+        }
+        Value::String(addr, len) => {
+            w.write_u8(0x09)?;
+            write_usize(addr.0, w)?;
+            write_usize(len, w)?;
+        }
+        Value::Symbol(sym) => {
+            w.write_u8(0x0a)?;
+            write_str(sym.as_str(), w)?;
+        }
+        Value::Vector(addr, len) => {
+            w.write_u8(0x0b)?;
+            write_usize(addr.0, w)?;
+            write_usize(len, w)?;
+        }
+        Value::Ref(addr) => {
+            w.write_u8(0x0c)?;
+            write_usize(addr.0, w)?;
+        }
+        Value::Map(addr, len) => {
+            w.write_u8(0x0d)?;
+            write_usize(addr.0, w)?;
+            write_usize(len, w)?;
+        }
+        Value::Bignum(addr) => {
+            w.write_u8(0x0e)?;
+            write_usize(addr.0, w)?;
+        }
+        Value::Float(n) => {
+            w.write_u8(0x0f)?;
+            w.write_f64::<LittleEndian>(n)?;
+        }
+        Value::Handle(_) => bail!("Can't save a handle in an image"),
+    }
+    Ok(())
+}
+
+fn write_map_node<W: Write>(node: &MapNode, w: &mut W) -> Result<()> {
+    match *node {
+        MapNode::Branch(bitmap, ref children) => {
+            w.write_u8(0x00)?;
+            w.write_u32::<LittleEndian>(bitmap)?;
+            for &child in children {
+                match child {
+                    MapChild::Entry(k, v) => {
+                        w.write_u8(0x00)?;
+                        write_usize(k.0, w)?;
+                        write_usize(v.0, w)?;
+                    }
+                    MapChild::Node(addr) => {
+                        w.write_u8(0x01)?;
+                        write_usize(addr.0, w)?;
+                    }
+                }
+            }
+        }
+        MapNode::Collision(ref pairs) => {
+            w.write_u8(0x01)?;
+            write_usize(pairs.len(), w)?;
+            for &(k, v) in pairs {
+                write_usize(k.0, w)?;
+                write_usize(v.0, w)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the given maps are well-formed tries with the given numbers of
+/// entries. Every subtrie must be one level deeper than its parent, and only
+/// `Collision` nodes may be deeper than the bits of the hash; otherwise,
+/// looking up a key could loop forever, or shift its hash by more than its
+/// width.
+fn check_maps(maps: &[MapNode], roots: &[(Addr<Map>, usize)]) -> Result<()> {
+    fn count(
+        maps: &[MapNode],
+        addr: Addr<Map>,
+        shift: u32,
+        memo: &mut HashMap<(usize, u32), usize>,
+    ) -> Result<usize> {
+        if let Some(&n) = memo.get(&(addr.0, shift)) {
+            return Ok(n);
+        }
+        let n = match maps[addr.0] {
+            MapNode::Branch(_, ref children) => {
+                if shift >= 64 {
+                    bail!("Invalid map in image: node {} is too deep", addr.0)
+                }
+                let mut n = 0;
+                for &child in children {
+                    n += match child {
+                        MapChild::Entry(_, _) => 1,
+                        MapChild::Node(node) => count(maps, node, shift + BITS, memo)?,
+                    };
+                }
+                n
+            }
+            MapNode::Collision(ref pairs) => {
+                if shift < 64 {
+                    bail!("Invalid map in image: node {} isn't deep enough to collide", addr.0)
+                }
+                pairs.len()
+            }
+        };
+        memo.insert((addr.0, shift), n);
+        Ok(n)
+    }
+
+    let mut memo = HashMap::new();
+    for &(addr, len) in roots {
+        let n = count(maps, addr, 0, &mut memo)?;
+        if n != len {
+            bail!("Invalid map in image: expected {} entries, but found {}", len, n)
+        }
+    }
+    Ok(())
+}
+
+/// Reads values from an image, checking their addresses against the lengths
+/// of the heaps.
+struct ImageReader<'r, R: 'r + Read> {
+    intrinsics: HashMap<Symbol, Value>,
+    r: &'r mut R,
+
+    /// The lengths of the bytes, closure, continuation, vector, string,
+    /// value, map, and bignum heaps, in that order.
+    lens: [usize; 8],
+
+    /// The string heap, which string slices must split at char boundaries.
+    strs: String,
+
+    /// The root and length of every map value read, to be checked once the
+    /// map heap has been read.
+    maps: Vec<(Addr<Map>, usize)>,
+}
+
+impl<'r, R: Read> ImageReader<'r, R> {
+    /// Reads a slice of a heap, checking that it's in bounds.
+    fn slice<T>(&mut self, heap: usize) -> Result<(Addr<T>, usize)> {
+        let addr = read_usize(self.r)?;
+        let len = read_usize(self.r)?;
+        match addr.checked_add(len) {
+            Some(end) if end <= self.lens[heap] => Ok((Addr(addr, PhantomData), len)),
+            _ => bail!("Invalid slice in image: {}+{}", addr, len),
+        }
+    }
+
+    fn map_node(&mut self) -> Result<MapNode> {
+        Ok(match self.r.read_u8()? {
+            0x00 => {
+                let bitmap = self.r.read_u32::<LittleEndian>()?;
+                let mut children = Vec::with_capacity(bitmap.count_ones() as usize);
+                for _ in 0..bitmap.count_ones() {
+                    let child = match self.r.read_u8()? {
+                        0x00 => {
+                            let k = read_addr(self.r, self.lens[5])?;
+                            let v = read_addr(self.r, self.lens[5])?;
+                            MapChild::Entry(k, v)
+                        }
+                        0x01 => MapChild::Node(read_addr(self.r, self.lens[6])?),
+                        discrim => bail!("Unknown discriminant for MapChild: {}", discrim),
+                    };
+                    children.push(child);
+                }
+                MapNode::Branch(bitmap, children)
+            }
+            0x01 => {
+                let len = read_usize(self.r)?;
+                let mut pairs = with_capacity(len);
+                for _ in 0..len {
+                    let k = read_addr(self.r, self.lens[5])?;
+                    let v = read_addr(self.r, self.lens[5])?;
+                    pairs.push((k, v));
+                }
+                MapNode::Collision(pairs)
+            }
+            discrim => bail!("Unknown discriminant for MapNode: {}", discrim),
+        })
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        Ok(match self.r.read_u8()? {
+            0x00 => Value::Byte(self.r.read_u8()?),
+            0x01 => {
+                let (addr, len) = self.slice(0)?;
+                Value::Bytes(addr, len)
+            }
+            0x02 => Value::Closure(read_addr(self.r, self.lens[1])?),
+            0x03 => {
+                let hd = read_addr(self.r, self.lens[5])?;
+                let tl = read_addr(self.r, self.lens[5])?;
+                Value::Cons(hd, tl)
+            }
+            0x04 => Value::Continuation(read_addr(self.r, self.lens[2])?),
+            0x05 => Value::Fixnum(read_usize(self.r)? as isize),
+            0x06 => {
+                let name = read_string(self.r)?;
+                match self.intrinsics.get(&Symbol::from(name.as_str())) {
+                    Some(&val) => val,
+                    None => bail!("Unknown intrinsic in image: {}", name),
+                }
+            }
+            0x07 => Value::Nil,
+            0x08 => {
+                let type_ = read_string(self.r)?;
+                let addr = read_addr(self.r, self.lens[5])?;
+                Value::Object(type_.into(), addr)
+            }
+            0x09 => {
+                let (addr, len) = self.slice(4)?;
+                let start = addr.0;
+                if !self.strs.is_char_boundary(start) || !self.strs.is_char_boundary(start + len) {
+                    bail!("Invalid string in image: {}+{} splits a character", start, len)
+                }
+                Value::String(addr, len)
+            }
+            0x0a => Value::Symbol(read_string(self.r)?.into()),
+            0x0b => {
+                let (addr, len) = self.slice(3)?;
+                Value::Vector(addr, len)
+            }
+            0x0c => Value::Ref(read_addr(self.r, self.lens[5])?),
+            0x0d => {
+                let addr = read_addr(self.r, self.lens[6])?;
+                let len = read_usize(self.r)?;
+                self.maps.push((addr, len));
+                Value::Map(addr, len)
+            }
+            0x0e => Value::Bignum(read_addr(self.r, self.lens[7])?),
+            0x0f => Value::Float(self.r.read_f64::<LittleEndian>()?),
+            discrim => bail!("Unknown discriminant for Value: {}", discrim),
+        })
+    }
+}
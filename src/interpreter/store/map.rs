@@ -0,0 +1,321 @@
+//! Persistent hash maps, stored in the map heap as hash array mapped tries.
+//!
+//! Each node is immutable once stored; inserting or removing an entry copies
+//! the nodes on the path to it, so every older version of a map stays valid
+//! and shares the rest of its nodes.
+
+use std::hash::Hasher;
+use std::marker::PhantomData;
+
+use interpreter::store::{Addr, Map};
+use interpreter::{Store, Value};
+
+/// The number of bits of the hash used at each level of the trie.
+pub const BITS: u32 = 5;
+
+/// A node of a hash array mapped trie.
+#[derive(Clone, Debug)]
+pub enum MapNode {
+    /// A node with up to 32 children, indexed by the next five bits of the
+    /// hashes of their keys. The bitmap records which children are present,
+    /// and the children are stored in order.
+    Branch(u32, Vec<MapChild>),
+
+    /// The entries whose keys have the same hash, once all of its bits have
+    /// been used.
+    Collision(Vec<(Addr<Value>, Addr<Value>)>),
+}
+
+impl MapNode {
+    /// Returns an empty node for the given depth in the trie.
+    fn empty(shift: u32) -> MapNode {
+        if shift >= 64 {
+            MapNode::Collision(Vec::new())
+        } else {
+            MapNode::Branch(0, Vec::new())
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match *self {
+            MapNode::Branch(_, ref children) => children.is_empty(),
+            MapNode::Collision(ref entries) => entries.is_empty(),
+        }
+    }
+
+    /// Returns the only entry of the node, if it has exactly one entry and no
+    /// subtries.
+    fn single_entry(&self) -> Option<MapChild> {
+        match *self {
+            MapNode::Branch(_, ref children) if children.len() == 1 => match children[0] {
+                MapChild::Entry(k, v) => Some(MapChild::Entry(k, v)),
+                MapChild::Node(_) => None,
+            },
+            MapNode::Collision(ref entries) if entries.len() == 1 => {
+                let (k, v) = entries[0];
+                Some(MapChild::Entry(k, v))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A child of a `MapNode::Branch`.
+#[derive(Clone, Copy, Debug)]
+pub enum MapChild {
+    /// A single entry, as the addresses of its key and value.
+    Entry(Addr<Value>, Addr<Value>),
+
+    /// A subtrie, for when more than one key shares the bits so far.
+    Node(Addr<Map>),
+}
+
+/// A 64-bit FNV-1a hasher for map keys. Unlike the hashers in `std`, this is
+/// guaranteed to be stable, so the layout of maps saved in an image doesn't
+/// depend on the version of `oftb` that loads them.
+pub struct KeyHasher(u64);
+
+impl Default for KeyHasher {
+    fn default() -> KeyHasher {
+        KeyHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for KeyHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Returns the bit for the given hash in the bitmap of a node at the given
+/// depth, and the index of the child for that bit.
+fn slot(bitmap: u32, hash: u64, shift: u32) -> (u32, usize) {
+    let bit = 1 << ((hash >> shift) & 0x1f);
+    (bit, (bitmap & (bit - 1)).count_ones() as usize)
+}
+
+impl<'program> Store<'program> {
+    /// Gets the entries of a map out of the map heap, sorted by key.
+    pub fn get_map(&self, addr: Addr<Map>) -> Vec<(Value, Value)> {
+        let mut entries = self.map_entries(addr);
+        entries.sort_by(|a, b| a.0.compare(b.0, self));
+        entries
+    }
+
+    /// Gets the entries of a map out of the map heap, in the order they're
+    /// stored in, which depends on the hashes of their keys.
+    pub fn map_entries(&self, addr: Addr<Map>) -> Vec<(Value, Value)> {
+        let mut entries = Vec::new();
+        let mut work = vec![addr];
+        while let Some(addr) = work.pop() {
+            match self.maps[addr.0] {
+                MapNode::Branch(_, ref children) => for &child in children {
+                    match child {
+                        MapChild::Entry(k, v) => entries.push((self.get(k), self.get(v))),
+                        MapChild::Node(node) => work.push(node),
+                    }
+                },
+                MapNode::Collision(ref pairs) => for &(k, v) in pairs {
+                    entries.push((self.get(k), self.get(v)));
+                },
+            }
+        }
+        entries
+    }
+
+    /// Looks up the value for a key in a map.
+    pub fn map_get(&self, addr: Addr<Map>, key: Value) -> Option<Value> {
+        let hash = self.hash_key(key);
+        let mut addr = addr;
+        let mut shift = 0;
+        loop {
+            match self.maps[addr.0] {
+                MapNode::Branch(bitmap, ref children) => {
+                    let (bit, i) = slot(bitmap, hash, shift);
+                    if bitmap & bit == 0 {
+                        return None;
+                    }
+                    match children[i] {
+                        MapChild::Entry(k, v) => {
+                            return if self.get(k).equals(key, self) {
+                                Some(self.get(v))
+                            } else {
+                                None
+                            };
+                        }
+                        MapChild::Node(node) => {
+                            addr = node;
+                            shift += BITS;
+                        }
+                    }
+                }
+                MapNode::Collision(ref pairs) => {
+                    return pairs
+                        .iter()
+                        .find(|&&(k, _)| self.get(k).equals(key, self))
+                        .map(|&(_, v)| self.get(v));
+                }
+            }
+        }
+    }
+
+    /// Returns a map with the given key set to the given value, and whether
+    /// the key was new. The original map is left unchanged.
+    pub fn map_insert(&mut self, addr: Addr<Map>, key: Value, val: Value) -> (Addr<Map>, bool) {
+        let hash = self.hash_key(key);
+        let address_keyed =
+            self.address_keyed_maps.contains(&addr.0) || key.hashes_by_address(self);
+        let k = self.store(key);
+        let v = self.store(val);
+        let (map, added) = self.insert_node(addr, 0, hash, k, v);
+        if address_keyed {
+            self.address_keyed_maps.insert(map.0);
+        }
+        (map, added)
+    }
+
+    /// Returns a map without the given key, or `None` if the key isn't in the
+    /// map. The original map is left unchanged.
+    pub fn map_remove(&mut self, addr: Addr<Map>, key: Value) -> Option<Addr<Map>> {
+        let hash = self.hash_key(key);
+        let map = self.remove_node(addr, 0, hash, key)?;
+        if self.address_keyed_maps.contains(&addr.0) {
+            self.address_keyed_maps.insert(map.0);
+        }
+        Some(map)
+    }
+
+    /// Stores a new, empty map into the map heap.
+    pub fn store_map(&mut self) -> Addr<Map> {
+        self.store_map_node(MapNode::empty(0))
+    }
+
+    fn hash_key(&self, key: Value) -> u64 {
+        let mut hasher = KeyHasher::default();
+        key.hash(self, &mut hasher);
+        hasher.finish()
+    }
+
+    fn insert_node(
+        &mut self,
+        addr: Addr<Map>,
+        shift: u32,
+        hash: u64,
+        k: Addr<Value>,
+        v: Addr<Value>,
+    ) -> (Addr<Map>, bool) {
+        let key = self.get(k);
+        let (node, added) = match self.maps[addr.0].clone() {
+            MapNode::Branch(bitmap, mut children) => {
+                let (bit, i) = slot(bitmap, hash, shift);
+                if bitmap & bit == 0 {
+                    children.insert(i, MapChild::Entry(k, v));
+                    (MapNode::Branch(bitmap | bit, children), true)
+                } else {
+                    let child = children[i];
+                    let (child, added) = match child {
+                        MapChild::Entry(old_k, old_v) => {
+                            let old_key = self.get(old_k);
+                            if old_key.equals(key, self) {
+                                (MapChild::Entry(old_k, v), false)
+                            } else {
+                                let shift = shift + BITS;
+                                let old_hash = self.hash_key(old_key);
+                                let node = self.store_map_node(MapNode::empty(shift));
+                                let (node, _) = self.insert_node(node, shift, old_hash, old_k, old_v);
+                                let (node, _) = self.insert_node(node, shift, hash, k, v);
+                                (MapChild::Node(node), true)
+                            }
+                        }
+                        MapChild::Node(node) => {
+                            let (node, added) = self.insert_node(node, shift + BITS, hash, k, v);
+                            (MapChild::Node(node), added)
+                        }
+                    };
+                    children[i] = child;
+                    (MapNode::Branch(bitmap, children), added)
+                }
+            }
+            MapNode::Collision(mut pairs) => {
+                let found = pairs
+                    .iter()
+                    .position(|&(old_k, _)| self.get(old_k).equals(key, self));
+                match found {
+                    Some(i) => {
+                        pairs[i].1 = v;
+                        (MapNode::Collision(pairs), false)
+                    }
+                    None => {
+                        pairs.push((k, v));
+                        (MapNode::Collision(pairs), true)
+                    }
+                }
+            }
+        };
+        (self.store_map_node(node), added)
+    }
+
+    fn remove_node(
+        &mut self,
+        addr: Addr<Map>,
+        shift: u32,
+        hash: u64,
+        key: Value,
+    ) -> Option<Addr<Map>> {
+        let node = match self.maps[addr.0].clone() {
+            MapNode::Branch(bitmap, mut children) => {
+                let (bit, i) = slot(bitmap, hash, shift);
+                if bitmap & bit == 0 {
+                    return None;
+                }
+                let child = children[i];
+                match child {
+                    MapChild::Entry(k, _) => {
+                        if !self.get(k).equals(key, self) {
+                            return None;
+                        }
+                        children.remove(i);
+                        MapNode::Branch(bitmap & !bit, children)
+                    }
+                    MapChild::Node(node) => {
+                        // A subtrie left with a single entry is replaced by
+                        // that entry, so the trie doesn't keep subtries for
+                        // keys that have been removed.
+                        let node = self.remove_node(node, shift + BITS, hash, key)?;
+                        if self.maps[node.0].is_empty() {
+                            children.remove(i);
+                            MapNode::Branch(bitmap & !bit, children)
+                        } else {
+                            children[i] = self.maps[node.0]
+                                .single_entry()
+                                .unwrap_or(MapChild::Node(node));
+                            MapNode::Branch(bitmap, children)
+                        }
+                    }
+                }
+            }
+            MapNode::Collision(mut pairs) => {
+                let i = pairs
+                    .iter()
+                    .position(|&(k, _)| self.get(k).equals(key, self))?;
+                pairs.remove(i);
+                MapNode::Collision(pairs)
+            }
+        };
+        Some(self.store_map_node(node))
+    }
+
+    fn store_map_node(&mut self, node: MapNode) -> Addr<Map> {
+        let n = self.maps.len();
+        self.maps.push(node);
+        Addr(n, PhantomData)
+    }
+}
+
@@ -1,49 +1,117 @@
+mod chars;
+mod gc;
+mod handle;
+mod image;
+mod map;
+
+use std::collections::HashSet;
 use std::fmt::{Debug, Formatter, Result as FmtResult};
 use std::marker::PhantomData;
+use std::mem::size_of;
 
+use num::{BigInt, ToPrimitive};
 use symbol::Symbol;
 
 use flatanf::Expr;
-use interpreter::{Env, Value};
+use interpreter::store::chars::CharIndex;
+pub use interpreter::store::handle::HandleState;
+pub use interpreter::store::map::KeyHasher;
+use interpreter::store::map::MapNode;
+use interpreter::{Captured, Clock, Env, Rng, Stdin, Value};
 use Literal;
 
+/// A phantom type for `Addr<Bignum>`.
+pub enum Bignum {}
+
 /// A phantom type for `Addr<Bytes>`.
 pub enum Bytes {}
 
 /// A phantom type for `Addr<Closure>`.
 pub enum Closure {}
 
+/// A phantom type for `Addr<Continuation>`.
+pub enum Continuation {}
+
+/// A phantom type for `Addr<Handle>`.
+pub enum Handle {}
+
+/// A phantom type for `Addr<Map>`.
+pub enum Map {}
+
 /// A phantom type for `Addr<Vector>`.
 pub enum Vector {}
 
 /// The type of the store.
 #[derive(Debug)]
 pub struct Store<'program> {
+    bigs: Vec<BigInt>,
     bytes: Vec<u8>,
     clos: Vec<(usize, &'program Expr, Option<Symbol>, Env)>,
+    conts: Vec<Captured<'program>>,
+    handles: Vec<HandleState>,
+    maps: Vec<MapNode>,
     strs: String,
     vecs: Vec<Addr<Value>>,
 
     vals: Vec<Value>,
+
+    /// The roots of the maps with keys that are hashed by their addresses,
+    /// which need to be rebuilt when the garbage collector moves their keys.
+    address_keyed_maps: HashSet<usize>,
+
+    char_index: CharIndex,
+
+    /// The clock used by the time intrinsics.
+    clock: Clock,
+
+    /// The name of the running program, as given to the `process:program_name`
+    /// intrinsic.
+    program_name: Option<String>,
+
+    /// The random number generator used by the random intrinsics.
+    rng: Rng,
+
+    /// The input read by the io intrinsics.
+    stdin: Stdin,
 }
 
 impl<'program> Store<'program> {
     /// Creates a new, empty heap.
     pub fn new() -> Store<'program> {
         Store {
+            bigs: Vec::new(),
             bytes: Vec::new(),
             clos: Vec::new(),
+            conts: Vec::new(),
+            handles: Vec::new(),
+            maps: Vec::new(),
             strs: String::new(),
             vecs: Vec::new(),
             vals: vec![Value::Nil],
+            address_keyed_maps: HashSet::new(),
+            char_index: CharIndex::default(),
+            clock: Clock::default(),
+            program_name: None,
+            rng: Rng::default(),
+            stdin: Stdin::default(),
         }
     }
 
+    /// Returns the clock used by the time intrinsics.
+    pub fn clock(&mut self) -> &mut Clock {
+        &mut self.clock
+    }
+
     /// Gets a value out of the value heap.
     pub fn get(&self, addr: Addr<Value>) -> Value {
         self.vals[addr.0]
     }
 
+    /// Gets a bignum out of the bignum heap.
+    pub fn get_bignum(&self, addr: Addr<Bignum>) -> &BigInt {
+        &self.bigs[addr.0]
+    }
+
     /// Gets a bytes value out of the bytes heap.
     pub fn get_bytes(&self, addr: Addr<Bytes>, len: usize) -> &[u8] {
         let start = addr.0;
@@ -57,6 +125,26 @@ impl<'program> Store<'program> {
         (addr, body, name, env.clone())
     }
 
+    /// Gets a continuation out of the continuation heap.
+    pub fn get_continuation(&self, addr: Addr<Continuation>) -> &Captured<'program> {
+        &self.conts[addr.0]
+    }
+
+    /// Returns the name of the running program, if one was set.
+    pub fn program_name(&self) -> Option<&str> {
+        self.program_name.as_ref().map(|s| &s[..])
+    }
+
+    /// Returns the random number generator used by the random intrinsics.
+    pub fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    /// Returns the input read by the io intrinsics.
+    pub fn stdin(&mut self) -> &mut Stdin {
+        &mut self.stdin
+    }
+
     /// Gets a string out of the string heap.
     pub fn get_str(&self, addr: Addr<String>, len: usize) -> &str {
         let start = addr.0;
@@ -89,6 +177,61 @@ impl<'program> Store<'program> {
         self.clos[addr.0].2 = Some(name);
     }
 
+    /// Replaces the value in a cell of the value heap. The cell must not be
+    /// shared (e.g. it should come from `store_ref`), or every value that
+    /// points to it will see the change.
+    pub fn mutate(&mut self, addr: Addr<Value>, value: Value) {
+        self.vals[addr.0] = value;
+    }
+
+    /// Replaces a single byte in the bytes heap.
+    pub fn mutate_bytes(&mut self, addr: Addr<Bytes>, byte: u8) {
+        self.bytes[addr.0] = byte;
+    }
+
+    /// Replaces a single element of the vector heap with a newly stored
+    /// value. The old value's cell is left alone, since it may be shared with
+    /// other values (e.g. the list a vector was made from).
+    pub fn mutate_vec_val(&mut self, addr: Addr<Vector>, value: Value) {
+        let cell = self.store(value);
+        self.vecs[addr.0] = cell;
+    }
+
+    /// Replaces the clock used by the time intrinsics, e.g. with a fake clock.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    /// Sets the name of the running program.
+    pub fn set_program_name(&mut self, name: Option<String>) {
+        self.program_name = name;
+    }
+
+    /// Replaces the random number generator used by the random intrinsics,
+    /// e.g. with one created from a known seed.
+    pub fn set_rng(&mut self, rng: Rng) {
+        self.rng = rng;
+    }
+
+    /// Replaces the input read by the io intrinsics, e.g. with fake input.
+    pub fn set_stdin(&mut self, stdin: Stdin) {
+        self.stdin = stdin;
+    }
+
+    /// Returns the approximate size of the heaps, in bytes.
+    pub fn size(&self) -> usize {
+        self.bigs.len() * size_of::<BigInt>()
+            + self.bytes.len()
+            + self.clos.len() * size_of::<(usize, &'program Expr, Option<Symbol>, Env)>()
+            + self.conts.len() * size_of::<Captured<'program>>()
+            + self.handles.len() * size_of::<HandleState>()
+            + self.maps.len() * size_of::<MapNode>()
+            + self.strs.len()
+            + self.vecs.len() * size_of::<Addr<Value>>()
+            + self.vals.len() * size_of::<Value>()
+            + self.char_index.size()
+    }
+
     /// Stores a value into the value heap.
     pub fn store(&mut self, value: Value) -> Addr<Value> {
         let n = if let Value::Nil = value {
@@ -101,6 +244,28 @@ impl<'program> Store<'program> {
         Addr(n, PhantomData)
     }
 
+    /// Stores an integer, as a fixnum if it fits in one, or into the bignum
+    /// heap otherwise. This keeps bignums normalized, so a bignum is never
+    /// equal to a fixnum.
+    pub fn store_bignum(&mut self, n: BigInt) -> Value {
+        if let Some(n) = n.to_isize() {
+            Value::Fixnum(n)
+        } else {
+            let addr = self.bigs.len();
+            self.bigs.push(n);
+            Value::Bignum(Addr(addr, PhantomData))
+        }
+    }
+
+    /// Stores a value into a new cell of the value heap, for a reference.
+    /// Unlike `store`, this never reuses a cell, even for nil, so the cell
+    /// may be mutated.
+    pub fn store_ref(&mut self, value: Value) -> Addr<Value> {
+        let n = self.vals.len();
+        self.vals.push(value);
+        Addr(n, PhantomData)
+    }
+
     /// Stores a value into the bytes heap.
     pub fn store_bytes(&mut self, bs: &[u8]) -> (Addr<Bytes>, usize) {
         let n = self.bytes.len();
@@ -122,9 +287,17 @@ impl<'program> Store<'program> {
         Addr(n, PhantomData)
     }
 
+    /// Stores a value into the continuation heap.
+    pub fn store_continuation(&mut self, cont: Captured<'program>) -> Addr<Continuation> {
+        let n = self.conts.len();
+        self.conts.push(cont);
+        Addr(n, PhantomData)
+    }
+
     /// Builds a literal onto the value heap.
     pub fn store_literal(&mut self, lit: &Literal) -> Value {
         match *lit {
+            Literal::Bignum(ref n) => self.store_bignum(n.clone()),
             Literal::Byte(n) => Value::Byte(n),
             Literal::Bytes(ref bs) => {
                 let (a, l) = self.store_bytes(bs);
@@ -136,6 +309,7 @@ impl<'program> Store<'program> {
                 Value::Cons(self.store(h), self.store(t))
             }
             Literal::Fixnum(n) => Value::Fixnum(n),
+            Literal::Float(n) => Value::Float(n),
             Literal::Nil => Value::Nil,
             Literal::String(ref s) => {
                 let (a, l) = self.store_str(s);
@@ -0,0 +1,1700 @@
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::hash::Hasher;
+use std::fs::{remove_dir, remove_file, File};
+use std::process;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use num::BigInt;
+use podio::{LittleEndian, ReadPodExt, WritePodExt};
+
+use symbol::Symbol;
+
+use flatanf::{AExpr, CExpr, Expr, Program};
+use interpreter::{
+    BranchCoverage, Clock, Control, Coverage, Evaluation, HandleState, Interpreter, Pause, Profile,
+    RuntimeError, State, Stdin, Store, Value,
+};
+use intrinsics::Intrinsics;
+use Literal;
+
+fn call(func: &str, args: Vec<AExpr>) -> Expr {
+    Expr::CExpr(CExpr::Call(AExpr::Global(func.into()), args))
+}
+
+fn lit(lit: Literal) -> AExpr {
+    AExpr::Literal(lit)
+}
+
+#[test]
+fn gc_frees_garbage() {
+    let mut store = Store::new();
+    let mut globals = HashMap::new();
+
+    let list = store.store_literal(&Literal::list(vec![
+        Literal::Fixnum(1),
+        Literal::String("two".to_string()),
+        Literal::Vector(vec![Literal::Bytes(vec![3])]),
+    ]));
+    for i in 0..100 {
+        store.store_literal(&Literal::String(format!("garbage {}", i)));
+    }
+    globals.insert("list".into(), list);
+
+    let before = store.size();
+    let mut state = State::Halted(Value::Nil);
+    store.collect(&mut globals, &mut state);
+    assert!(store.size() < before);
+    assert_eq!(
+        globals[&Symbol::from("list")].display(&store, false).to_string(),
+        r#"(1 "two" [b"\x03"])"#
+    );
+}
+
+#[test]
+fn gc_preserves_slices() {
+    let mut store = Store::new();
+    let mut globals = HashMap::new();
+
+    store.store_literal(&Literal::String("garbage".to_string()));
+    let (a, l) = store.store_str("hello, world");
+    let a: usize = a.into();
+    globals.insert("hello".into(), Value::String(a.into(), 5));
+    globals.insert("world".into(), Value::String((a + 7).into(), l - 7));
+    globals.insert("lo, wo".into(), Value::String((a + 3).into(), 6));
+
+    let mut state = State::Halted(Value::Nil);
+    store.collect(&mut globals, &mut state);
+    for (name, val) in globals {
+        assert_eq!(val.display(&store, true).to_string(), name.as_str());
+    }
+}
+
+#[test]
+fn gc_relocates_running_state() {
+    // let $0 = (list "a" "b") in
+    // let $0 = (cons $0 $0) in
+    // (vector/slice 1 3 [$1 $0 $1])
+    let expr = Expr::Let(
+        Box::new(call(
+            "intrinsics:list",
+            vec![
+                lit(Literal::String("a".to_string())),
+                lit(Literal::String("b".to_string())),
+            ],
+        )),
+        Box::new(Expr::Let(
+            Box::new(call(
+                "intrinsics:cons",
+                vec![AExpr::Local(0), AExpr::Local(0)],
+            )),
+            Box::new(call(
+                "intrinsics/vector:slice",
+                vec![
+                    lit(Literal::Fixnum(1)),
+                    lit(Literal::Fixnum(3)),
+                    AExpr::Vector(vec![AExpr::Local(1), AExpr::Local(0), AExpr::Local(1)]),
+                ],
+            )),
+        )),
+    );
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    interpreter.set_gc_threshold(Some(0));
+    let val = interpreter.eval(&expr).unwrap();
+    assert_eq!(
+        val.display(&interpreter.store, false).to_string(),
+        r#"[(("a" "b") "a" "b") ("a" "b")]"#
+    );
+}
+
+#[test]
+fn intrinsic_type_error() {
+    let expr = call("intrinsics:car", vec![lit(Literal::Fixnum(1))]);
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    match interpreter.eval(&expr) {
+        Err(RuntimeError::TypeError(name, expected, found)) => {
+            assert_eq!(name, "intrinsics:car");
+            assert_eq!(expected, "a cons");
+            assert_eq!(found, "1");
+        }
+        r => panic!("Expected a type error, got {:?}", r),
+    }
+}
+
+#[test]
+fn intrinsic_argn_mismatch() {
+    let expr = call("intrinsics:cons", vec![lit(Literal::Nil)]);
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    match interpreter.eval(&expr) {
+        Err(RuntimeError::ArgnMismatch(name, 2, 1)) => assert_eq!(name, "intrinsics:cons"),
+        r => panic!("Expected an argn mismatch, got {:?}", r),
+    }
+}
+
+#[test]
+fn unknown_global() {
+    let expr = call("foo:bar", vec![]);
+
+    let mut interpreter = Interpreter::new();
+    match interpreter.eval(&expr) {
+        Err(RuntimeError::UnknownGlobal(name)) => assert_eq!(name.as_str(), "foo:bar"),
+        r => panic!("Expected an unknown global, got {:?}", r),
+    }
+}
+
+#[test]
+fn stack_trace() {
+    // let $0 = (fn test:f (x) (car x)) in
+    // let $0 = (fn test:g (x) (let $0 = ($1 x) in $0)) in
+    // let $0 = (fn test:h (x) ($1 x)) in
+    // ($0 1)
+    let f = AExpr::Lambda(
+        Some("test:f".into()),
+        1,
+        Box::new(call("intrinsics:car", vec![AExpr::Local(0)])),
+    );
+    let g = AExpr::Lambda(
+        Some("test:g".into()),
+        1,
+        Box::new(Expr::Let(
+            Box::new(Expr::CExpr(CExpr::Call(
+                AExpr::Local(1),
+                vec![AExpr::Local(0)],
+            ))),
+            Box::new(Expr::AExpr(AExpr::Local(0))),
+        )),
+    );
+    let h = AExpr::Lambda(
+        Some("test:h".into()),
+        1,
+        Box::new(Expr::CExpr(CExpr::Call(
+            AExpr::Local(1),
+            vec![AExpr::Local(0)],
+        ))),
+    );
+    let expr = Expr::Let(
+        Box::new(Expr::AExpr(f)),
+        Box::new(Expr::Let(
+            Box::new(Expr::AExpr(g)),
+            Box::new(Expr::Let(
+                Box::new(Expr::AExpr(h)),
+                Box::new(Expr::CExpr(CExpr::Call(
+                    AExpr::Local(0),
+                    vec![lit(Literal::Fixnum(1))],
+                ))),
+            )),
+        )),
+    );
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    assert!(interpreter.eval(&expr).is_err());
+
+    // test:h tail-calls test:g, so it shouldn't appear.
+    let trace = interpreter.stack_trace().unwrap();
+    assert_eq!(trace.to_string(), "  at test:f\n  at test:g");
+}
+
+#[test]
+fn call_cc() {
+    // let $0 = (call/cc (fn (k) (let $0 = ($0 5) in (+ 10 $0)))) in
+    // (+ 1 $0)
+    let f = AExpr::Lambda(
+        None,
+        1,
+        Box::new(Expr::Let(
+            Box::new(Expr::CExpr(CExpr::Call(
+                AExpr::Local(0),
+                vec![lit(Literal::Fixnum(5))],
+            ))),
+            Box::new(call(
+                "intrinsics/math:add",
+                vec![lit(Literal::Fixnum(10)), AExpr::Local(0)],
+            )),
+        )),
+    );
+    let expr = Expr::Let(
+        Box::new(call("intrinsics:call_cc", vec![f])),
+        Box::new(call(
+            "intrinsics/math:add",
+            vec![lit(Literal::Fixnum(1)), AExpr::Local(0)],
+        )),
+    );
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    assert_eq!(interpreter.eval(&expr).unwrap(), Value::Fixnum(6));
+}
+
+#[test]
+fn call_ec_out_of_extent() {
+    // let $0 = (call/ec (fn (k) k)) in
+    // ($0 1)
+    let f = AExpr::Lambda(None, 1, Box::new(Expr::AExpr(AExpr::Local(0))));
+    let expr = Expr::Let(
+        Box::new(call("intrinsics:call_ec", vec![f])),
+        Box::new(Expr::CExpr(CExpr::Call(
+            AExpr::Local(0),
+            vec![lit(Literal::Fixnum(1))],
+        ))),
+    );
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    match interpreter.eval(&expr) {
+        Err(RuntimeError::EscapeOutOfExtent) => {}
+        r => panic!("Expected an out-of-extent escape, got {:?}", r),
+    }
+}
+
+#[test]
+fn handler_catches_runtime_error() {
+    // (with-handler (fn (e) e) (fn () (car 1)))
+    let handler = AExpr::Lambda(None, 1, Box::new(Expr::AExpr(AExpr::Local(0))));
+    let body = AExpr::Lambda(
+        None,
+        0,
+        Box::new(call("intrinsics:car", vec![lit(Literal::Fixnum(1))])),
+    );
+    let expr = call("intrinsics:with_handler", vec![handler, body]);
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    let val = interpreter.eval(&expr).unwrap();
+    assert_eq!(
+        val.display(&interpreter.store, false).to_string(),
+        r#"(type-error "Type error in `intrinsics:car': expected a cons, found 1")"#
+    );
+}
+
+#[test]
+fn raise_unwinds_to_nearest_handler() {
+    // (with-handler (fn (e) (cons 'outer e))
+    //   (fn () (let $0 = (with-handler (fn (e) (cons 'inner e))
+    //                      (fn () (raise 1)))
+    //           in (raise $0))))
+    let handler = |name: &str| {
+        AExpr::Lambda(
+            None,
+            1,
+            Box::new(call(
+                "intrinsics:cons",
+                vec![lit(Literal::Symbol(name.into())), AExpr::Local(0)],
+            )),
+        )
+    };
+    let inner = call(
+        "intrinsics:with_handler",
+        vec![
+            handler("inner"),
+            AExpr::Lambda(
+                None,
+                0,
+                Box::new(call("intrinsics:raise", vec![lit(Literal::Fixnum(1))])),
+            ),
+        ],
+    );
+    let body = AExpr::Lambda(
+        None,
+        0,
+        Box::new(Expr::Let(
+            Box::new(inner),
+            Box::new(call("intrinsics:raise", vec![AExpr::Local(0)])),
+        )),
+    );
+    let expr = call("intrinsics:with_handler", vec![handler("outer"), body]);
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    let val = interpreter.eval(&expr).unwrap();
+    assert_eq!(
+        val.display(&interpreter.store, false).to_string(),
+        "(outer inner | 1)"
+    );
+}
+
+#[test]
+fn uncaught_raise() {
+    let expr = call("intrinsics:raise", vec![lit(Literal::Symbol("oops".into()))]);
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    match interpreter.eval(&expr) {
+        Err(RuntimeError::Uncaught(val)) => assert_eq!(val, "oops"),
+        r => panic!("Expected an uncaught error, got {:?}", r),
+    }
+}
+
+#[test]
+fn fuel_pauses_and_resumes() {
+    // let $0 = (+ 1 2) in
+    // let $0 = (+ $0 3) in
+    // (+ $0 4)
+    let expr = Expr::Let(
+        Box::new(call(
+            "intrinsics/math:add",
+            vec![lit(Literal::Fixnum(1)), lit(Literal::Fixnum(2))],
+        )),
+        Box::new(Expr::Let(
+            Box::new(call(
+                "intrinsics/math:add",
+                vec![AExpr::Local(0), lit(Literal::Fixnum(3))],
+            )),
+            Box::new(call(
+                "intrinsics/math:add",
+                vec![AExpr::Local(0), lit(Literal::Fixnum(4))],
+            )),
+        )),
+    );
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    assert_eq!(
+        interpreter.eval_with_fuel(&expr, 1).unwrap(),
+        Evaluation::Paused(Pause::OutOfFuel)
+    );
+    assert_eq!(interpreter.steps(), 1);
+    assert_eq!(
+        interpreter.resume(100).unwrap(),
+        Evaluation::Done(Value::Fixnum(10))
+    );
+}
+
+#[test]
+fn interrupt_pauses_evaluation() {
+    let expr = call("intrinsics:list", vec![]);
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    interpreter.interrupt_flag().store(true, Ordering::SeqCst);
+    assert_eq!(
+        interpreter.eval_with_fuel(&expr, 100).unwrap(),
+        Evaluation::Paused(Pause::Interrupted)
+    );
+    assert_eq!(interpreter.steps(), 0);
+    assert_eq!(
+        interpreter.resume(100).unwrap(),
+        Evaluation::Done(Value::Nil)
+    );
+}
+
+#[test]
+fn profile_attributes_steps_to_frames() {
+    // let $0 = (fn test:f (x) (cons x x)) in
+    // ($0 1)
+    let f = AExpr::Lambda(
+        Some("test:f".into()),
+        1,
+        Box::new(call(
+            "intrinsics:cons",
+            vec![AExpr::Local(0), AExpr::Local(0)],
+        )),
+    );
+    let expr = Expr::Let(
+        Box::new(Expr::AExpr(f)),
+        Box::new(Expr::CExpr(CExpr::Call(
+            AExpr::Local(0),
+            vec![lit(Literal::Fixnum(1))],
+        ))),
+    );
+
+    let mut profile = Profile::new();
+    profile.set_root(Some("test".into()));
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    interpreter.set_profile(Some(profile));
+    interpreter.eval(&expr).unwrap();
+    let profile = interpreter.take_profile().unwrap();
+
+    let mut folded = Vec::new();
+    profile.write_folded(&mut folded).unwrap();
+    assert_eq!(String::from_utf8(folded).unwrap(), "test 3\ntest;test:f 1\n");
+
+    let funcs = profile.functions();
+    assert_eq!(funcs.len(), 2);
+    assert_eq!(funcs[0].name.as_str(), "test");
+    assert_eq!(funcs[0].total_cost.steps, 4);
+    assert_eq!(funcs[1].name.as_str(), "test:f");
+    assert_eq!(funcs[1].self_cost.steps, 1);
+    assert!(funcs[1].self_cost.bytes > 0);
+}
+
+#[test]
+fn coverage_records_branches_and_functions() {
+    // test:f = (fn test:f (x) (if x 1 2))
+    // test:g = (fn (x) x)
+    let program = Program {
+        intrinsics: HashSet::new(),
+        decls: vec![
+            (
+                "test:f".into(),
+                Expr::AExpr(AExpr::Lambda(
+                    Some("test:f".into()),
+                    1,
+                    Box::new(Expr::CExpr(CExpr::If(
+                        AExpr::Local(0),
+                        Box::new(Expr::AExpr(lit(Literal::Fixnum(1)))),
+                        Box::new(Expr::AExpr(lit(Literal::Fixnum(2)))),
+                    ))),
+                )),
+            ),
+            (
+                "test:g".into(),
+                Expr::AExpr(AExpr::Lambda(
+                    None,
+                    1,
+                    Box::new(Expr::AExpr(AExpr::Local(0))),
+                )),
+            ),
+        ],
+    };
+    let main = call("test:f", vec![lit(Literal::Symbol("true".into()))]);
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_coverage(Some(Coverage::new()));
+    for &(name, ref expr) in &program.decls {
+        let val = interpreter.eval(expr).unwrap();
+        interpreter.globals.insert(name, val);
+    }
+    interpreter.eval(&main).unwrap();
+    let report = interpreter.take_coverage().unwrap().report(&program);
+
+    assert_eq!(report.decls[0].functions, vec![("test:f".to_string(), 1)]);
+    assert_eq!(
+        report.decls[0].branches,
+        vec![BranchCoverage {
+            hits: 1,
+            then_hits: 1,
+            else_hits: 0,
+        }]
+    );
+    assert_eq!(report.decls[1].functions, vec![("test:g/lambda0".to_string(), 0)]);
+
+    let mut lcov = Vec::new();
+    report.write_lcov(&mut lcov).unwrap();
+    assert_eq!(
+        String::from_utf8(lcov).unwrap(),
+        "TN:\nSF:test\nFN:1,test:f\nFN:2,test:g/lambda0\nFNDA:1,test:f\n\
+         FNDA:0,test:g/lambda0\nFNF:2\nFNH:1\nBRDA:1,0,0,1\nBRDA:1,0,1,0\n\
+         BRF:2\nBRH:1\nDA:1,1\nDA:2,1\nLF:2\nLH:2\nend_of_record\n"
+    );
+}
+
+#[test]
+fn image_round_trip() {
+    // test:xs = (list "a" [1 2])
+    // test:f = let $0 = (cons 'x test:xs) in (fn test:f () $0)
+    let program = Program {
+        intrinsics: HashSet::new(),
+        decls: vec![
+            (
+                "test:xs".into(),
+                call(
+                    "intrinsics:list",
+                    vec![
+                        lit(Literal::String("a".to_string())),
+                        AExpr::Vector(vec![lit(Literal::Fixnum(1)), lit(Literal::Fixnum(2))]),
+                    ],
+                ),
+            ),
+            (
+                "test:f".into(),
+                Expr::Let(
+                    Box::new(call(
+                        "intrinsics:cons",
+                        vec![
+                            lit(Literal::Symbol("x".into())),
+                            AExpr::Global("test:xs".into()),
+                        ],
+                    )),
+                    Box::new(Expr::AExpr(AExpr::Lambda(
+                        Some("test:f".into()),
+                        0,
+                        Box::new(Expr::AExpr(AExpr::Local(0))),
+                    ))),
+                ),
+            ),
+        ],
+    };
+    let main = call("test:f", vec![]);
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    for &(name, ref expr) in &program.decls {
+        let val = interpreter.eval(expr).unwrap();
+        interpreter.globals.insert(name, val);
+    }
+    let mut image = Vec::new();
+    interpreter
+        .save_image::<Intrinsics, _>(&program, &mut image)
+        .unwrap();
+
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .load_image::<Intrinsics, _>(&program, &mut &image[..])
+        .unwrap();
+    let val = interpreter.eval(&main).unwrap();
+    assert_eq!(
+        val.display(&interpreter.store, false).to_string(),
+        r#"(x "a" [1 2])"#
+    );
+
+    let other = Program {
+        intrinsics: HashSet::new(),
+        decls: Vec::new(),
+    };
+    assert!(
+        Interpreter::new()
+            .load_image::<Intrinsics, _>(&other, &mut &image[..])
+            .is_err()
+    );
+}
+
+#[test]
+fn corrupted_images_fail_to_load() {
+    let program = Program {
+        intrinsics: HashSet::new(),
+        decls: Vec::new(),
+    };
+    let load = |image: &[u8]| Store::load_image::<Intrinsics, _>(&program, &mut &image[..]);
+
+    let mut store = Store::new();
+    let mut globals = HashMap::new();
+    let list = store.store_literal(&Literal::list(vec![
+        Literal::String("caf\u{e9}".to_string()),
+        Literal::Vector(vec![Literal::Bytes(vec![1, 2])]),
+    ]));
+    globals.insert("list".into(), list);
+    let mut image = Vec::new();
+    store
+        .save_image::<Intrinsics, _>(&globals, &program, &mut image)
+        .unwrap();
+    assert!(load(&image).is_ok());
+
+    // Every truncation fails, and no corruption panics or aborts.
+    for len in 0..image.len() {
+        assert!(load(&image[..len]).is_err());
+    }
+    for i in 0..image.len() {
+        let mut corrupted = image.clone();
+        corrupted[i] ^= 0xff;
+        let _ = load(&corrupted);
+    }
+
+    // The heap lengths follow the signature, version, and fingerprint.
+    let mut corrupted = image.clone();
+    for b in &mut corrupted[13..21] {
+        *b = 0xff;
+    }
+    corrupted[20] = 0x7f;
+    assert!(load(&corrupted).is_err());
+
+    // A string that splits the two bytes of \u{e9}.
+    let mut store = Store::new();
+    let (a, _) = store.store_str("caf\u{e9}");
+    let mut globals = HashMap::new();
+    globals.insert("s".into(), Value::String(a, 4));
+    let mut image = Vec::new();
+    store
+        .save_image::<Intrinsics, _>(&globals, &program, &mut image)
+        .unwrap();
+    assert!(load(&image).is_err());
+
+    // A map of two keys that share the first bits of their hashes, so its
+    // root is the last map node, with a subtrie as its only child. The image
+    // ends with the map heap, the empty tables of address-keyed maps and
+    // environments, and then the global: a count, its name, and the value's
+    // tag, root, and length.
+    let (image, maps) = (1..)
+        .map(|i| {
+            let mut store = Store::new();
+            let map = store.store_map();
+            let (map, _) = store.map_insert(map, Value::Fixnum(0), Value::Nil);
+            let (map, _) = store.map_insert(map, Value::Fixnum(i), Value::Nil);
+            let mut globals = HashMap::new();
+            globals.insert("m".into(), Value::Map(map, 2));
+            let mut image = Vec::new();
+            store
+                .save_image::<Intrinsics, _>(&globals, &program, &mut image)
+                .unwrap();
+            let maps = (&image[61..69]).read_u64::<LittleEndian>().unwrap();
+            (image, maps)
+        })
+        .find(|&(_, maps)| maps > 3)
+        .unwrap();
+    assert!(load(&image).is_ok());
+    let end = image.len();
+    let child = end - 58;
+    assert_eq!(image[child - 1], 0x01);
+
+    // The subtrie is replaced by the root itself.
+    let mut corrupted = image.clone();
+    (&mut corrupted[child..child + 8])
+        .write_u64::<LittleEndian>(maps - 1)
+        .unwrap();
+    assert!(load(&corrupted).is_err());
+
+    // The map claims to have three entries.
+    let mut corrupted = image.clone();
+    corrupted[end - 8] = 3;
+    assert!(load(&corrupted).is_err());
+}
+
+#[test]
+fn refs_are_mutable_cells() {
+    // let $0 = (ref/new nil) in
+    // (ref/set $0 5);
+    // let $0 = (ref/get $0) in
+    // (list $1 $0 nil)
+    let expr = Expr::Let(
+        Box::new(call("intrinsics/ref:new", vec![lit(Literal::Nil)])),
+        Box::new(Expr::Seq(
+            Box::new(call(
+                "intrinsics/ref:set",
+                vec![AExpr::Local(0), lit(Literal::Fixnum(5))],
+            )),
+            Box::new(Expr::Let(
+                Box::new(call("intrinsics/ref:get", vec![AExpr::Local(0)])),
+                Box::new(call(
+                    "intrinsics:list",
+                    vec![AExpr::Local(1), AExpr::Local(0), lit(Literal::Nil)],
+                )),
+            )),
+        )),
+    );
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    interpreter.set_gc_threshold(Some(0));
+    let val = interpreter.eval(&expr).unwrap();
+    assert_eq!(
+        val.display(&interpreter.store, false).to_string(),
+        "(<<ref 5>> 5 ())"
+    );
+}
+
+#[test]
+fn cyclic_values_display() {
+    let mut store = Store::new();
+    let r = store.store_ref(Value::Nil);
+    store.mutate(r, Value::Ref(r));
+    assert_eq!(
+        Value::Ref(r).display(&store, false).to_string(),
+        "<<ref <<cycle>>>>"
+    );
+
+    let one = store.store(Value::Fixnum(1));
+    let inner = store.store_vec(&[one, one]);
+    let inner = store.store(Value::Vector(inner.0, inner.1));
+    let (a, l) = store.store_vec(&[inner, inner, one]);
+    store.mutate_vec_val(a, Value::Vector(a, l));
+    assert_eq!(
+        Value::Vector(a, l).display(&store, false).to_string(),
+        "[<<cycle>> [1 1] 1]"
+    );
+}
+
+#[test]
+fn cyclic_vectors_compare_and_hash() {
+    let mut store = Store::new();
+    let cyclic = |store: &mut Store, n| {
+        let n = store.store(Value::Fixnum(n));
+        let (a, l) = store.store_vec(&[n, n]);
+        store.mutate_vec_val(a, Value::Vector(a, l));
+        Value::Vector(a, l)
+    };
+    let x = cyclic(&mut store, 1);
+    let y = cyclic(&mut store, 1);
+    let z = cyclic(&mut store, 2);
+
+    assert!(x.equals(y, &store));
+    assert!(!x.equals(z, &store));
+    assert_eq!(x.compare(y, &store), CmpOrdering::Equal);
+    assert_eq!(x.compare(z, &store), CmpOrdering::Less);
+    assert_eq!(z.compare(x, &store), CmpOrdering::Greater);
+
+    let map = store.store_map();
+    let (map, _) = store.map_insert(map, x, Value::Fixnum(1));
+    let (map, _) = store.map_insert(map, z, Value::Fixnum(2));
+    assert_eq!(store.map_get(map, y), Some(Value::Fixnum(1)));
+    assert_eq!(store.map_get(map, z), Some(Value::Fixnum(2)));
+    assert_eq!(
+        Value::Map(map, 2).display(&store, false).to_string(),
+        "{[<<cycle>> 1] 1, [<<cycle>> 2] 2}"
+    );
+}
+
+#[test]
+fn vector_and_bytes_set_are_visible_through_slices() {
+    // let $0 = (list 1 2 3) in
+    // let $0 = (list->vector $0) in
+    // let $0 = (vector/slice 1 3 $0) in
+    // let $0 = (bytes/slice 1 3 b"abc") in
+    // (vector/set 0 'x $1);
+    // (bytes/set 0 0x7a $0);
+    // (list $3 $2 $1 $0)
+    let expr = Expr::Let(
+        Box::new(call(
+            "intrinsics:list",
+            vec![
+                lit(Literal::Fixnum(1)),
+                lit(Literal::Fixnum(2)),
+                lit(Literal::Fixnum(3)),
+            ],
+        )),
+        Box::new(Expr::Let(
+            Box::new(call("intrinsics/convert:list_to_vector", vec![AExpr::Local(0)])),
+            Box::new(Expr::Let(
+                Box::new(call(
+                    "intrinsics/vector:slice",
+                    vec![lit(Literal::Fixnum(1)), lit(Literal::Fixnum(3)), AExpr::Local(0)],
+                )),
+                Box::new(Expr::Let(
+                    Box::new(call(
+                        "intrinsics/bytes:slice",
+                        vec![
+                            lit(Literal::Fixnum(1)),
+                            lit(Literal::Fixnum(3)),
+                            lit(Literal::Bytes(b"abc".to_vec())),
+                        ],
+                    )),
+                    Box::new(Expr::Seq(
+                        Box::new(call(
+                            "intrinsics/vector:set",
+                            vec![
+                                lit(Literal::Fixnum(0)),
+                                lit(Literal::Symbol("x".into())),
+                                AExpr::Local(1),
+                            ],
+                        )),
+                        Box::new(Expr::Seq(
+                            Box::new(call(
+                                "intrinsics/bytes:set",
+                                vec![
+                                    lit(Literal::Fixnum(0)),
+                                    lit(Literal::Byte(b'z')),
+                                    AExpr::Local(0),
+                                ],
+                            )),
+                            Box::new(call(
+                                "intrinsics:list",
+                                vec![
+                                    AExpr::Local(3),
+                                    AExpr::Local(2),
+                                    AExpr::Local(1),
+                                    AExpr::Local(0),
+                                ],
+                            )),
+                        )),
+                    )),
+                )),
+            )),
+        )),
+    );
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    interpreter.set_gc_threshold(Some(0));
+    let val = interpreter.eval(&expr).unwrap();
+    assert_eq!(
+        val.display(&interpreter.store, false).to_string(),
+        r#"((1 2 3) [1 x 3] [x 3] b"\x7a\x63")"#
+    );
+}
+
+#[test]
+fn maps_are_persistent_and_survive_gc() {
+    let mut store = Store::new();
+    let mut globals = HashMap::new();
+
+    let mut map = store.store_map();
+    for i in 0..1000 {
+        map = store.map_insert(map, Value::Fixnum(i), Value::Fixnum(i * i)).0;
+    }
+    let old = map;
+    let key = store.store_literal(&Literal::String("key".to_string()));
+    let (map, added) = store.map_insert(map, key, Value::Symbol("value".into()));
+    assert!(added);
+    let map = store.map_remove(map, Value::Fixnum(500)).unwrap();
+    assert!(store.map_remove(map, Value::Fixnum(500)).is_none());
+    globals.insert("old".into(), Value::Map(old, 1000));
+    globals.insert("new".into(), Value::Map(map, 1000));
+
+    let mut state = State::Halted(Value::Nil);
+    store.collect(&mut globals, &mut state);
+    let get = |store: &Store, name: &str, key: Value| match globals[&Symbol::from(name)] {
+        Value::Map(a, _) => store.map_get(a, key),
+        val => panic!("Not a map: {:?}", val),
+    };
+    let key = store.store_literal(&Literal::String("key".to_string()));
+    assert_eq!(get(&store, "new", Value::Fixnum(499)), Some(Value::Fixnum(249001)));
+    assert_eq!(get(&store, "new", Value::Fixnum(500)), None);
+    assert_eq!(get(&store, "new", key), Some(Value::Symbol("value".into())));
+    assert_eq!(get(&store, "old", Value::Fixnum(500)), Some(Value::Fixnum(250000)));
+    assert_eq!(get(&store, "old", key), None);
+
+    let mut reversed = store.store_map();
+    for i in (0..1000).rev() {
+        reversed = store.map_insert(reversed, Value::Fixnum(i), Value::Fixnum(i * i)).0;
+    }
+    let old = globals[&Symbol::from("old")];
+    assert!(old.equals(Value::Map(reversed, 1000), &store));
+    assert_eq!(old.compare(Value::Map(reversed, 1000), &store), CmpOrdering::Equal);
+    assert!(!old.equals(globals[&Symbol::from("new")], &store));
+
+    let mut small = store.store_map();
+    small = store.map_insert(small, key, Value::Fixnum(2)).0;
+    small = store.map_insert(small, Value::Fixnum(1), Value::Nil).0;
+    assert_eq!(
+        Value::Map(small, 2).display(&store, false).to_string(),
+        r#"{1 (), "key" 2}"#
+    );
+}
+
+#[test]
+fn map_remove_collapses_subtries() {
+    let size_after_gc = |store: &mut Store, map| {
+        let mut globals = HashMap::new();
+        globals.insert("map".into(), Value::Map(map, 1));
+        let mut state = State::Halted(Value::Nil);
+        store.collect(&mut globals, &mut state);
+        store.size()
+    };
+
+    let mut store = Store::new();
+    let mut map = store.store_map();
+    for i in 0..1000 {
+        map = store.map_insert(map, Value::Fixnum(i), Value::Nil).0;
+    }
+    for i in 1..1000 {
+        map = store.map_remove(map, Value::Fixnum(i)).unwrap();
+    }
+    assert_eq!(store.map_get(map, Value::Fixnum(0)), Some(Value::Nil));
+    let removed = size_after_gc(&mut store, map);
+
+    let mut store = Store::new();
+    let map = store.store_map();
+    let map = store.map_insert(map, Value::Fixnum(0), Value::Nil).0;
+    assert_eq!(removed, size_after_gc(&mut store, map));
+}
+
+#[test]
+fn maps_keyed_on_refs_survive_gc() {
+    let mut store = Store::new();
+    let mut globals = HashMap::new();
+
+    for i in 0..100 {
+        store.store_ref(Value::Fixnum(i));
+    }
+    let refs = (0..100)
+        .map(|_| Value::Ref(store.store_ref(Value::Nil)))
+        .collect::<Vec<_>>();
+    let hashes = refs
+        .iter()
+        .map(|r| {
+            let mut hasher = DefaultHasher::new();
+            r.hash(&store, &mut hasher);
+            hasher.finish()
+        })
+        .collect::<HashSet<_>>();
+    assert_eq!(hashes.len(), refs.len());
+
+    // Refs inside other keys are hashed by their addresses too.
+    let mut map = store.store_map();
+    for (i, &r) in refs.iter().enumerate() {
+        let key = if i % 2 == 0 {
+            r
+        } else {
+            let nil = store.store(Value::Nil);
+            Value::Cons(store.store(r), nil)
+        };
+        map = store.map_insert(map, key, Value::Fixnum(i as isize)).0;
+    }
+    assert!(!Value::Fixnum(0).hashes_by_address(&store));
+    let cells = refs.iter().map(|&r| store.store(r)).collect::<Vec<_>>();
+    let (a, l) = store.store_vec(&cells);
+    globals.insert("refs".into(), Value::Vector(a, l));
+    globals.insert("map".into(), Value::Map(map, refs.len()));
+
+    let mut state = State::Halted(Value::Nil);
+    store.collect(&mut globals, &mut state);
+    let refs = match globals[&Symbol::from("refs")] {
+        Value::Vector(a, l) => store.get_vec(a, l),
+        val => panic!("Not a vector: {:?}", val),
+    };
+    let map = match globals[&Symbol::from("map")] {
+        Value::Map(a, _) => a,
+        val => panic!("Not a map: {:?}", val),
+    };
+    for (i, &r) in refs.iter().enumerate() {
+        let key = if i % 2 == 0 {
+            r
+        } else {
+            let nil = store.store(Value::Nil);
+            Value::Cons(store.store(r), nil)
+        };
+        assert!(key.hashes_by_address(&store));
+        assert_eq!(store.map_get(map, key), Some(Value::Fixnum(i as isize)));
+    }
+    let other = Value::Ref(store.store_ref(Value::Nil));
+    assert_eq!(store.map_get(map, other), None);
+}
+
+#[test]
+fn mutable_map_keys_are_rejected() {
+    let new = || call("intrinsics/map:new", vec![]);
+    let key = || {
+        lit(Literal::list(vec![
+            Literal::Fixnum(1),
+            Literal::Vector(vec![Literal::Fixnum(2)]),
+        ]))
+    };
+    let exprs = vec![
+        Expr::Let(
+            Box::new(new()),
+            Box::new(call("intrinsics/map:get", vec![key(), AExpr::Local(0)])),
+        ),
+        Expr::Let(
+            Box::new(new()),
+            Box::new(call(
+                "intrinsics/map:insert",
+                vec![key(), lit(Literal::Nil), AExpr::Local(0)],
+            )),
+        ),
+        Expr::Let(
+            Box::new(new()),
+            Box::new(call(
+                "intrinsics/map:remove",
+                vec![lit(Literal::Bytes(vec![1])), AExpr::Local(0)],
+            )),
+        ),
+    ];
+    for expr in &exprs {
+        let mut interpreter = Interpreter::new();
+        interpreter.add_builtins::<Intrinsics>();
+        match interpreter.eval(expr) {
+            Err(RuntimeError::TypeError(_, "a key without vectors or bytes", _)) => {}
+            r => panic!("Expected a type error, got {:?}", r),
+        }
+    }
+
+    // References are compared by identity, so they can hold vectors.
+    // let $0 = (ref/new [2]) in
+    // let $0 = (map/new) in
+    // let $0 = (map/insert $1 1 $0) in
+    // (map/len $0)
+    let expr = Expr::Let(
+        Box::new(call(
+            "intrinsics/ref:new",
+            vec![lit(Literal::Vector(vec![Literal::Fixnum(2)]))],
+        )),
+        Box::new(Expr::Let(
+            Box::new(new()),
+            Box::new(Expr::Let(
+                Box::new(call(
+                    "intrinsics/map:insert",
+                    vec![AExpr::Local(1), lit(Literal::Fixnum(1)), AExpr::Local(0)],
+                )),
+                Box::new(call("intrinsics/map:len", vec![AExpr::Local(0)])),
+            )),
+        )),
+    );
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    assert_eq!(interpreter.eval(&expr).unwrap(), Value::Fixnum(1));
+}
+
+#[test]
+fn map_hashes_depend_on_entries() {
+    let mut store = Store::new();
+    let map = |store: &mut Store, entries: &[(isize, isize)]| {
+        let mut map = store.store_map();
+        for &(k, v) in entries {
+            map = store.map_insert(map, Value::Fixnum(k), Value::Fixnum(v)).0;
+        }
+        let mut hasher = DefaultHasher::new();
+        Value::Map(map, entries.len()).hash(store, &mut hasher);
+        hasher.finish()
+    };
+
+    let forward = map(&mut store, &[(1, 2), (3, 4), (5, 6)]);
+    let backward = map(&mut store, &[(5, 6), (3, 4), (1, 2)]);
+    let other_key = map(&mut store, &[(1, 2), (3, 4), (7, 6)]);
+    let other_val = map(&mut store, &[(1, 2), (3, 4), (5, 7)]);
+    assert_eq!(forward, backward);
+    assert_ne!(forward, other_key);
+    assert_ne!(forward, other_val);
+}
+
+#[test]
+fn fixnum_overflow_promotes_to_bignum() {
+    // let $0 = (add MAX 1) in
+    // let $0 = (subtract $0 1) in
+    // [$1 $0]
+    let max = isize::MAX;
+    let expr = Expr::Let(
+        Box::new(call(
+            "intrinsics/math:add",
+            vec![lit(Literal::Fixnum(max)), lit(Literal::Fixnum(1))],
+        )),
+        Box::new(Expr::Let(
+            Box::new(call(
+                "intrinsics/math:subtract",
+                vec![AExpr::Local(0), lit(Literal::Fixnum(1))],
+            )),
+            Box::new(Expr::AExpr(AExpr::Vector(vec![
+                AExpr::Local(1),
+                AExpr::Local(0),
+            ]))),
+        )),
+    );
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    interpreter.set_gc_threshold(Some(0));
+    let val = interpreter.eval(&expr).unwrap();
+    let vals = match val {
+        Value::Vector(a, l) => interpreter.store.get_vec(a, l),
+        val => panic!("Not a vector: {:?}", val),
+    };
+    let big = BigInt::from(max as i64) + 1;
+    match vals[0] {
+        Value::Bignum(a) => assert_eq!(interpreter.store.get_bignum(a), &big),
+        val => panic!("Not a bignum: {:?}", val),
+    }
+    assert_eq!(vals[1], Value::Fixnum(max));
+
+    let lits = ::parser::parse_program(&big.to_string()).unwrap();
+    assert_eq!(lits, vec![Literal::Bignum(big)]);
+}
+
+#[test]
+fn math_promotes_integers_to_floats() {
+    // let $0 = (add 1 0.5) in
+    // let $0 = (divide 7 2.0) in
+    // let $0 = (multiply BIG 0.5) in
+    // [$2 $1 $0]
+    let big = BigInt::from(1) << 64;
+    let expr = Expr::Let(
+        Box::new(call(
+            "intrinsics/math:add",
+            vec![lit(Literal::Fixnum(1)), lit(Literal::Float(0.5))],
+        )),
+        Box::new(Expr::Let(
+            Box::new(call(
+                "intrinsics/math:divide",
+                vec![lit(Literal::Fixnum(7)), lit(Literal::Float(2.0))],
+            )),
+            Box::new(Expr::Let(
+                Box::new(call(
+                    "intrinsics/math:multiply",
+                    vec![lit(Literal::Bignum(big)), lit(Literal::Float(0.5))],
+                )),
+                Box::new(Expr::AExpr(AExpr::Vector(vec![
+                    AExpr::Local(2),
+                    AExpr::Local(1),
+                    AExpr::Local(0),
+                ]))),
+            )),
+        )),
+    );
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    let val = interpreter.eval(&expr).unwrap();
+    assert_eq!(
+        val.display(&interpreter.store, false).to_string(),
+        "[1.5 3.5 9223372036854775808.0]"
+    );
+    assert_eq!(
+        Value::Float(2.0).compare(Value::Fixnum(2), &interpreter.store),
+        CmpOrdering::Greater
+    );
+    assert_eq!(
+        Value::Float(1.5).compare(Value::Fixnum(2), &interpreter.store),
+        CmpOrdering::Less
+    );
+}
+
+#[test]
+fn division_by_zero_is_catchable() {
+    // (with-handler (fn (e) e) (fn () (modulo 1 0)))
+    let handler = AExpr::Lambda(None, 1, Box::new(Expr::AExpr(AExpr::Local(0))));
+    let body = AExpr::Lambda(
+        None,
+        0,
+        Box::new(call(
+            "intrinsics/math:modulo",
+            vec![lit(Literal::Fixnum(1)), lit(Literal::Fixnum(0))],
+        )),
+    );
+    let expr = call("intrinsics:with_handler", vec![handler, body]);
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    let val = interpreter.eval(&expr).unwrap();
+    assert_eq!(
+        val.display(&interpreter.store, false).to_string(),
+        r#"(divide-by-zero "Division by zero in `intrinsics/math:modulo'")"#
+    );
+
+    let expr = call(
+        "intrinsics/math:divide",
+        vec![lit(Literal::Byte(1)), lit(Literal::Byte(0))],
+    );
+    match interpreter.eval(&expr) {
+        Err(RuntimeError::DivideByZero(name)) => assert_eq!(name, "intrinsics/math:divide"),
+        r => panic!("Expected a division by zero, got {:?}", r),
+    }
+
+    for &func in &[
+        "divide_saturating",
+        "divide_wrapping",
+        "modulo_saturating",
+        "modulo_wrapping",
+    ] {
+        let func = format!("intrinsics/math:{}", func);
+        for args in &[
+            (Literal::Byte(1), Literal::Byte(0)),
+            (Literal::Fixnum(1), Literal::Fixnum(0)),
+        ] {
+            let expr = call(&func, vec![lit(args.0.clone()), lit(args.1.clone())]);
+            let mut interpreter = Interpreter::new();
+            interpreter.add_builtins::<Intrinsics>();
+            match interpreter.eval(&expr) {
+                Err(RuntimeError::DivideByZero(name)) => assert_eq!(name, func),
+                r => panic!("Expected a division by zero, got {:?}", r),
+            }
+        }
+    }
+}
+
+#[test]
+fn overflowing_math_variants() {
+    let max = isize::MAX;
+    let min = isize::MIN;
+    let cases = [
+        ("add", Literal::Byte(200), Literal::Byte(100), "44".to_string()),
+        ("add_checked", Literal::Byte(200), Literal::Byte(100), "none".to_string()),
+        ("add_checked", Literal::Byte(200), Literal::Byte(50), "(some | 250)".to_string()),
+        ("subtract_saturating", Literal::Byte(1), Literal::Byte(2), "0".to_string()),
+        ("add_wrapping", Literal::Fixnum(max), Literal::Fixnum(1), min.to_string()),
+        ("multiply_saturating", Literal::Fixnum(max), Literal::Fixnum(2), max.to_string()),
+        ("subtract_checked", Literal::Fixnum(min), Literal::Fixnum(1), "none".to_string()),
+        ("divide_checked", Literal::Byte(7), Literal::Byte(0), "none".to_string()),
+        ("divide_checked", Literal::Byte(7), Literal::Byte(2), "(some | 3)".to_string()),
+        ("divide_checked", Literal::Fixnum(min), Literal::Fixnum(-1), "none".to_string()),
+        ("divide_checked", Literal::Fixnum(-7), Literal::Fixnum(0), "none".to_string()),
+        ("divide_saturating", Literal::Byte(7), Literal::Byte(2), "3".to_string()),
+        ("divide_saturating", Literal::Fixnum(min), Literal::Fixnum(-1), max.to_string()),
+        ("divide_wrapping", Literal::Byte(255), Literal::Byte(16), "15".to_string()),
+        ("divide_wrapping", Literal::Fixnum(min), Literal::Fixnum(-1), min.to_string()),
+        ("modulo_checked", Literal::Byte(7), Literal::Byte(0), "none".to_string()),
+        ("modulo_checked", Literal::Fixnum(min), Literal::Fixnum(-1), "none".to_string()),
+        ("modulo_checked", Literal::Fixnum(-7), Literal::Fixnum(2), "(some | -1)".to_string()),
+        ("modulo_saturating", Literal::Byte(7), Literal::Byte(4), "3".to_string()),
+        ("modulo_saturating", Literal::Fixnum(min), Literal::Fixnum(-1), "0".to_string()),
+        ("modulo_wrapping", Literal::Byte(7), Literal::Byte(4), "3".to_string()),
+        ("modulo_wrapping", Literal::Fixnum(min), Literal::Fixnum(-1), "0".to_string()),
+    ];
+    let exprs = cases
+        .iter()
+        .map(|&(func, ref l, ref r, _)| {
+            let func = format!("intrinsics/math:{}", func);
+            call(&func, vec![lit(l.clone()), lit(r.clone())])
+        })
+        .collect::<Vec<_>>();
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    for (expr, case) in exprs.iter().zip(cases.iter()) {
+        let val = interpreter.eval(expr).unwrap();
+        assert_eq!(val.display(&interpreter.store, false).to_string(), case.3);
+    }
+}
+
+#[test]
+fn string_char_index_matches_scan() {
+    let mut store = Store::new();
+    let mut globals = HashMap::new();
+
+    store.store_str("garbage");
+    let s = "aé😀".repeat(40);
+    let (a, l) = store.store_str(&s);
+    globals.insert("s".into(), Value::String(a, l));
+    let check = |store: &mut Store, a, l| {
+        let offsets = s.char_indices()
+            .map(|(i, _)| Some(i))
+            .chain(vec![Some(s.len()), None])
+            .collect::<Vec<_>>();
+        for (n, &offset) in offsets.iter().enumerate() {
+            assert_eq!(store.str_char_offset(a, l, n), offset);
+        }
+        assert_eq!(store.str_char_count(a, l), 120);
+    };
+    let before = store.size();
+    check(&mut store, a, l);
+    assert!(store.size() > before);
+
+    let mut state = State::Halted(Value::Nil);
+    store.collect(&mut globals, &mut state);
+    match globals[&Symbol::from("s")] {
+        Value::String(a, l) => check(&mut store, a, l),
+        val => panic!("Not a string: {:?}", val),
+    }
+}
+
+#[test]
+fn bytes_convert_to_strings_and_fixnums() {
+    let cases = [
+        ("bytes_to_string", Literal::Bytes(b"caf\xc3\xa9".to_vec()), r#"(ok | "caf\xe9")"#),
+        (
+            "bytes_to_string",
+            Literal::Bytes(b"caf\xc3".to_vec()),
+            r#"(err | "incomplete utf-8 byte sequence from index 3")"#,
+        ),
+        ("bytes_to_string_lossy", Literal::Bytes(b"a\xffb".to_vec()), r#""a\ufffdb""#),
+        ("bytes_to_fixnum_be", Literal::Bytes(vec![1, 2]), "258"),
+        ("bytes_to_fixnum_le", Literal::Bytes(vec![1, 2]), "513"),
+        ("bytes_to_fixnum_le", Literal::Bytes(vec![0xff; 8]), "-1"),
+        (
+            "fixnum_to_bytes_be",
+            Literal::Fixnum(258),
+            r#"b"\x00\x00\x00\x00\x00\x00\x01\x02""#,
+        ),
+        (
+            "fixnum_to_bytes_le",
+            Literal::Fixnum(-2),
+            r#"b"\xfe\xff\xff\xff\xff\xff\xff\xff""#,
+        ),
+    ];
+    let exprs = cases
+        .iter()
+        .map(|&(func, ref arg, _)| {
+            let func = format!("intrinsics/convert:{}", func);
+            call(&func, vec![lit(arg.clone())])
+        })
+        .collect::<Vec<_>>();
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    for (expr, case) in exprs.iter().zip(cases.iter()) {
+        let val = interpreter.eval(expr).unwrap();
+        assert_eq!(val.display(&interpreter.store, false).to_string(), case.2);
+    }
+}
+
+#[test]
+fn fixnums_format_with_radix_and_padding() {
+    let cases = [
+        (16, 0, Literal::Fixnum(255), "ff"),
+        (2, 8, Literal::Fixnum(5), "00000101"),
+        (10, 4, Literal::Fixnum(-7), "-007"),
+        (10, 1, Literal::Fixnum(-123), "-123"),
+        (36, 0, Literal::Bignum(BigInt::from(36) << 64), "3w5e11264sgsg0"),
+    ];
+    let exprs = cases
+        .iter()
+        .map(|&(radix, width, ref n, _)| {
+            call(
+                "intrinsics/fixnum:format",
+                vec![
+                    lit(Literal::Fixnum(radix)),
+                    lit(Literal::Fixnum(width)),
+                    lit(n.clone()),
+                ],
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    for (expr, case) in exprs.iter().zip(cases.iter()) {
+        let val = interpreter.eval(expr).unwrap();
+        assert_eq!(val.display(&interpreter.store, true).to_string(), case.3);
+    }
+}
+
+#[test]
+fn floats_convert_to_fixnums_and_bignums() {
+    let cases = [
+        (-2.5, "-2", false),
+        (1e15 + 0.5, "1000000000000000", false),
+        (-9223372036854775808.0, "-9223372036854775808", false),
+        (9223372036854775808.0, "9223372036854775808", true),
+        (1e20, "100000000000000000000", true),
+    ];
+    let exprs = cases
+        .iter()
+        .map(|&(n, _, _)| call("intrinsics/float:to_fixnum", vec![lit(Literal::Float(n))]))
+        .collect::<Vec<_>>();
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    for (expr, case) in exprs.iter().zip(cases.iter()) {
+        let val = interpreter.eval(expr).unwrap();
+        assert_eq!(val.display(&interpreter.store, false).to_string(), case.1);
+        match val {
+            Value::Fixnum(_) => assert!(!case.2),
+            Value::Bignum(_) => assert!(case.2),
+            val => panic!("Not an integer: {:?}", val),
+        }
+    }
+}
+
+#[test]
+fn gc_closes_unreachable_handles() {
+    let mut store = Store::new();
+    let mut globals = HashMap::new();
+
+    let path = env::temp_dir().join(format!("oftb-handle-test-{}", process::id()));
+    for name in &["kept", "dropped", "closed"] {
+        let file = File::create(&path).unwrap();
+        let handle = store.store_handle(HandleState::File(file));
+        globals.insert(Symbol::from(*name), handle);
+    }
+    match globals[&Symbol::from("closed")] {
+        Value::Handle(a) => store.close_handle(a),
+        val => panic!("Not a handle: {:?}", val),
+    }
+    globals.remove(&Symbol::from("dropped"));
+    assert_eq!(store.open_handles(), 2);
+
+    let mut state = State::Halted(Value::Nil);
+    store.collect(&mut globals, &mut state);
+    assert_eq!(store.open_handles(), 1);
+    match globals[&Symbol::from("kept")] {
+        Value::Handle(a) => match *store.get_handle(a) {
+            HandleState::File(_) => {}
+            ref h => panic!("Not an open file: {:?}", h),
+        },
+        val => panic!("Not a handle: {:?}", val),
+    }
+    remove_file(&path).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn gc_reaps_unreachable_processes() {
+    use std::path::Path;
+    use std::process::Command;
+    use std::thread::sleep;
+
+    let mut store = Store::new();
+    let child = Command::new("true").spawn().unwrap();
+    let proc_dir = format!("/proc/{}", child.id());
+    store.store_handle(HandleState::Process(child));
+
+    let mut state = State::Halted(Value::Nil);
+    store.collect(&mut HashMap::new(), &mut state);
+    assert_eq!(store.open_handles(), 0);
+    let start = Instant::now();
+    while Path::new(&proc_dir).exists() {
+        assert!(start.elapsed() < Duration::from_secs(10), "{} wasn't reaped", proc_dir);
+        sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn fs_operations_return_results() {
+    let dir = env::temp_dir().join(format!("oftb-fs-test-{}", process::id()));
+    let path = |p: &str| lit(Literal::String(dir.join(p).display().to_string()));
+    let cases = [
+        ("make_dir", vec![path("a/b")], "(ok)"),
+        ("exists", vec![path("a/b")], "true"),
+        ("stat", vec![path("a")], "type dir"),
+        ("rename", vec![path("a/b"), path("a/c")], "(ok)"),
+        ("exists", vec![path("a/b")], "()"),
+        ("remove", vec![path("a/b")], "(err not-found | \"Couldn't remove "),
+        ("remove", vec![path("a/c")], "(ok)"),
+        ("make_dir", vec![path("a/d")], "(ok)"),
+        ("remove_all", vec![path("a")], "(ok)"),
+        ("exists", vec![path("a")], "()"),
+        ("stat", vec![path("a")], "(err not-found | "),
+    ];
+    let exprs = cases
+        .iter()
+        .map(|&(func, ref args, _)| call(&format!("intrinsics/fs:{}", func), args.clone()))
+        .collect::<Vec<_>>();
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    for (expr, case) in exprs.iter().zip(cases.iter()) {
+        let val = interpreter.eval(expr).unwrap();
+        let val = val.display(&interpreter.store, false).to_string();
+        assert!(val.contains(case.2), "{}: {}", case.0, val);
+    }
+    remove_dir(&dir).unwrap();
+}
+
+#[test]
+fn exit_cannot_be_caught() {
+    // (with-handler (fn (e) e) (fn () (exit 3)))
+    let handler = AExpr::Lambda(None, 1, Box::new(Expr::AExpr(AExpr::Local(0))));
+    let body = AExpr::Lambda(
+        None,
+        0,
+        Box::new(call("intrinsics/process:exit", vec![lit(Literal::Fixnum(3))])),
+    );
+    let expr = call("intrinsics:with_handler", vec![handler, body]);
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    match interpreter.eval(&expr) {
+        Err(RuntimeError::Exit(3)) => {}
+        r => panic!("Expected an exit, got {:?}", r),
+    }
+    assert!(interpreter.stack_trace().unwrap().0.is_empty());
+
+    let expr = call(
+        "intrinsics:panic",
+        vec![lit(Literal::Cons(
+            Box::new(Literal::Symbol("exit".into())),
+            Box::new(Literal::Fixnum(2)),
+        ))],
+    );
+    match interpreter.eval(&expr) {
+        Err(RuntimeError::Exit(2)) => {}
+        r => panic!("Expected an exit, got {:?}", r),
+    }
+
+    // Every fixnum is a valid exit code when isize is 32 bits.
+    if let Some(code) = (i32::MAX as isize).checked_add(1) {
+        let expr = call("intrinsics/process:exit", vec![lit(Literal::Fixnum(code))]);
+        match interpreter.eval(&expr) {
+            Err(RuntimeError::OutOfBounds(..)) => {}
+            r => panic!("Expected an out-of-bounds exit code, got {:?}", r),
+        }
+    }
+}
+
+#[test]
+fn fake_clock_advances_when_sleeping() {
+    let exprs = [
+        call("intrinsics/time:sleep_ns", vec![lit(Literal::Fixnum(1_500_000_000))]),
+        call("intrinsics/time:monotonic_ns", vec![]),
+        call("intrinsics/time:unix_time", vec![]),
+    ];
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    interpreter
+        .store
+        .set_clock(Clock::fake(Duration::from_secs(1_000_000)));
+    let start = Instant::now();
+    let vals = exprs
+        .iter()
+        .map(|expr| interpreter.eval(expr).unwrap())
+        .collect::<Vec<_>>();
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(
+        vals,
+        [Value::Nil, Value::Fixnum(1_500_000_000), Value::Fixnum(1_000_001)]
+    );
+}
+
+#[test]
+fn io_reads_from_fake_stdin() {
+    let read_line = call("intrinsics/io:read_line", vec![]);
+    let read_bytes = |n| call("intrinsics/io:read_bytes", vec![lit(Literal::Fixnum(n))]);
+    let is_eof = call("intrinsics/io:is_eof", vec![]);
+    let exprs = [
+        read_line.clone(),
+        is_eof.clone(),
+        read_bytes(2),
+        is_eof.clone(),
+        read_line.clone(),
+        read_bytes(8),
+        is_eof,
+        read_bytes(8),
+        read_line,
+    ];
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    interpreter
+        .store
+        .set_stdin(Stdin::fake(&b"one\r\ntwo\nthree"[..]));
+    let vals = exprs
+        .iter()
+        .map(|expr| {
+            let val = interpreter.eval(expr).unwrap();
+            val.display(&interpreter.store, false).to_string()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vals,
+        [
+            "(some | \"one\")",
+            "()",
+            r#"b"\x74\x77""#,
+            "()",
+            "(some | \"o\")",
+            r#"b"\x74\x68\x72\x65\x65""#,
+            "true",
+            "b\"\"",
+            "none",
+        ]
+    );
+}
+
+#[test]
+fn random_is_reproducible_from_a_seed() {
+    let range = |lo, hi| {
+        call(
+            "intrinsics/random:fixnum",
+            vec![lit(Literal::Fixnum(lo)), lit(Literal::Fixnum(hi))],
+        )
+    };
+    let exprs = [
+        call("intrinsics/random:seed", vec![lit(Literal::Fixnum(42))]),
+        range(-3, 3),
+        range(isize::MIN, isize::MAX),
+        call("intrinsics/random:bytes", vec![lit(Literal::Fixnum(13))]),
+        call(
+            "intrinsics/random:shuffle",
+            vec![lit(Literal::Vector((0..20).map(Literal::Fixnum).collect()))],
+        ),
+    ];
+
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    let first = {
+        let mut run = || {
+            exprs
+                .iter()
+                .map(|expr| {
+                    let val = interpreter.eval(expr).unwrap();
+                    val.display(&interpreter.store, false).to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+        let first = run();
+        assert_eq!(first, run());
+        first
+    };
+
+    let samples = (0..100)
+        .map(|_| match interpreter.eval(&exprs[1]).unwrap() {
+            Value::Fixnum(n) => n,
+            val => panic!("Not a fixnum: {:?}", val),
+        })
+        .collect::<HashSet<_>>();
+    assert_eq!(samples, (-3..3).collect());
+    assert_eq!(first[3].len(), r#"b"""#.len() + 13 * 4);
+    let mut shuffled = first[4]
+        .trim_matches(|c| c == '[' || c == ']')
+        .split(' ')
+        .map(|n| n.parse::<isize>().unwrap())
+        .collect::<Vec<_>>();
+    assert_ne!(shuffled, (0..20).collect::<Vec<_>>());
+    shuffled.sort();
+    assert_eq!(shuffled, (0..20).collect::<Vec<_>>());
+}
+
+/// Builds `let $0 = (fn test:f (x) (cons x x)) in let $0 = ($0 1) in (cons $0 2)`,
+/// returning it with the body of `test:f` and the call `($0 1)`.
+fn stepping_program() -> (Expr, Expr, Expr) {
+    let body = call(
+        "intrinsics:cons",
+        vec![AExpr::Local(0), AExpr::Local(0)],
+    );
+    let f = AExpr::Lambda(Some("test:f".into()), 1, Box::new(body.clone()));
+    let call_f = Expr::CExpr(CExpr::Call(AExpr::Local(0), vec![lit(Literal::Fixnum(1))]));
+    let expr = Expr::Let(
+        Box::new(Expr::AExpr(f)),
+        Box::new(Expr::Let(
+            Box::new(call_f.clone()),
+            Box::new(call(
+                "intrinsics:cons",
+                vec![AExpr::Local(0), lit(Literal::Fixnum(2))],
+            )),
+        )),
+    );
+    (expr, body, call_f)
+}
+
+/// Returns the expression about to be evaluated.
+fn control<'a>(interpreter: &Interpreter<'a>) -> &'a Expr {
+    match *interpreter.state() {
+        State::Running(Control::Normal(expr), _, _) => expr,
+        ref state => panic!("not running: {:?}", state),
+    }
+}
+
+#[test]
+fn step_until_breaks_on_function_entry() {
+    let (expr, body, _) = stepping_program();
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    interpreter.load_expr(&expr);
+
+    let f = Symbol::from("test:f");
+    assert!(
+        interpreter
+            .step_until(|i| i.entered_function() == Some(f))
+            .is_none()
+    );
+    assert_eq!(control(&interpreter), &body);
+    assert_eq!(interpreter.return_depth(), Some(interpreter.depth() - 1));
+
+    let result = interpreter.step_until(|i| i.entered_function() == Some(f));
+    let val = result.unwrap().unwrap();
+    assert_eq!(val.display(&interpreter.store, false).to_string(), "((1 | 1) | 2)");
+}
+
+#[test]
+fn step_until_steps_over_calls() {
+    let (expr, _, call_f) = stepping_program();
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    interpreter.load_expr(&expr);
+
+    assert!(interpreter.step_until(|i| control(i) == &call_f).is_none());
+    let depth = interpreter.depth();
+    let mut entered = false;
+    assert!(
+        interpreter
+            .step_until(|i| {
+                entered |= i.entered_function().is_some();
+                i.depth() <= depth
+            })
+            .is_none()
+    );
+    assert!(entered);
+    assert_eq!(interpreter.depth(), depth - 1);
+    assert_eq!(control(&interpreter).to_string(), "(intrinsics:cons $0 2)");
+}
+
+#[test]
+fn step_until_finishes_to_the_caller() {
+    let (expr, _, _) = stepping_program();
+    let mut interpreter = Interpreter::new();
+    interpreter.add_builtins::<Intrinsics>();
+    interpreter.load_expr(&expr);
+
+    assert!(interpreter.step_until(|i| i.entered_function().is_some()).is_none());
+    let depth = interpreter.return_depth().unwrap();
+    assert!(interpreter.step_until(|i| i.depth() <= depth).is_none());
+    assert_eq!(interpreter.return_depth(), None);
+    assert_eq!(control(&interpreter).to_string(), "(intrinsics:cons $0 2)");
+    match *interpreter.state() {
+        State::Running(_, ref env, _) => {
+            let locals = env.locals();
+            assert_eq!(
+                locals[0].display(&interpreter.store, false).to_string(),
+                "(1 | 1)"
+            );
+        }
+        _ => unreachable!(),
+    }
+}
@@ -1,10 +1,15 @@
 use std::cmp::{min, Ordering};
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::hash::{Hash, Hasher};
 
+use num::Signed;
 use symbol::Symbol;
 
-use interpreter::{Addr, Bytes, Closure, Kont, State, Store, Vector};
-use util::{escape_bytes, escape_str};
+use interpreter::store::KeyHasher;
+use interpreter::{
+    Addr, Bignum, Bytes, Closure, Continuation, Handle, Kont, Map, State, Store, Vector,
+};
+use util::{bignum_to_float, escape_bytes, escape_str, write_float};
 
 /// The type of an intrinsic function.
 #[derive(Copy, Clone)]
@@ -61,14 +66,20 @@ impl Ord for Intrinsic {
 /// A value stored in the store.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Value {
+    Bignum(Addr<Bignum>),
     Byte(u8),
     Bytes(Addr<Bytes>, usize),
     Closure(Addr<Closure>),
     Cons(Addr<Value>, Addr<Value>),
+    Continuation(Addr<Continuation>),
     Fixnum(isize),
+    Float(f64),
+    Handle(Addr<Handle>),
     Intrinsic(Intrinsic),
+    Map(Addr<Map>, usize),
     Nil,
     Object(Symbol, Addr<Value>),
+    Ref(Addr<Value>),
     String(Addr<String>, usize),
     Symbol(Symbol),
     Vector(Addr<Vector>, usize),
@@ -89,10 +100,49 @@ impl Value {
         }
     }
 
-    /// Compares two values "deeply". Note that this may take arbitrarily long,
-    /// or even not terminate (for cyclic structures).
+    /// Compares two values "deeply". Note that this may take arbitrarily long.
+    /// References are compared by identity rather than by their contents,
+    /// since those may change. Vectors may be cyclic, so a pair of vectors
+    /// that's already being compared further out is treated as equal.
     pub fn compare(self, other: Value, store: &Store) -> Ordering {
+        self.compare_in(other, store, &mut Vec::new())
+    }
+
+    /// Compares two values, where `parents` holds the pairs of vectors that
+    /// contain them.
+    fn compare_in(
+        self,
+        other: Value,
+        store: &Store,
+        parents: &mut Vec<(Value, Value)>,
+    ) -> Ordering {
         match (self, other) {
+            // Floats are ordered numerically with integers, and after them
+            // when they're numerically equal. NaN is greater than all other
+            // floats.
+            (Value::Float(l), Value::Float(r)) => compare_floats(l, r),
+            (Value::Float(l), Value::Bignum(r)) => {
+                let r = bignum_to_float(store.get_bignum(r));
+                compare_floats(l, r).then(Ordering::Greater)
+            }
+            (Value::Float(l), Value::Fixnum(r)) => {
+                compare_floats(l, r as f64).then(Ordering::Greater)
+            }
+            (Value::Float(_), _) => Value::Fixnum(0).compare_in(other, store, parents),
+            (_, Value::Float(_)) => other.compare_in(self, store, parents).reverse(),
+
+            // Bignums are ordered numerically with fixnums. Since they're
+            // normalized, every positive bignum is greater than every fixnum,
+            // and every negative one is less.
+            (Value::Bignum(l), Value::Bignum(r)) => store.get_bignum(l).cmp(store.get_bignum(r)),
+            (Value::Bignum(l), Value::Fixnum(_)) => if store.get_bignum(l).is_negative() {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            },
+            (Value::Bignum(_), _) => Value::Fixnum(0).compare_in(other, store, parents),
+            (_, Value::Bignum(_)) => other.compare_in(self, store, parents).reverse(),
+
             (Value::Byte(l), Value::Byte(r)) => l.cmp(&r),
             (Value::Byte(_), _) => Ordering::Less,
 
@@ -119,34 +169,91 @@ impl Value {
                 let lt = store.get(lt);
                 let rh = store.get(rh);
                 let rt = store.get(rt);
-                match lh.compare(rh, store) {
-                    Ordering::Equal => lt.compare(rt, store),
+                match lh.compare_in(rh, store, parents) {
+                    Ordering::Equal => lt.compare_in(rt, store, parents),
                     o => o,
                 }
             }
             (Value::Cons(_, _), _) => Ordering::Less,
 
+            (Value::Continuation(_), Value::Byte(_)) => Ordering::Greater,
+            (Value::Continuation(_), Value::Bytes(_, _)) => Ordering::Greater,
+            (Value::Continuation(_), Value::Closure(_)) => Ordering::Greater,
+            (Value::Continuation(_), Value::Cons(_, _)) => Ordering::Greater,
+            (Value::Continuation(l), Value::Continuation(r)) => {
+                let l: usize = l.into();
+                let r: usize = r.into();
+                l.cmp(&r)
+            }
+            (Value::Continuation(_), _) => Ordering::Less,
+
             (Value::Fixnum(_), Value::Byte(_)) => Ordering::Greater,
             (Value::Fixnum(_), Value::Bytes(_, _)) => Ordering::Greater,
             (Value::Fixnum(_), Value::Closure(_)) => Ordering::Greater,
             (Value::Fixnum(_), Value::Cons(_, _)) => Ordering::Greater,
+            (Value::Fixnum(_), Value::Continuation(_)) => Ordering::Greater,
             (Value::Fixnum(l), Value::Fixnum(r)) => l.cmp(&r),
             (Value::Fixnum(_), _) => Ordering::Less,
 
+            (Value::Handle(_), Value::Byte(_)) => Ordering::Greater,
+            (Value::Handle(_), Value::Bytes(_, _)) => Ordering::Greater,
+            (Value::Handle(_), Value::Closure(_)) => Ordering::Greater,
+            (Value::Handle(_), Value::Cons(_, _)) => Ordering::Greater,
+            (Value::Handle(_), Value::Continuation(_)) => Ordering::Greater,
+            (Value::Handle(_), Value::Fixnum(_)) => Ordering::Greater,
+            (Value::Handle(l), Value::Handle(r)) => {
+                let l: usize = l.into();
+                let r: usize = r.into();
+                l.cmp(&r)
+            }
+            (Value::Handle(_), _) => Ordering::Less,
+
             (Value::Intrinsic(_), Value::Byte(_)) => Ordering::Greater,
             (Value::Intrinsic(_), Value::Bytes(_, _)) => Ordering::Greater,
             (Value::Intrinsic(_), Value::Closure(_)) => Ordering::Greater,
             (Value::Intrinsic(_), Value::Cons(_, _)) => Ordering::Greater,
+            (Value::Intrinsic(_), Value::Continuation(_)) => Ordering::Greater,
             (Value::Intrinsic(_), Value::Fixnum(_)) => Ordering::Greater,
+            (Value::Intrinsic(_), Value::Handle(_)) => Ordering::Greater,
             (Value::Intrinsic(l), Value::Intrinsic(r)) => l.cmp(&r),
             (Value::Intrinsic(_), _) => Ordering::Less,
 
+            (Value::Map(_, _), Value::Byte(_)) => Ordering::Greater,
+            (Value::Map(_, _), Value::Bytes(_, _)) => Ordering::Greater,
+            (Value::Map(_, _), Value::Closure(_)) => Ordering::Greater,
+            (Value::Map(_, _), Value::Cons(_, _)) => Ordering::Greater,
+            (Value::Map(_, _), Value::Continuation(_)) => Ordering::Greater,
+            (Value::Map(_, _), Value::Fixnum(_)) => Ordering::Greater,
+            (Value::Map(_, _), Value::Handle(_)) => Ordering::Greater,
+            (Value::Map(_, _), Value::Intrinsic(_)) => Ordering::Greater,
+            (Value::Map(la, _), Value::Map(ra, _)) => {
+                let l = store.get_map(la);
+                let r = store.get_map(ra);
+
+                let len = min(l.len(), r.len());
+                for i in 0..len {
+                    match l[i].0.compare_in(r[i].0, store, parents) {
+                        Ordering::Equal => {}
+                        o => return o,
+                    }
+                    match l[i].1.compare_in(r[i].1, store, parents) {
+                        Ordering::Equal => {}
+                        o => return o,
+                    }
+                }
+                l.len().cmp(&r.len())
+            }
+            (Value::Map(_, _), _) => Ordering::Less,
+
             (Value::Nil, Value::Byte(_)) => Ordering::Greater,
             (Value::Nil, Value::Bytes(_, _)) => Ordering::Greater,
             (Value::Nil, Value::Closure(_)) => Ordering::Greater,
             (Value::Nil, Value::Cons(_, _)) => Ordering::Greater,
+            (Value::Nil, Value::Continuation(_)) => Ordering::Greater,
             (Value::Nil, Value::Fixnum(_)) => Ordering::Greater,
+            (Value::Nil, Value::Handle(_)) => Ordering::Greater,
             (Value::Nil, Value::Intrinsic(_)) => Ordering::Greater,
+            (Value::Nil, Value::Map(_, _)) => Ordering::Greater,
             (Value::Nil, Value::Nil) => Ordering::Equal,
             (Value::Nil, _) => Ordering::Less,
 
@@ -154,23 +261,48 @@ impl Value {
             (Value::Object(_, _), Value::Bytes(_, _)) => Ordering::Greater,
             (Value::Object(_, _), Value::Closure(_)) => Ordering::Greater,
             (Value::Object(_, _), Value::Cons(_, _)) => Ordering::Greater,
+            (Value::Object(_, _), Value::Continuation(_)) => Ordering::Greater,
             (Value::Object(_, _), Value::Fixnum(_)) => Ordering::Greater,
+            (Value::Object(_, _), Value::Handle(_)) => Ordering::Greater,
             (Value::Object(_, _), Value::Intrinsic(_)) => Ordering::Greater,
+            (Value::Object(_, _), Value::Map(_, _)) => Ordering::Greater,
             (Value::Object(_, _), Value::Nil) => Ordering::Greater,
             (Value::Object(lt, lv), Value::Object(rt, rv)) => match lt.cmp(&rt) {
-                Ordering::Equal => store.get(lv).compare(store.get(rv), store),
+                Ordering::Equal => store.get(lv).compare_in(store.get(rv), store, parents),
                 o => o,
             },
             (Value::Object(_, _), _) => Ordering::Less,
 
+            (Value::Ref(_), Value::Byte(_)) => Ordering::Greater,
+            (Value::Ref(_), Value::Bytes(_, _)) => Ordering::Greater,
+            (Value::Ref(_), Value::Closure(_)) => Ordering::Greater,
+            (Value::Ref(_), Value::Cons(_, _)) => Ordering::Greater,
+            (Value::Ref(_), Value::Continuation(_)) => Ordering::Greater,
+            (Value::Ref(_), Value::Fixnum(_)) => Ordering::Greater,
+            (Value::Ref(_), Value::Handle(_)) => Ordering::Greater,
+            (Value::Ref(_), Value::Intrinsic(_)) => Ordering::Greater,
+            (Value::Ref(_), Value::Map(_, _)) => Ordering::Greater,
+            (Value::Ref(_), Value::Nil) => Ordering::Greater,
+            (Value::Ref(_), Value::Object(_, _)) => Ordering::Greater,
+            (Value::Ref(l), Value::Ref(r)) => {
+                let l: usize = l.into();
+                let r: usize = r.into();
+                l.cmp(&r)
+            }
+            (Value::Ref(_), _) => Ordering::Less,
+
             (Value::String(_, _), Value::Byte(_)) => Ordering::Greater,
             (Value::String(_, _), Value::Bytes(_, _)) => Ordering::Greater,
             (Value::String(_, _), Value::Closure(_)) => Ordering::Greater,
             (Value::String(_, _), Value::Cons(_, _)) => Ordering::Greater,
+            (Value::String(_, _), Value::Continuation(_)) => Ordering::Greater,
             (Value::String(_, _), Value::Fixnum(_)) => Ordering::Greater,
+            (Value::String(_, _), Value::Handle(_)) => Ordering::Greater,
             (Value::String(_, _), Value::Intrinsic(_)) => Ordering::Greater,
+            (Value::String(_, _), Value::Map(_, _)) => Ordering::Greater,
             (Value::String(_, _), Value::Nil) => Ordering::Greater,
             (Value::String(_, _), Value::Object(_, _)) => Ordering::Greater,
+            (Value::String(_, _), Value::Ref(_)) => Ordering::Greater,
             (Value::String(la, ln), Value::String(ra, rn)) => {
                 store.get_str(la, ln).cmp(&store.get_str(ra, rn))
             }
@@ -180,10 +312,14 @@ impl Value {
             (Value::Symbol(_), Value::Bytes(_, _)) => Ordering::Greater,
             (Value::Symbol(_), Value::Closure(_)) => Ordering::Greater,
             (Value::Symbol(_), Value::Cons(_, _)) => Ordering::Greater,
+            (Value::Symbol(_), Value::Continuation(_)) => Ordering::Greater,
             (Value::Symbol(_), Value::Fixnum(_)) => Ordering::Greater,
+            (Value::Symbol(_), Value::Handle(_)) => Ordering::Greater,
             (Value::Symbol(_), Value::Intrinsic(_)) => Ordering::Greater,
+            (Value::Symbol(_), Value::Map(_, _)) => Ordering::Greater,
             (Value::Symbol(_), Value::Nil) => Ordering::Greater,
             (Value::Symbol(_), Value::Object(_, _)) => Ordering::Greater,
+            (Value::Symbol(_), Value::Ref(_)) => Ordering::Greater,
             (Value::Symbol(_), Value::String(_, _)) => Ordering::Greater,
             (Value::Symbol(l), Value::Symbol(r)) => l.as_str().cmp(&r.as_str()),
             (Value::Symbol(_), _) => Ordering::Less,
@@ -192,32 +328,48 @@ impl Value {
             (Value::Vector(_, _), Value::Bytes(_, _)) => Ordering::Greater,
             (Value::Vector(_, _), Value::Closure(_)) => Ordering::Greater,
             (Value::Vector(_, _), Value::Cons(_, _)) => Ordering::Greater,
+            (Value::Vector(_, _), Value::Continuation(_)) => Ordering::Greater,
             (Value::Vector(_, _), Value::Fixnum(_)) => Ordering::Greater,
+            (Value::Vector(_, _), Value::Handle(_)) => Ordering::Greater,
             (Value::Vector(_, _), Value::Intrinsic(_)) => Ordering::Greater,
+            (Value::Vector(_, _), Value::Map(_, _)) => Ordering::Greater,
             (Value::Vector(_, _), Value::Nil) => Ordering::Greater,
             (Value::Vector(_, _), Value::Object(_, _)) => Ordering::Greater,
+            (Value::Vector(_, _), Value::Ref(_)) => Ordering::Greater,
             (Value::Vector(_, _), Value::String(_, _)) => Ordering::Greater,
             (Value::Vector(_, _), Value::Symbol(_)) => Ordering::Greater,
             (Value::Vector(la, ln), Value::Vector(ra, rn)) => {
+                if parents.contains(&(self, other)) {
+                    return Ordering::Equal;
+                }
                 let l = store.get_vec(la, ln);
                 let r = store.get_vec(ra, rn);
 
-                let len = min(l.len(), r.len());
-                for i in 0..len {
-                    match l[i].compare(r[i], store) {
-                        Ordering::Equal => continue,
-                        o => return o,
-                    }
-                }
-                l.len().cmp(&r.len())
+                parents.push((self, other));
+                let o = l.iter()
+                    .zip(&r)
+                    .map(|(&l, &r)| l.compare_in(r, store, parents))
+                    .find(|&o| o != Ordering::Equal)
+                    .unwrap_or_else(|| ln.cmp(&rn));
+                parents.pop();
+                o
             }
         }
     }
 
     /// Determines if two values are "deeply" equal. Note that this may take
-    /// arbitrarily long, or even not terminate (for cyclic structures).
+    /// arbitrarily long. References are only equal to themselves, and NaN is
+    /// equal to itself. As in `compare`, cyclic vectors are equal if they
+    /// don't differ before their cycles repeat.
     pub fn equals(self, other: Value, store: &Store) -> bool {
+        self.equals_in(other, store, &mut Vec::new())
+    }
+
+    /// Determines if two values are equal, where `parents` holds the pairs of
+    /// vectors that contain them.
+    fn equals_in(self, other: Value, store: &Store, parents: &mut Vec<(Value, Value)>) -> bool {
         match (self, other) {
+            (Value::Bignum(l), Value::Bignum(r)) => store.get_bignum(l) == store.get_bignum(r),
             (Value::Byte(l), Value::Byte(r)) => l == r,
             (Value::Bytes(la, ln), Value::Bytes(ra, rn)) => if ln == rn {
                 store.get_bytes(la, ln) == store.get_bytes(ra, rn)
@@ -230,14 +382,26 @@ impl Value {
                 let lt = store.get(lt);
                 let rh = store.get(rh);
                 let rt = store.get(rt);
-                lh.equals(rh, store) && lt.equals(rt, store)
+                lh.equals_in(rh, store, parents) && lt.equals_in(rt, store, parents)
             }
+            (Value::Continuation(l), Value::Continuation(r)) => l == r,
             (Value::Fixnum(l), Value::Fixnum(r)) => l == r,
+            (Value::Float(l), Value::Float(r)) => l == r || (l.is_nan() && r.is_nan()),
+            (Value::Handle(l), Value::Handle(r)) => l == r,
             (Value::Intrinsic(l), Value::Intrinsic(r)) => l == r,
+            (Value::Map(la, ln), Value::Map(ra, rn)) => {
+                ln == rn && store.get_map(la).into_iter().all(|(k, lv)| {
+                    store
+                        .map_get(ra, k)
+                        .map(|rv| lv.equals_in(rv, store, parents))
+                        .unwrap_or(false)
+                })
+            }
             (Value::Nil, Value::Nil) => true,
             (Value::Object(lt, lv), Value::Object(rt, rv)) => {
-                lt == rt && store.get(lv).equals(store.get(rv), store)
+                lt == rt && store.get(lv).equals_in(store.get(rv), store, parents)
             }
+            (Value::Ref(l), Value::Ref(r)) => l == r,
             (Value::String(la, ln), Value::String(ra, rn)) => if ln == rn {
                 store.get_str(la, ln) == store.get_str(ra, rn)
             } else {
@@ -245,21 +409,201 @@ impl Value {
             },
 
             (Value::Symbol(l), Value::Symbol(r)) => l == r,
-            (Value::Vector(la, ln), Value::Vector(ra, rn)) => if ln == rn {
-                let l = store.get_vec(la, ln);
-                let r = store.get_vec(ra, rn);
-                for i in 0..ln {
-                    if !l[i].equals(r[i], store) {
-                        return false;
-                    }
-                }
+            (Value::Vector(la, ln), Value::Vector(ra, rn)) => if ln != rn {
+                false
+            } else if parents.contains(&(self, other)) {
                 true
             } else {
-                false
+                let l = store.get_vec(la, ln);
+                let r = store.get_vec(ra, rn);
+
+                parents.push((self, other));
+                let eq = l.iter()
+                    .zip(&r)
+                    .all(|(&l, &r)| l.equals_in(r, store, parents));
+                parents.pop();
+                eq
             },
             _ => false,
         }
     }
+
+    /// Returns whether a value contains a vector or bytes, which can be mutated
+    /// in place. The contents of references aren't looked at, since
+    /// references are compared by identity.
+    pub fn contains_mutable(self, store: &Store) -> bool {
+        match self {
+            Value::Bytes(_, _) | Value::Vector(_, _) => true,
+            Value::Cons(h, t) => {
+                store.get(h).contains_mutable(store) || store.get(t).contains_mutable(store)
+            }
+            Value::Map(a, _) => store
+                .map_entries(a)
+                .into_iter()
+                .any(|(k, v)| k.contains_mutable(store) || v.contains_mutable(store)),
+            Value::Object(_, v) => store.get(v).contains_mutable(store),
+            Value::Bignum(_)
+            | Value::Byte(_)
+            | Value::Closure(_)
+            | Value::Continuation(_)
+            | Value::Fixnum(_)
+            | Value::Float(_)
+            | Value::Handle(_)
+            | Value::Intrinsic(_)
+            | Value::Nil
+            | Value::Ref(_)
+            | Value::String(_, _)
+            | Value::Symbol(_) => false,
+        }
+    }
+
+    /// Returns whether the hash of a value depends on the addresses of values
+    /// it contains, which change when the garbage collector moves them.
+    pub fn hashes_by_address(self, store: &Store) -> bool {
+        self.hashes_by_address_in(store, false)
+    }
+
+    /// Returns whether the hash of a value depends on addresses, following
+    /// `hash_in` in only looking at the lengths of vectors inside vectors.
+    fn hashes_by_address_in(self, store: &Store, in_vector: bool) -> bool {
+        match self {
+            Value::Closure(_) | Value::Continuation(_) | Value::Handle(_) | Value::Ref(_) => true,
+            Value::Cons(h, t) => {
+                store.get(h).hashes_by_address_in(store, in_vector)
+                    || store.get(t).hashes_by_address_in(store, in_vector)
+            }
+            Value::Map(a, _) => store.map_entries(a).into_iter().any(|(k, v)| {
+                k.hashes_by_address_in(store, in_vector) || v.hashes_by_address_in(store, in_vector)
+            }),
+            Value::Object(_, v) => store.get(v).hashes_by_address_in(store, in_vector),
+            Value::Vector(a, l) => {
+                !in_vector
+                    && store
+                        .get_vec(a, l)
+                        .into_iter()
+                        .any(|v| v.hashes_by_address_in(store, true))
+            }
+            Value::Bignum(_)
+            | Value::Byte(_)
+            | Value::Bytes(_, _)
+            | Value::Fixnum(_)
+            | Value::Float(_)
+            | Value::Intrinsic(_)
+            | Value::Nil
+            | Value::String(_, _)
+            | Value::Symbol(_) => false,
+        }
+    }
+
+    /// Hashes a value consistently with `equals`. Values that are only equal
+    /// to themselves (closures, continuations, handles, and references) are
+    /// hashed by their addresses, so their hashes change when the garbage
+    /// collector moves them; it rebuilds the maps they're keys of. Intrinsics
+    /// are hashed by their type alone, since their addresses differ between
+    /// runs that share an image. Vectors may be cyclic, so vectors inside
+    /// vectors are hashed by their lengths alone.
+    pub fn hash<H: Hasher>(self, store: &Store, state: &mut H) {
+        self.hash_in(store, state, false)
+    }
+
+    /// Hashes a value, which is inside a vector if `in_vector` is true.
+    fn hash_in<H: Hasher>(self, store: &Store, state: &mut H, in_vector: bool) {
+        match self {
+            Value::Bignum(a) => {
+                14u8.hash(state);
+                store.get_bignum(a).hash(state);
+            }
+            Value::Byte(n) => {
+                0u8.hash(state);
+                n.hash(state);
+            }
+            Value::Bytes(a, l) => {
+                1u8.hash(state);
+                store.get_bytes(a, l).hash(state);
+            }
+            Value::Closure(a) => {
+                2u8.hash(state);
+                Into::<usize>::into(a).hash(state);
+            }
+            Value::Cons(h, t) => {
+                3u8.hash(state);
+                store.get(h).hash_in(store, state, in_vector);
+                store.get(t).hash_in(store, state, in_vector);
+            }
+            Value::Continuation(a) => {
+                4u8.hash(state);
+                Into::<usize>::into(a).hash(state);
+            }
+            Value::Fixnum(n) => {
+                5u8.hash(state);
+                n.hash(state);
+            }
+            Value::Float(n) => {
+                // Zero and NaN have multiple representations, which must
+                // all hash the same.
+                15u8.hash(state);
+                let n = if n == 0.0 {
+                    0.0
+                } else if n.is_nan() {
+                    f64::NAN
+                } else {
+                    n
+                };
+                n.to_bits().hash(state);
+            }
+            Value::Handle(a) => {
+                16u8.hash(state);
+                Into::<usize>::into(a).hash(state);
+            }
+            Value::Intrinsic(_) => 6u8.hash(state),
+            Value::Map(a, l) => {
+                // Equal maps may have their entries in different orders, so
+                // each entry is hashed on its own, and the hashes are summed.
+                7u8.hash(state);
+                l.hash(state);
+                let sum = store.map_entries(a).into_iter().fold(0u64, |sum, (k, v)| {
+                    let mut hasher = KeyHasher::default();
+                    k.hash_in(store, &mut hasher, in_vector);
+                    v.hash_in(store, &mut hasher, in_vector);
+                    sum.wrapping_add(hasher.finish())
+                });
+                sum.hash(state);
+            }
+            Value::Nil => 8u8.hash(state),
+            Value::Object(t, v) => {
+                9u8.hash(state);
+                t.as_str().hash(state);
+                store.get(v).hash_in(store, state, in_vector);
+            }
+            Value::Ref(a) => {
+                10u8.hash(state);
+                Into::<usize>::into(a).hash(state);
+            }
+            Value::String(a, l) => {
+                11u8.hash(state);
+                store.get_str(a, l).hash(state);
+            }
+            Value::Symbol(s) => {
+                12u8.hash(state);
+                s.as_str().hash(state);
+            }
+            Value::Vector(a, l) => {
+                13u8.hash(state);
+                l.hash(state);
+                if !in_vector {
+                    for v in store.get_vec(a, l) {
+                        v.hash_in(store, state, true);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Compares two floats, ordering NaN after all other values.
+fn compare_floats(l: f64, r: f64) -> Ordering {
+    l.partial_cmp(&r)
+        .unwrap_or_else(|| l.is_nan().cmp(&r.is_nan()))
 }
 
 struct DisplayValue<'store, 'program: 'store> {
@@ -270,7 +614,44 @@ struct DisplayValue<'store, 'program: 'store> {
 
 impl<'store, 'program: 'store> Display for DisplayValue<'store, 'program> {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
-        match self.value {
+        self.write(self.value, self.printlike, &mut Vec::new(), fmt)
+    }
+}
+
+impl<'store, 'program: 'store> DisplayValue<'store, 'program> {
+    /// Writes a value. Refs and vectors can be mutated to contain themselves,
+    /// so `parents` holds the refs and vectors that contain the value, and a
+    /// value that contains itself is written as `<<cycle>>` the second time.
+    fn write(
+        &self,
+        value: Value,
+        printlike: bool,
+        parents: &mut Vec<Value>,
+        fmt: &mut Formatter,
+    ) -> FmtResult {
+        match value {
+            Value::Ref(_) | Value::Vector(_, _) => {
+                if parents.contains(&value) {
+                    return write!(fmt, "<<cycle>>");
+                }
+                parents.push(value);
+                let r = self.write_inner(value, printlike, parents, fmt);
+                parents.pop();
+                r
+            }
+            _ => self.write_inner(value, printlike, parents, fmt),
+        }
+    }
+
+    fn write_inner(
+        &self,
+        value: Value,
+        printlike: bool,
+        parents: &mut Vec<Value>,
+        fmt: &mut Formatter,
+    ) -> FmtResult {
+        match value {
+            Value::Bignum(a) => write!(fmt, "{}", self.store.get_bignum(a)),
             Value::Byte(n) => write!(fmt, "{}", n),
             Value::Bytes(a, l) => escape_bytes(self.store.get_bytes(a, l), fmt),
             Value::Closure(a) => {
@@ -281,35 +662,59 @@ impl<'store, 'program: 'store> Display for DisplayValue<'store, 'program> {
                 }
             }
             Value::Cons(h, t) => {
-                write!(fmt, "({}", self.store.get(h).display(self.store, false))?;
+                write!(fmt, "(")?;
+                self.write(self.store.get(h), false, parents, fmt)?;
                 let mut l = self.store.get(t);
                 loop {
                     match l {
                         Value::Cons(h, t) => {
-                            let h = self.store.get(h).display(self.store, false);
-                            write!(fmt, " {}", h)?;
+                            write!(fmt, " ")?;
+                            self.write(self.store.get(h), false, parents, fmt)?;
                             l = self.store.get(t);
                         }
                         Value::Nil => break,
                         _ => {
-                            write!(fmt, " | {}", l.display(self.store, false))?;
+                            write!(fmt, " | ")?;
+                            self.write(l, false, parents, fmt)?;
                             break;
                         }
                     }
                 }
                 write!(fmt, ")")
             }
+            Value::Continuation(_) => write!(fmt, "<<continuation>>"),
             Value::Fixnum(n) => write!(fmt, "{}", n),
+            Value::Float(n) => write_float(n, fmt),
+            Value::Handle(_) => write!(fmt, "<<handle>>"),
             Value::Intrinsic(i) => write!(fmt, "<<function {}>>", i),
+            Value::Map(a, _) => {
+                write!(fmt, "{{")?;
+                let mut first = true;
+                for (k, v) in self.store.get_map(a) {
+                    if first {
+                        first = false;
+                    } else {
+                        write!(fmt, ", ")?;
+                    }
+                    self.write(k, false, parents, fmt)?;
+                    write!(fmt, " ")?;
+                    self.write(v, false, parents, fmt)?;
+                }
+                write!(fmt, "}}")
+            }
             Value::Nil => write!(fmt, "()"),
-            Value::Object(t, v) => write!(
-                fmt,
-                "<<{} {}>>",
-                t,
-                self.store.get(v).display(self.store, false)
-            ),
+            Value::Object(t, v) => {
+                write!(fmt, "<<{} ", t)?;
+                self.write(self.store.get(v), false, parents, fmt)?;
+                write!(fmt, ">>")
+            }
+            Value::Ref(a) => {
+                write!(fmt, "<<ref ")?;
+                self.write(self.store.get(a), false, parents, fmt)?;
+                write!(fmt, ">>")
+            }
             Value::String(a, l) => {
-                if self.printlike {
+                if printlike {
                     write!(fmt, "{}", self.store.get_str(a, l))
                 } else {
                     escape_str(self.store.get_str(a, l), fmt)
@@ -325,7 +730,7 @@ impl<'store, 'program: 'store> Display for DisplayValue<'store, 'program> {
                     } else {
                         write!(fmt, " ")?;
                     }
-                    write!(fmt, "{}", v.display(self.store, false))?;
+                    self.write(v, false, parents, fmt)?;
                 }
                 write!(fmt, "]")
             }
@@ -7,19 +7,28 @@ macro_rules! __intrinsics_count {
 }
 
 /// Given an argument variable and an arguments specification, ensures that the
-/// arguments match the specification, returning the number of fixed arguments.
-///
-/// TODO: Return a real error.
+/// arguments match the specification, returning an error if they don't.
 macro_rules! __intrinsics_argn {
-    // These two cases are just to silence a warning.
-    ($name:ident, $args_var:ident,) => {};
-    ($name:ident, $args_var:ident, *$rest:ident) => {};
+    // This case is just to silence a warning.
+    ($args_var:ident, *$rest:ident) => {};
 
-    ($name:ident, $args_var:ident, $($args:ident),*) => {
-        assert_eq!($args_var.len(), __intrinsics_count!($($args)*), concat!("bad argn to ", stringify!($name)));
+    ($args_var:ident, $($args:ident),*) => {
+        if $args_var.len() != __intrinsics_count!($($args)*) {
+            runtime_error!($crate::interpreter::RuntimeError::ArgnMismatch(
+                __intrinsics_name!().to_string(),
+                __intrinsics_count!($($args)*),
+                $args_var.len(),
+            ));
+        }
     };
-    ($name:ident, $args_var:ident, $($args:ident,)* *$rest:ident) => {
-        assert!($args_var.len() >= __intrinsics_count!($($args)*), concat!("bad argn to ", stringify!($name)));
+    ($args_var:ident, $($args:ident,)* *$rest:ident) => {
+        if $args_var.len() < __intrinsics_count!($($args)*) {
+            runtime_error!($crate::interpreter::RuntimeError::TooFewArgs(
+                __intrinsics_name!().to_string(),
+                __intrinsics_count!($($args)*),
+                $args_var.len(),
+            ));
+        }
     };
 }
 
@@ -75,8 +84,14 @@ macro_rules! __intrinsics_mod {
             $konts: $crate::std::vec::Vec<$crate::interpreter::Kont<'program>>,
         ) -> $crate::interpreter::State<'program> {
             #[allow(unused_macros)]
-            macro_rules! __intrinsics_name { () => (stringify!($name)) }
-            __intrinsics_argn!($name, _args, $($args)*);
+            macro_rules! __intrinsics_name {
+                () => (concat!($mod_name, ":", stringify!($name)))
+            }
+            #[allow(unused_macros)]
+            macro_rules! __intrinsics_store { () => ($store) }
+            #[allow(unused_macros)]
+            macro_rules! __intrinsics_konts { () => ($konts) }
+            __intrinsics_argn!(_args, $($args)*);
             __intrinsics_args!(_args, $($args)*);
             #[allow(unreachable_code)]
             $crate::interpreter::eval::kontinue($body, $store, $konts)
@@ -159,12 +174,67 @@ macro_rules! intrinsics {
     }
 }
 
+/// Returns the description of the type of values of the given variant, for
+/// use in type errors.
+macro_rules! __intrinsics_type_name {
+    (Bignum) => ("a bignum");
+    (Byte) => ("a byte");
+    (Bytes) => ("bytes");
+    (Closure) => ("a closure");
+    (Cons) => ("a cons");
+    (Fixnum) => ("a fixnum");
+    (Float) => ("a float");
+    (Handle) => ("a handle");
+    (Intrinsic) => ("an intrinsic");
+    (Map) => ("a map");
+    (Object) => ("an object");
+    (Ref) => ("a reference");
+    (String) => ("a string");
+    (Symbol) => ("a symbol");
+    (Vector) => ("a vector");
+}
+
+/// Returns from an intrinsic with the given runtime error.
+macro_rules! runtime_error {
+    ($err:expr) => {{
+        let err = $err;
+        return $crate::interpreter::eval::error(
+            err,
+            __intrinsics_store!(),
+            __intrinsics_konts!(),
+        );
+    }};
+}
+
+/// Unwraps a `Result`, returning from an intrinsic with its runtime error if
+/// it's an `Err`.
+macro_rules! try_runtime {
+    ($result:expr) => {
+        match $result {
+            Ok(val) => val,
+            Err(err) => runtime_error!(err),
+        }
+    };
+}
+
+/// Returns from an intrinsic with a type error, given a description of the
+/// expected type and the value that was found instead.
+macro_rules! type_error {
+    ($expected:expr, $found:expr) => {
+        runtime_error!($crate::interpreter::RuntimeError::TypeError(
+            __intrinsics_name!(),
+            $expected,
+            $found.display(__intrinsics_store!(), false).to_string(),
+        ))
+    };
+}
+
 macro_rules! typeck_name {
     ($($id:ident as Value :: $variant:ident $args:tt),*) => {
         $(let $id = if let Value::$variant $args = $id {
             $args
         } else {
-            unimplemented!("TODO typeck_var failed in {}", __intrinsics_name!())
+            type_error!(__intrinsics_type_name!($variant), $id)
         };)*
     };
 }
@@ -4,9 +4,14 @@
 mod macros;
 
 use std::cmp::Ordering;
-use std::process::exit;
+use std::fs::{File, FileType};
+use std::io::{stderr, stdout, ErrorKind, Read, Result as IoResult, Write};
 
-use interpreter::{Addr, Kont, Store, Value};
+use num::{BigInt, FromPrimitive, Signed};
+
+use interpreter::{Addr, Captured, Handle, HandleState, Kont, RuntimeError, Store, Value};
+use parser::{parse_float, parse_integer, parse_integer_radix};
+use util::bignum_to_float;
 use {parse_file, Literal};
 
 fn boolify(b: bool) -> Value {
@@ -17,15 +22,192 @@ fn boolify(b: bool) -> Value {
     }
 }
 
-fn print_values(store: &Store, values: &[Value], printlike: bool) {
+/// Converts a fixnum to an exit code, if it fits in one.
+fn exit_code(n: isize) -> Option<i32> {
+    if n < i32::MIN as isize || n > i32::MAX as isize {
+        None
+    } else {
+        Some(n as i32)
+    }
+}
+
+/// Applies an arithmetic operation to two integers, as fixnums if the result
+/// fits in one, or as bignums otherwise.
+fn integer_op<F, G>(
+    name: &'static str,
+    l: Value,
+    r: Value,
+    store: &mut Store,
+    fixnum: F,
+    bignum: G,
+) -> Result<Value, RuntimeError>
+where
+    F: FnOnce(isize, isize) -> Option<isize>,
+    G: FnOnce(BigInt, BigInt) -> BigInt,
+{
+    if let (Value::Fixnum(l), Value::Fixnum(r)) = (l, r) {
+        if let Some(n) = fixnum(l, r) {
+            return Ok(Value::Fixnum(n));
+        }
+    }
+    let l = to_bigint(name, l, store)?;
+    let r = to_bigint(name, r, store)?;
+    Ok(store.store_bignum(bignum(l, r)))
+}
+
+/// Performs an arithmetic operation on two numbers as floats.
+fn float_op<F>(
+    name: &'static str,
+    l: Value,
+    r: Value,
+    store: &Store,
+    op: F,
+) -> Result<Value, RuntimeError>
+where
+    F: FnOnce(f64, f64) -> f64,
+{
+    let l = to_float(name, l, store)?;
+    let r = to_float(name, r, store)?;
+    Ok(Value::Float(op(l, r)))
+}
+
+fn is_integer(val: Value) -> bool {
+    matches!(val, Value::Bignum(_) | Value::Fixnum(_))
+}
+
+fn is_number(val: Value) -> bool {
+    matches!(val, Value::Bignum(_) | Value::Fixnum(_) | Value::Float(_))
+}
+
+/// Converts an `Option` to the `(some . x)` or `none` convention used by
+/// `ministd`.
+fn option(val: Option<Value>, store: &mut Store) -> Value {
+    match val {
+        Some(val) => {
+            let some = store.store(Value::Symbol("some".into()));
+            let val = store.store(val);
+            Value::Cons(some, val)
+        }
+        None => Value::Symbol("none".into()),
+    }
+}
+
+/// Converts an integer to a bignum, or returns a type error for the intrinsic
+/// with the given name.
+fn to_bigint(name: &'static str, val: Value, store: &Store) -> Result<BigInt, RuntimeError> {
+    match val {
+        Value::Bignum(a) => Ok(store.get_bignum(a).clone()),
+        Value::Fixnum(n) => Ok(BigInt::from(n as i64)),
+        _ => Err(RuntimeError::TypeError(
+            name,
+            "an integer",
+            val.display(store, false).to_string(),
+        )),
+    }
+}
+
+/// Converts a number to a float, or returns a type error for the intrinsic
+/// with the given name.
+fn to_float(name: &'static str, val: Value, store: &Store) -> Result<f64, RuntimeError> {
+    match val {
+        Value::Bignum(a) => Ok(bignum_to_float(store.get_bignum(a))),
+        Value::Fixnum(n) => Ok(n as f64),
+        Value::Float(n) => Ok(n),
+        _ => Err(RuntimeError::TypeError(
+            name,
+            "a number",
+            val.display(store, false).to_string(),
+        )),
+    }
+}
+
+/// Converts a `Result` to the `(ok . x)` or `(err . e)` convention used by
+/// `ministd`.
+fn result(val: Result<Value, Value>, store: &mut Store) -> Value {
+    let (tag, val) = match val {
+        Ok(val) => ("ok", val),
+        Err(val) => ("err", val),
+    };
+    let tag = store.store(Value::Symbol(tag.into()));
+    let val = store.store(val);
+    Value::Cons(tag, val)
+}
+
+/// Checks that a value is a fixnum that can be used as a radix, returning it.
+fn radix(val: Value) -> Option<u32> {
+    match val {
+        Value::Fixnum(n @ 2..=36) => Some(n as u32),
+        _ => None,
+    }
+}
+
+fn print_values<W: Write>(
+    w: &mut W,
+    store: &Store,
+    values: &[Value],
+    printlike: bool,
+    newline: bool,
+) -> IoResult<()> {
     let mut first = true;
     for val in values {
         if first {
             first = false;
         } else if printlike {
-            print!(" ");
+            write!(w, " ")?;
         }
-        print!("{}", val.display(store, printlike));
+        write!(w, "{}", val.display(store, printlike))?;
+    }
+    if newline {
+        writeln!(w)?;
+    }
+    Ok(())
+}
+
+/// Returns the symbol used to describe a file type, e.g. in directory listings.
+fn file_type_name(t: FileType) -> &'static str {
+    if t.is_dir() {
+        "dir"
+    } else if t.is_symlink() {
+        "symlink"
+    } else {
+        "file"
+    }
+}
+
+/// Converts the result of a filesystem operation to a `ministd` result. The
+/// error is a pair of a symbol naming the kind of error (e.g. `not-found`) and
+/// a message describing it.
+fn io_result(val: Result<Value, (ErrorKind, String)>, store: &mut Store) -> Value {
+    let val = val.map_err(|(kind, msg)| {
+        let kind = match kind {
+            ErrorKind::AlreadyExists => "already-exists",
+            ErrorKind::Interrupted => "interrupted",
+            ErrorKind::InvalidData => "invalid-data",
+            ErrorKind::InvalidInput => "invalid-input",
+            ErrorKind::NotFound => "not-found",
+            ErrorKind::PermissionDenied => "permission-denied",
+            ErrorKind::UnexpectedEof => "unexpected-eof",
+            _ => "other",
+        };
+        let kind = store.store(Value::Symbol(kind.into()));
+        let (a, l) = store.store_str(&msg);
+        let msg = store.store(Value::String(a, l));
+        Value::Cons(kind, msg)
+    });
+    result(val, store)
+}
+
+/// Runs an I/O operation on the file behind a handle, returning a message
+/// describing the failure if the operation fails or the handle isn't an open
+/// file.
+fn with_file<T, F>(store: &mut Store, handle: Addr<Handle>, f: F) -> Result<T, String>
+where
+    F: FnOnce(&mut File) -> IoResult<T>,
+{
+    match *store.get_handle(handle) {
+        HandleState::File(ref mut file) => f(file).map_err(|err| err.to_string()),
+        HandleState::Closed => Err("The handle is closed".to_string()),
+        _ => Err("The handle isn't to a file".to_string()),
     }
 }
 
@@ -43,21 +225,33 @@ intrinsics! {
             if lst == Value::Nil {
                 return ::interpreter::eval::apply(func, args, store, konts)
             } else {
-                panic!("type error in apply (not a list)")
+                type_error!("a list", lst)
             }
         }
 
+        fn call_cc[store, konts](func) {
+            let cont = store.store_continuation(Captured::Full(konts.clone()));
+            return ::interpreter::eval::apply(func, vec![Value::Continuation(cont)], store, konts)
+        }
+
+        fn call_ec[store, konts](func) {
+            let cont = store.store_continuation(Captured::Escape);
+            let mut konts = konts;
+            konts.push(Kont::Escape(cont));
+            return ::interpreter::eval::apply(func, vec![Value::Continuation(cont)], store, konts)
+        }
+
         fn car[store, _k](l) {
             match l {
                 Value::Cons(h, _) => store.get(h),
-                _ => unimplemented!("Can't take car of {}", l.display(store, false))
+                _ => type_error!("a cons", l),
             }
         }
 
         fn cdr[store, _k](l) {
             match l {
                 Value::Cons(_, t) => store.get(t),
-                _ => unimplemented!("Can't take cdr of {}", l.display(store, false))
+                _ => type_error!("a cons", l),
             }
         }
 
@@ -77,15 +271,27 @@ intrinsics! {
 
         fn eq_num[store, _k](l, r) {
             boolify(match (l, r) {
+                (Value::Byte(l), Value::Float(r)) => f64::from(l) == r,
+                (Value::Float(l), Value::Byte(r)) => l == f64::from(r),
+                (Value::Float(_), _) | (_, Value::Float(_)) if is_number(l) && is_number(r) => {
+                    let l = try_runtime!(to_float(__intrinsics_name!(), l, store));
+                    let r = try_runtime!(to_float(__intrinsics_name!(), r, store));
+                    l == r
+                }
+                (Value::Bignum(l), Value::Bignum(r)) => store.get_bignum(l) == store.get_bignum(r),
+                (Value::Bignum(_), Value::Byte(_))
+                | (Value::Bignum(_), Value::Fixnum(_))
+                | (Value::Byte(_), Value::Bignum(_))
+                | (Value::Fixnum(_), Value::Bignum(_)) => false,
                 (Value::Byte(l), Value::Byte(r)) => l == r,
                 (Value::Byte(l), Value::Fixnum(r)) => l as isize == r,
                 (Value::Fixnum(l), Value::Byte(r)) => l == r as isize,
                 (Value::Fixnum(l), Value::Fixnum(r)) => l == r,
-                _ => panic!(
-                    "type error in (eq_num {} {})",
-                    l.display(store, false),
-                    r.display(store, false),
-                ),
+                (Value::Bignum(_), _)
+                | (Value::Byte(_), _)
+                | (Value::Fixnum(_), _)
+                | (Value::Float(_), _) => type_error!("a number", r),
+                _ => type_error!("a number", l),
             })
         }
 
@@ -111,19 +317,41 @@ intrinsics! {
             l
         }
 
-        fn panic[store, _k](msg) {
+        fn panic[store, konts](msg) {
+            // Panicking with `(exit . n)` or `exit` predates process:exit, and
+            // does the same thing.
             if let Value::Cons(hd, tl) = msg {
                 if store.get(hd) == Value::Symbol("exit".into()) {
                     if let Value::Fixnum(n) = store.get(tl) {
-                        // TODO: When this gets more Erlang-like, the behavior here will need to
-                        // change.
-                        exit(n as i32);
+                        match exit_code(n) {
+                            Some(code) => runtime_error!(RuntimeError::Exit(code)),
+                            None => runtime_error!(RuntimeError::OutOfBounds(
+                                __intrinsics_name!(),
+                                format!("{} isn't a valid exit code", n),
+                            )),
+                        }
                     }
                 }
             } else if msg == Value::Symbol("exit".into()) {
-                exit(0);
+                runtime_error!(RuntimeError::Exit(0))
             }
-            panic!("{}", msg.display(store, false))
+            // A handler gets the list `(panic msg)`.
+            let err = RuntimeError::Panic(msg.display(store, false).to_string());
+            let nil = store.store(Value::Nil);
+            let tl = Value::Cons(store.store(msg), nil);
+            let val = Value::Cons(store.store(Value::Symbol("panic".into())), store.store(tl));
+            return ::interpreter::eval::raise(val, err, store, konts)
+        }
+
+        fn raise[store, konts](val) {
+            let err = RuntimeError::Uncaught(val.display(store, false).to_string());
+            return ::interpreter::eval::raise(val, err, store, konts)
+        }
+
+        fn with_handler[store, konts](handler, body) {
+            let mut konts = konts;
+            konts.push(Kont::Handler(handler));
+            return ::interpreter::eval::apply(body, Vec::new(), store, konts)
         }
     }
 
@@ -172,75 +400,81 @@ intrinsics! {
     mod "bytes" as bytes {
         fn append[store, _k](l, r) {
             // TODO: Make this more optimized.
-            match (l, r) {
-                (Value::Bytes(la, ll), Value::Bytes(ra, rl)) => {
-                    let mut vals = store.get_bytes(la, ll).to_owned();
-                    vals.extend(store.get_bytes(ra, rl));
-                    let (a, l) = store.store_bytes(&vals);
-                    Value::Bytes(a, l)
-                }
-                _ => unimplemented!("TODO type error in bytes-append")
-            }
+            typeck_name!(l as Value::Bytes(la, ll), r as Value::Bytes(ra, rl));
+            let mut vals = store.get_bytes(l.0, l.1).to_owned();
+            vals.extend(store.get_bytes(r.0, r.1));
+            let (a, l) = store.store_bytes(&vals);
+            Value::Bytes(a, l)
         }
 
         fn length[_s, _k](s) {
-            if let Value::Bytes(_, l) = s {
-                Value::Fixnum(l as isize)
+            typeck_name!(s as Value::Bytes(a, l));
+            Value::Fixnum(s.1 as isize)
+        }
+
+        fn nth[store, _k](n, s) {
+            typeck_name!(n as Value::Fixnum(n), s as Value::Bytes(a, l));
+            let (a, l) = s;
+            if n >= 0 && (n as usize) < l {
+                Value::Byte(store.get_bytes(a, l)[n as usize])
             } else {
-                unimplemented!("TODO Type Error in bytes-length")
+                runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!("index {} on bytes of length {}", n, l),
+                ))
             }
         }
 
-        fn nth[store, _k](n, s) {
-            if let (Value::Fixnum(n), Value::Bytes(a, l)) = (n, s) {
-                let n = n as usize;
-                if n < l {
-                    let a: usize = a.into();
-                    store.get_vec_val(Addr::from(a + n))
-                } else {
-                    unimplemented!("TODO out of bounds in bytes-nth")
-                }
+        fn set[store, _k](n, b, s) {
+            typeck_name!(
+                n as Value::Fixnum(n),
+                b as Value::Byte(b),
+                s as Value::Bytes(a, l)
+            );
+            let (a, l) = s;
+            if n >= 0 && (n as usize) < l {
+                let a: usize = a.into();
+                store.mutate_bytes(Addr::from(a + n as usize), b);
+                Value::Nil
             } else {
-                unimplemented!("TODO Type Error in bytes-nth")
+                runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!("index {} on bytes of length {}", n, l),
+                ))
             }
         }
 
         fn slice[_s, _k](start, end, s) {
-            let (a, l) = if let (
-                Value::Fixnum(start),
-                Value::Fixnum(end),
-                Value::Bytes(a, l),
-            ) = (start, end, s)
-            {
-                let start = start as usize;
-                let end = end as usize;
-                if start <= l && end <= l && start <= end {
-                    let a: usize = a.into();
-                    (a + start as usize, end - start)
-                } else {
-                    unimplemented!(
-                        "TODO out of bounds in bytes-slice (bounds of [{}, {}) on bytes of length {})",
-                        start,
-                        end,
-                        l,
-                    )
-                }
+            typeck_name!(
+                start as Value::Fixnum(start),
+                end as Value::Fixnum(end),
+                s as Value::Bytes(a, l)
+            );
+            let (a, l) = s;
+            if 0 <= start && start <= end && end as usize <= l {
+                let a: usize = a.into();
+                Value::Bytes((a + start as usize).into(), (end - start) as usize)
             } else {
-                unimplemented!("TODO Type Error in bytes-slice")
-            };
-
-            Value::Bytes(a.into(), l)
+                runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!("bounds of [{}, {}) on bytes of length {}", start, end, l),
+                ))
+            }
         }
     }
 
     mod "convert" as convert {
+        fn bignum_to_bytes[store, _k](n) {
+            typeck_name!(n as Value::Bignum(n));
+            let bs = store.get_bignum(n).to_signed_bytes_le();
+            let (a, l) = store.store_bytes(&bs);
+            Value::Bytes(a, l)
+        }
+
         fn byte_to_bytes[store, _k](b) {
-            if let Value::Byte(b) = b {
-                let (a, l) = store.store_bytes(&[b]);
-                Value::Bytes(a, l)
-            } else {
-                unimplemented!("TODO Type Error in byte->bytes")
-            }
+            typeck_name!(b as Value::Byte(b));
+            let (a, l) = store.store_bytes(&[b]);
+            Value::Bytes(a, l)
         }
 
         fn byte_to_fixnum[_s, _k](b) {
@@ -248,11 +482,93 @@ intrinsics! {
             Value::Fixnum(b as isize)
         }
 
+        fn bytes_to_fixnum_be[store, _k](bs) {
+            typeck_name!(bs as Value::Bytes(a, l));
+            if bs.1 > 8 {
+                runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!("{} bytes don't fit in a fixnum", bs.1),
+                ))
+            }
+            let n = store
+                .get_bytes(bs.0, bs.1)
+                .iter()
+                .fold(0u64, |n, &b| (n << 8) | u64::from(b));
+            Value::Fixnum(n as i64 as isize)
+        }
+
+        fn bytes_to_fixnum_le[store, _k](bs) {
+            typeck_name!(bs as Value::Bytes(a, l));
+            if bs.1 > 8 {
+                runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!("{} bytes don't fit in a fixnum", bs.1),
+                ))
+            }
+            let n = store
+                .get_bytes(bs.0, bs.1)
+                .iter()
+                .rev()
+                .fold(0u64, |n, &b| (n << 8) | u64::from(b));
+            Value::Fixnum(n as i64 as isize)
+        }
+
+        fn bytes_to_string[store, _k](bs) {
+            typeck_name!(bs as Value::Bytes(a, l));
+            let s = ::std::str::from_utf8(store.get_bytes(bs.0, bs.1))
+                .map(|s| s.to_string())
+                .map_err(|err| err.to_string());
+            let val = match s {
+                Ok(s) => {
+                    let (a, l) = store.store_str(&s);
+                    Ok(Value::String(a, l))
+                }
+                Err(msg) => {
+                    let (a, l) = store.store_str(&msg);
+                    Err(Value::String(a, l))
+                }
+            };
+            result(val, store)
+        }
+
+        fn bytes_to_string_lossy[store, _k](bs) {
+            typeck_name!(bs as Value::Bytes(a, l));
+            let s = String::from_utf8_lossy(store.get_bytes(bs.0, bs.1)).into_owned();
+            let (a, l) = store.store_str(&s);
+            Value::String(a, l)
+        }
+
         fn fixnum_to_byte[_s, _k](n) {
             typeck_name!(n as Value::Fixnum(n));
             Value::Byte(n as u8)
         }
 
+        fn fixnum_to_bytes_be[store, _k](n) {
+            typeck_name!(n as Value::Fixnum(n));
+            let n = n as i64 as u64;
+            let bs = (0..8).rev().map(|i| (n >> (8 * i)) as u8).collect::<Vec<_>>();
+            let (a, l) = store.store_bytes(&bs);
+            Value::Bytes(a, l)
+        }
+
+        fn fixnum_to_bytes_le[store, _k](n) {
+            typeck_name!(n as Value::Fixnum(n));
+            let n = n as i64 as u64;
+            let bs = (0..8).map(|i| (n >> (8 * i)) as u8).collect::<Vec<_>>();
+            let (a, l) = store.store_bytes(&bs);
+            Value::Bytes(a, l)
+        }
+
+        fn float_to_bytes[store, _k](n) {
+            use podio::{LittleEndian, WritePodExt};
+
+            typeck_name!(n as Value::Float(n));
+            let mut bs = Vec::with_capacity(8);
+            bs.write_f64::<LittleEndian>(n).unwrap();
+            let (a, l) = store.store_bytes(&bs);
+            Value::Bytes(a, l)
+        }
+
         fn list_to_vector[store, _k](s) {
             let mut lst = s;
             let mut vec = Vec::new();
@@ -264,7 +580,7 @@ intrinsics! {
                 let (a, l) = store.store_vec(&vec);
                 Value::Vector(a, l)
             } else {
-                unimplemented!("TODO Type Error in list->vector")
+                type_error!("a list", lst)
             }
         }
 
@@ -276,21 +592,15 @@ intrinsics! {
         }
 
         fn string_to_symbol[store, _k](s) {
-            if let Value::String(a, l) = s {
-                // TODO Check the string for validity.
-                Value::Symbol(store.get_str(a, l).into())
-            } else {
-                unimplemented!("TODO Type Error in string->symbol: {}", s.display(store, false))
-            }
+            // TODO Check the string for validity.
+            typeck_name!(s as Value::String(a, l));
+            Value::Symbol(store.get_str(s.0, s.1).into())
         }
 
         fn symbol_to_string[store, _k](s) {
-            if let Value::Symbol(s) = s {
-                let (a, l) = store.store_str(s.as_str());
-                Value::String(a, l)
-            } else {
-                unimplemented!("TODO Type Error in symbol->string: {}", s.display(store, false))
-            }
+            typeck_name!(s as Value::Symbol(s));
+            let (a, l) = store.store_str(s.as_str());
+            Value::String(a, l)
         }
     }
 
@@ -300,6 +610,25 @@ intrinsics! {
             Value::Fixnum(a & b)
         }
 
+        fn format[store, _k](radix_val, width, n) {
+            let radix = match radix(radix_val) {
+                Some(radix) => radix,
+                None => runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!("radix {}", radix_val.display(store, false)),
+                )),
+            };
+            typeck_name!(width as Value::Fixnum(width));
+            let n = try_runtime!(to_bigint(__intrinsics_name!(), n, store));
+            let digits = n.abs().to_str_radix(radix);
+            let sign = if n.is_negative() { "-" } else { "" };
+            let width = if width < 0 { 0 } else { width as usize };
+            let padding = width.saturating_sub(sign.len() + digits.len());
+            let s = format!("{}{}{}", sign, "0".repeat(padding), digits);
+            let (a, l) = store.store_str(&s);
+            Value::String(a, l)
+        }
+
         fn not[_s, _k](n) {
             typeck_name!(n as Value::Fixnum(n));
             Value::Fixnum(!n)
@@ -310,6 +639,39 @@ intrinsics! {
             Value::Fixnum(a | b)
         }
 
+        fn parse[store, _k](s) {
+            typeck_name!(s as Value::String(a, l));
+            let n = parse_integer(store.get_str(s.0, s.1));
+            let val = match n {
+                Ok(n) => Ok(store.store_bignum(n)),
+                Err(msg) => {
+                    let (a, l) = store.store_str(&msg);
+                    Err(Value::String(a, l))
+                }
+            };
+            result(val, store)
+        }
+
+        fn parse_radix[store, _k](radix_val, s) {
+            let radix = match radix(radix_val) {
+                Some(radix) => radix,
+                None => runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!("radix {}", radix_val.display(store, false)),
+                )),
+            };
+            typeck_name!(s as Value::String(a, l));
+            let n = parse_integer_radix(store.get_str(s.0, s.1), radix);
+            let val = match n {
+                Ok(n) => Ok(store.store_bignum(n)),
+                Err(msg) => {
+                    let (a, l) = store.store_str(&msg);
+                    Err(Value::String(a, l))
+                }
+            };
+            result(val, store)
+        }
+
         fn rol[_s, _k](a, b) {
             typeck_name!(a as Value::Fixnum(a), b as Value::Fixnum(b));
             Value::Fixnum(a.rotate_left(b as u32))
@@ -336,85 +698,792 @@ intrinsics! {
         }
     }
 
+    mod "float" as float {
+        fn ceil[_s, _k](n) {
+            typeck_name!(n as Value::Float(n));
+            Value::Float(n.ceil())
+        }
+
+        fn floor[_s, _k](n) {
+            typeck_name!(n as Value::Float(n));
+            Value::Float(n.floor())
+        }
+
+        fn format[store, _k](n) {
+            typeck_name!(n as Value::Float(n));
+            let s = Value::Float(n).display(store, false).to_string();
+            let (a, l) = store.store_str(&s);
+            Value::String(a, l)
+        }
+
+        fn from_fixnum[store, _k](n) {
+            if !is_integer(n) {
+                type_error!("an integer", n)
+            }
+            Value::Float(try_runtime!(to_float(__intrinsics_name!(), n, store)))
+        }
+
+        fn parse[store, _k](s) {
+            typeck_name!(s as Value::String(a, l));
+            let n = parse_float(store.get_str(s.0, s.1)).ok().map(Value::Float);
+            option(n, store)
+        }
+
+        fn sqrt[_s, _k](n) {
+            typeck_name!(n as Value::Float(n));
+            Value::Float(n.sqrt())
+        }
+
+        fn to_fixnum[store, _k](n) {
+            typeck_name!(n as Value::Float(n));
+            if !n.is_finite() {
+                runtime_error!(RuntimeError::IntrinsicFailed(
+                    __intrinsics_name!(),
+                    format!("{} has no integer part", n),
+                ))
+            }
+            // isize::MAX isn't representable as a float, so it rounds up to
+            // 2^63, which is out of range.
+            let n = n.trunc();
+            if n >= isize::MIN as f64 && n < isize::MAX as f64 {
+                Value::Fixnum(n as isize)
+            } else {
+                store.store_bignum(BigInt::from_f64(n).unwrap())
+            }
+        }
+    }
+
+    mod "fs" as fs {
+        // Unlike the oftb module, these return results rather than raising
+        // errors; see io_result for the form of the errors.
+
+        fn exists[store, _k](path) {
+            use std::path::Path;
+
+            typeck_name!(path as Value::String(addr, len));
+            boolify(Path::new(store.get_str(path.0, path.1)).exists())
+        }
+
+        fn make_dir[store, _k](path) {
+            use std::fs::create_dir_all;
+
+            typeck_name!(path as Value::String(addr, len));
+            let r = {
+                let path = store.get_str(path.0, path.1);
+                create_dir_all(path)
+                    .map(|()| Value::Nil)
+                    .map_err(|err| (err.kind(), format!("Couldn't create {:?}: {}", path, err)))
+            };
+            io_result(r, store)
+        }
+
+        fn remove[store, _k](path) {
+            use std::fs::{remove_dir, remove_file, symlink_metadata};
+
+            typeck_name!(path as Value::String(addr, len));
+            let r = {
+                let path = store.get_str(path.0, path.1);
+                symlink_metadata(path)
+                    .and_then(|m| if m.is_dir() {
+                        remove_dir(path)
+                    } else {
+                        remove_file(path)
+                    })
+                    .map(|()| Value::Nil)
+                    .map_err(|err| (err.kind(), format!("Couldn't remove {:?}: {}", path, err)))
+            };
+            io_result(r, store)
+        }
+
+        fn remove_all[store, _k](path) {
+            use std::fs::{remove_dir_all, remove_file, symlink_metadata};
+
+            typeck_name!(path as Value::String(addr, len));
+            let r = {
+                let path = store.get_str(path.0, path.1);
+                symlink_metadata(path)
+                    .and_then(|m| if m.is_dir() {
+                        remove_dir_all(path)
+                    } else {
+                        remove_file(path)
+                    })
+                    .map(|()| Value::Nil)
+                    .map_err(|err| (err.kind(), format!("Couldn't remove {:?}: {}", path, err)))
+            };
+            io_result(r, store)
+        }
+
+        fn rename[store, _k](from, to) {
+            use std::fs::rename;
+
+            typeck_name!(from as Value::String(fa, fl), to as Value::String(ta, tl));
+            let r = {
+                let from = store.get_str(from.0, from.1);
+                let to = store.get_str(to.0, to.1);
+                rename(from, to)
+                    .map(|()| Value::Nil)
+                    .map_err(|err| (
+                        err.kind(),
+                        format!("Couldn't rename {:?} to {:?}: {}", from, to, err),
+                    ))
+            };
+            io_result(r, store)
+        }
+
+        fn stat[store, _k](path) {
+            use std::fs::symlink_metadata;
+            use std::time::UNIX_EPOCH;
+
+            // Symlinks aren't followed, so the type is the same as read_dir
+            // would give. The modification time is a Unix timestamp.
+            typeck_name!(path as Value::String(addr, len));
+            let r = {
+                let path = store.get_str(path.0, path.1);
+                symlink_metadata(path)
+                    .and_then(|m| m.modified().map(|t| (m, t)))
+                    .map_err(|err| (err.kind(), format!("Couldn't stat {:?}: {}", path, err)))
+            };
+            let r = r.map(|(m, modified)| {
+                let modified = match modified.duration_since(UNIX_EPOCH) {
+                    Ok(d) => d.as_secs() as isize,
+                    Err(err) => -(err.duration().as_secs() as isize),
+                };
+                let fields = [
+                    ("modified", Value::Fixnum(modified)),
+                    ("size", Value::Fixnum(m.len() as isize)),
+                    ("type", Value::Symbol(file_type_name(m.file_type()).into())),
+                ];
+                let mut map = store.store_map();
+                for &(k, v) in &fields {
+                    map = store.map_insert(map, Value::Symbol(k.into()), v).0;
+                }
+                Value::Map(map, fields.len())
+            });
+            io_result(r, store)
+        }
+    }
+
+    mod "handle" as handle {
+        fn close[store, _k](h) {
+            typeck_name!(h as Value::Handle(a));
+            store.close_handle(h);
+            Value::Nil
+        }
+
+        fn next_entry[store, _k](h) {
+            typeck_name!(h as Value::Handle(a));
+            let entry = match *store.get_handle(h) {
+                HandleState::Dir(ref mut dir) => match dir.next() {
+                    Some(r) => r
+                        .and_then(|e| e.file_type().map(|t| Some((e.path(), t))))
+                        .map_err(|err| err.to_string()),
+                    None => Ok(None),
+                },
+                HandleState::Closed => Err("The handle is closed".to_string()),
+                _ => Err("The handle isn't to a directory".to_string()),
+            };
+            match entry {
+                Ok(Some((p, t))) => {
+                    let entry = Literal::Cons(
+                        Box::new(Literal::String(p.display().to_string())),
+                        Box::new(Literal::Symbol(file_type_name(t).into())));
+                    let entry = store.store_literal(&entry);
+                    option(Some(entry), store)
+                }
+                Ok(None) => option(None, store),
+                Err(err) => runtime_error!(RuntimeError::IntrinsicFailed(__intrinsics_name!(), err)),
+            }
+        }
+
+        fn open[store, _k](mode, path) {
+            use std::fs::OpenOptions;
+
+            typeck_name!(mode as Value::Symbol(mode), path as Value::String(addr, len));
+            let mut opts = OpenOptions::new();
+            match mode.as_str() {
+                "read" => opts.read(true),
+                "write" => opts.write(true).create(true).truncate(true),
+                "append" => opts.append(true).create(true),
+                "read-write" => opts.read(true).write(true).create(true),
+                _ => type_error!("a file mode", Value::Symbol(mode)),
+            };
+            let file = {
+                let path = store.get_str(path.0, path.1);
+                opts.open(path).map_err(|err| RuntimeError::IntrinsicFailed(
+                    __intrinsics_name!(),
+                    format!("Couldn't open {:?}: {}", path, err),
+                ))
+            };
+            match file {
+                Ok(file) => store.store_handle(HandleState::File(file)),
+                Err(err) => runtime_error!(err),
+            }
+        }
+
+        fn open_dir[store, _k](path) {
+            use std::fs::read_dir;
+
+            typeck_name!(path as Value::String(addr, len));
+            let dir = {
+                let path = store.get_str(path.0, path.1);
+                read_dir(path).map_err(|err| RuntimeError::IntrinsicFailed(
+                    __intrinsics_name!(),
+                    format!("Couldn't read directory {:?}: {}", path, err),
+                ))
+            };
+            match dir {
+                Ok(dir) => store.store_handle(HandleState::Dir(dir)),
+                Err(err) => runtime_error!(err),
+            }
+        }
+
+        fn read_chunk[store, _k](n, h) {
+            typeck_name!(n as Value::Fixnum(n), h as Value::Handle(a));
+            if n < 0 {
+                runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!("can't read {} bytes", n),
+                ))
+            }
+            let buf = with_file(store, h, |file| {
+                let mut buf = Vec::new();
+                Read::by_ref(file).take(n as u64).read_to_end(&mut buf).map(|_| buf)
+            });
+            match buf {
+                Ok(buf) => {
+                    let (a, l) = store.store_bytes(&buf);
+                    Value::Bytes(a, l)
+                }
+                Err(err) => runtime_error!(RuntimeError::IntrinsicFailed(__intrinsics_name!(), err)),
+            }
+        }
+
+        fn seek[store, _k](whence, offset, h) {
+            use std::io::{Seek, SeekFrom};
+
+            typeck_name!(
+                whence as Value::Symbol(whence),
+                offset as Value::Fixnum(offset),
+                h as Value::Handle(a)
+            );
+            let pos = match whence.as_str() {
+                "start" if offset < 0 => runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!("can't seek to {}", offset),
+                )),
+                "start" => SeekFrom::Start(offset as u64),
+                "current" => SeekFrom::Current(offset as i64),
+                "end" => SeekFrom::End(offset as i64),
+                _ => type_error!("one of start, current, or end", Value::Symbol(whence)),
+            };
+            match with_file(store, h, |file| file.seek(pos)) {
+                Ok(pos) => Value::Fixnum(pos as isize),
+                Err(err) => runtime_error!(RuntimeError::IntrinsicFailed(__intrinsics_name!(), err)),
+            }
+        }
+
+        fn write_chunk[store, _k](bytes, h) {
+            typeck_name!(bytes as Value::Bytes(addr, len), h as Value::Handle(a));
+            let bytes = store.get_bytes(bytes.0, bytes.1).to_vec();
+            if let Err(err) = with_file(store, h, |file| file.write_all(&bytes)) {
+                runtime_error!(RuntimeError::IntrinsicFailed(__intrinsics_name!(), err))
+            }
+            Value::Nil
+        }
+    }
+
     mod "io" as io {
+        // Input is read from the store's stdin, so it can be replaced by fake
+        // input.
+
         fn debug_print[store, _k](value) {
             eprintln!("{}", value.display(store, false));
             Value::Nil
         }
 
+        fn eprint[store, _k](*args) {
+            if let Err(err) = print_values(&mut stderr(), store, args, true, false) {
+                runtime_error!(RuntimeError::IntrinsicFailed(__intrinsics_name!(), err.to_string()))
+            }
+            Value::Nil
+        }
+
+        fn eprintln[store, _k](*args) {
+            if let Err(err) = print_values(&mut stderr(), store, args, true, true) {
+                runtime_error!(RuntimeError::IntrinsicFailed(__intrinsics_name!(), err.to_string()))
+            }
+            Value::Nil
+        }
+
+        fn ewrite[store, _k](*args) {
+            if let Err(err) = print_values(&mut stderr(), store, args, false, false) {
+                runtime_error!(RuntimeError::IntrinsicFailed(__intrinsics_name!(), err.to_string()))
+            }
+            Value::Nil
+        }
+
+        fn ewrite_bytes[store, _k](bytes) {
+            typeck_name!(bytes as Value::Bytes(addr, len));
+            let bytes = store.get_bytes(bytes.0, bytes.1);
+            if let Err(err) = stderr().write_all(bytes) {
+                runtime_error!(RuntimeError::IntrinsicFailed(__intrinsics_name!(), err.to_string()))
+            }
+            Value::Nil
+        }
+
+        fn ewriteln[store, _k](*args) {
+            if let Err(err) = print_values(&mut stderr(), store, args, false, true) {
+                runtime_error!(RuntimeError::IntrinsicFailed(__intrinsics_name!(), err.to_string()))
+            }
+            Value::Nil
+        }
+
+        fn flush[_s, _k]() {
+            if let Err(err) = stdout().flush().and_then(|()| stderr().flush()) {
+                runtime_error!(RuntimeError::IntrinsicFailed(__intrinsics_name!(), err.to_string()))
+            }
+            Value::Nil
+        }
+
+        fn is_eof[store, _k]() {
+            match store.stdin().is_eof() {
+                Ok(eof) => boolify(eof),
+                Err(err) => runtime_error!(RuntimeError::IntrinsicFailed(
+                    __intrinsics_name!(),
+                    err.to_string(),
+                )),
+            }
+        }
+
         fn print[store, _k](*args) {
-            print_values(store, args, true);
+            if let Err(err) = print_values(&mut stdout(), store, args, true, false) {
+                runtime_error!(RuntimeError::IntrinsicFailed(__intrinsics_name!(), err.to_string()))
+            }
             Value::Nil
         }
 
         fn println[store, _k](*args) {
-            print_values(store, args, true);
-            println!();
+            if let Err(err) = print_values(&mut stdout(), store, args, true, true) {
+                runtime_error!(RuntimeError::IntrinsicFailed(__intrinsics_name!(), err.to_string()))
+            }
             Value::Nil
         }
 
+        fn read_all[store, _k]() {
+            let s = match store.stdin().read_all() {
+                Ok(s) => s,
+                Err(err) => runtime_error!(RuntimeError::IntrinsicFailed(
+                    __intrinsics_name!(),
+                    err.to_string(),
+                )),
+            };
+            let (a, l) = store.store_str(&s);
+            Value::String(a, l)
+        }
+
+        fn read_bytes[store, _k](n) {
+            typeck_name!(n as Value::Fixnum(n));
+            if n < 0 {
+                runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!("can't read {} bytes", n),
+                ))
+            }
+            let buf = match store.stdin().read_bytes(n as u64) {
+                Ok(buf) => buf,
+                Err(err) => runtime_error!(RuntimeError::IntrinsicFailed(
+                    __intrinsics_name!(),
+                    err.to_string(),
+                )),
+            };
+            let (a, l) = store.store_bytes(&buf);
+            Value::Bytes(a, l)
+        }
+
+        fn read_line[store, _k]() {
+            match store.stdin().read_line() {
+                Ok(None) => option(None, store),
+                Ok(Some(mut line)) => {
+                    if line.ends_with('\n') {
+                        line.pop();
+                        if line.ends_with('\r') {
+                            line.pop();
+                        }
+                    }
+                    let (a, l) = store.store_str(&line);
+                    option(Some(Value::String(a, l)), store)
+                }
+                Err(err) => runtime_error!(RuntimeError::IntrinsicFailed(
+                    __intrinsics_name!(),
+                    err.to_string(),
+                )),
+            }
+        }
+
         fn write[store, _k](*args) {
-            print_values(store, args, false);
+            if let Err(err) = print_values(&mut stdout(), store, args, false, false) {
+                runtime_error!(RuntimeError::IntrinsicFailed(__intrinsics_name!(), err.to_string()))
+            }
+            Value::Nil
+        }
+
+        fn write_bytes[store, _k](bytes) {
+            typeck_name!(bytes as Value::Bytes(addr, len));
+            let bytes = store.get_bytes(bytes.0, bytes.1);
+            if let Err(err) = stdout().write_all(bytes) {
+                runtime_error!(RuntimeError::IntrinsicFailed(__intrinsics_name!(), err.to_string()))
+            }
             Value::Nil
         }
 
         fn writeln[store, _k](*args) {
-            print_values(store, args, false);
-            println!();
+            if let Err(err) = print_values(&mut stdout(), store, args, false, true) {
+                runtime_error!(RuntimeError::IntrinsicFailed(__intrinsics_name!(), err.to_string()))
+            }
             Value::Nil
         }
+    }
 
-        fn write_bytes[store, _k](bytes) {
-            use std::io::{stdout, Write};
+    mod "map" as map {
+        // Keys can't contain vectors or bytes, since mutating them would
+        // change their hashes while they're in a map.
 
-            let bytes = if let Value::Bytes(addr, len) = bytes {
-                store.get_bytes(addr, len)
-            } else {
-                unimplemented!("TODO Type Error in write-bytes")
-            };
-            stdout().write_all(bytes).unwrap();
-            Value::Nil
+        fn get[store, _k](key, m) {
+            typeck_name!(m as Value::Map(a, l));
+            if key.contains_mutable(store) {
+                type_error!("a key without vectors or bytes", key)
+            }
+            let val = store.map_get(m.0, key);
+            option(val, store)
+        }
+
+        fn insert[store, _k](key, val, m) {
+            typeck_name!(m as Value::Map(a, l));
+            if key.contains_mutable(store) {
+                type_error!("a key without vectors or bytes", key)
+            }
+            let (a, added) = store.map_insert(m.0, key, val);
+            Value::Map(a, if added { m.1 + 1 } else { m.1 })
+        }
+
+        fn keys[store, _k](m) {
+            typeck_name!(m as Value::Map(a, l));
+            let mut l = Value::Nil;
+            for (k, _) in store.get_map(m.0).into_iter().rev() {
+                let head = store.store(k);
+                let tail = store.store(l);
+                l = Value::Cons(head, tail);
+            }
+            l
+        }
+
+        fn len[_s, _k](m) {
+            typeck_name!(m as Value::Map(a, l));
+            Value::Fixnum(m.1 as isize)
+        }
+
+        fn new[store, _k]() {
+            Value::Map(store.store_map(), 0)
+        }
+
+        fn remove[store, _k](key, m) {
+            typeck_name!(m as Value::Map(a, l));
+            if key.contains_mutable(store) {
+                type_error!("a key without vectors or bytes", key)
+            }
+            match store.map_remove(m.0, key) {
+                Some(a) => Value::Map(a, m.1 - 1),
+                None => Value::Map(m.0, m.1),
+            }
         }
     }
 
     mod "math" as math {
+        // Bytes wrap on overflow, while fixnums are promoted to bignums. The
+        // _checked, _saturating, and _wrapping variants only accept bytes and
+        // fixnums, and never promote. Dividing by zero is an error, except in
+        // the _checked variants, which return none instead.
+
         fn add[store, _k](l, r) {
             match (l, r) {
-                (Value::Byte(l), Value::Byte(r)) => Value::Byte(l + r),
-                (Value::Fixnum(l), Value::Fixnum(r)) => Value::Fixnum(l + r),
-                _ => panic!("TODO Math Type Error {} + {}", l.display(store, false), r.display(store, false)),
+                (Value::Byte(l), Value::Byte(r)) => Value::Byte(l.wrapping_add(r)),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                _ if !is_number(l) => type_error!("a number", l),
+                _ if !is_number(r) => type_error!("a number", r),
+                (Value::Float(_), _) | (_, Value::Float(_)) => {
+                    try_runtime!(float_op(__intrinsics_name!(), l, r, store, |l, r| l + r))
+                }
+                _ => try_runtime!(integer_op(
+                    __intrinsics_name!(),
+                    l,
+                    r,
+                    store,
+                    isize::checked_add,
+                    |l, r| l + r,
+                )),
+            }
+        }
+
+        fn add_checked[store, _k](l, r) {
+            let n = match (l, r) {
+                (Value::Byte(l), Value::Byte(r)) => l.checked_add(r).map(Value::Byte),
+                (Value::Fixnum(l), Value::Fixnum(r)) => l.checked_add(r).map(Value::Fixnum),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                (Value::Fixnum(_), _) => type_error!("a fixnum", r),
+                _ => type_error!("a byte or fixnum", l),
+            };
+            option(n, store)
+        }
+
+        fn add_saturating[_s, _k](l, r) {
+            match (l, r) {
+                (Value::Byte(l), Value::Byte(r)) => Value::Byte(l.saturating_add(r)),
+                (Value::Fixnum(l), Value::Fixnum(r)) => Value::Fixnum(l.saturating_add(r)),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                (Value::Fixnum(_), _) => type_error!("a fixnum", r),
+                _ => type_error!("a byte or fixnum", l),
+            }
+        }
+
+        fn add_wrapping[_s, _k](l, r) {
+            match (l, r) {
+                (Value::Byte(l), Value::Byte(r)) => Value::Byte(l.wrapping_add(r)),
+                (Value::Fixnum(l), Value::Fixnum(r)) => Value::Fixnum(l.wrapping_add(r)),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                (Value::Fixnum(_), _) => type_error!("a fixnum", r),
+                _ => type_error!("a byte or fixnum", l),
             }
         }
 
         fn divide[store, _k](l, r) {
             match (l, r) {
+                (Value::Byte(_), Value::Byte(0)) => {
+                    runtime_error!(RuntimeError::DivideByZero(__intrinsics_name!()))
+                }
                 (Value::Byte(l), Value::Byte(r)) => Value::Byte(l / r),
-                (Value::Fixnum(l), Value::Fixnum(r)) => Value::Fixnum(l / r),
-                _ => panic!("TODO Math Type Error {} / {}", l.display(store, false), r.display(store, false)),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                _ if !is_number(l) => type_error!("a number", l),
+                _ if !is_number(r) => type_error!("a number", r),
+                (Value::Float(_), _) | (_, Value::Float(_)) => {
+                    try_runtime!(float_op(__intrinsics_name!(), l, r, store, |l, r| l / r))
+                }
+                (_, Value::Fixnum(0)) => {
+                    runtime_error!(RuntimeError::DivideByZero(__intrinsics_name!()))
+                }
+                _ => try_runtime!(integer_op(
+                    __intrinsics_name!(),
+                    l,
+                    r,
+                    store,
+                    isize::checked_div,
+                    |l, r| l / r,
+                )),
+            }
+        }
+
+        fn divide_checked[store, _k](l, r) {
+            let n = match (l, r) {
+                (Value::Byte(l), Value::Byte(r)) => l.checked_div(r).map(Value::Byte),
+                (Value::Fixnum(l), Value::Fixnum(r)) => l.checked_div(r).map(Value::Fixnum),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                (Value::Fixnum(_), _) => type_error!("a fixnum", r),
+                _ => type_error!("a byte or fixnum", l),
+            };
+            option(n, store)
+        }
+
+        fn divide_saturating[_s, _k](l, r) {
+            match (l, r) {
+                (Value::Byte(_), Value::Byte(0)) | (Value::Fixnum(_), Value::Fixnum(0)) => {
+                    runtime_error!(RuntimeError::DivideByZero(__intrinsics_name!()))
+                }
+                (Value::Byte(l), Value::Byte(r)) => Value::Byte(l / r),
+                (Value::Fixnum(l), Value::Fixnum(r)) => {
+                    Value::Fixnum(l.checked_div(r).unwrap_or(isize::MAX))
+                }
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                (Value::Fixnum(_), _) => type_error!("a fixnum", r),
+                _ => type_error!("a byte or fixnum", l),
+            }
+        }
+
+        fn divide_wrapping[_s, _k](l, r) {
+            match (l, r) {
+                (Value::Byte(_), Value::Byte(0)) | (Value::Fixnum(_), Value::Fixnum(0)) => {
+                    runtime_error!(RuntimeError::DivideByZero(__intrinsics_name!()))
+                }
+                (Value::Byte(l), Value::Byte(r)) => Value::Byte(l.wrapping_div(r)),
+                (Value::Fixnum(l), Value::Fixnum(r)) => Value::Fixnum(l.wrapping_div(r)),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                (Value::Fixnum(_), _) => type_error!("a fixnum", r),
+                _ => type_error!("a byte or fixnum", l),
             }
         }
 
         fn modulo[store, _k](l, r) {
             match (l, r) {
+                (Value::Byte(_), Value::Byte(0)) => {
+                    runtime_error!(RuntimeError::DivideByZero(__intrinsics_name!()))
+                }
                 (Value::Byte(l), Value::Byte(r)) => Value::Byte(l % r),
-                (Value::Fixnum(l), Value::Fixnum(r)) => Value::Fixnum(l % r),
-                _ => panic!("TODO Math Type Error {} mod {}", l.display(store, false), r.display(store, false)),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                _ if !is_number(l) => type_error!("a number", l),
+                _ if !is_number(r) => type_error!("a number", r),
+                (Value::Float(_), _) | (_, Value::Float(_)) => {
+                    try_runtime!(float_op(__intrinsics_name!(), l, r, store, |l, r| l % r))
+                }
+                (_, Value::Fixnum(0)) => {
+                    runtime_error!(RuntimeError::DivideByZero(__intrinsics_name!()))
+                }
+                _ => try_runtime!(integer_op(
+                    __intrinsics_name!(),
+                    l,
+                    r,
+                    store,
+                    isize::checked_rem,
+                    |l, r| l % r,
+                )),
+            }
+        }
+
+        fn modulo_checked[store, _k](l, r) {
+            let n = match (l, r) {
+                (Value::Byte(l), Value::Byte(r)) => l.checked_rem(r).map(Value::Byte),
+                (Value::Fixnum(l), Value::Fixnum(r)) => l.checked_rem(r).map(Value::Fixnum),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                (Value::Fixnum(_), _) => type_error!("a fixnum", r),
+                _ => type_error!("a byte or fixnum", l),
+            };
+            option(n, store)
+        }
+
+        fn modulo_saturating[_s, _k](l, r) {
+            // The remainder can't overflow, even when the quotient would, so
+            // this is the same as modulo_wrapping.
+            match (l, r) {
+                (Value::Byte(_), Value::Byte(0)) | (Value::Fixnum(_), Value::Fixnum(0)) => {
+                    runtime_error!(RuntimeError::DivideByZero(__intrinsics_name!()))
+                }
+                (Value::Byte(l), Value::Byte(r)) => Value::Byte(l % r),
+                (Value::Fixnum(l), Value::Fixnum(r)) => Value::Fixnum(l.wrapping_rem(r)),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                (Value::Fixnum(_), _) => type_error!("a fixnum", r),
+                _ => type_error!("a byte or fixnum", l),
+            }
+        }
+
+        fn modulo_wrapping[_s, _k](l, r) {
+            match (l, r) {
+                (Value::Byte(_), Value::Byte(0)) | (Value::Fixnum(_), Value::Fixnum(0)) => {
+                    runtime_error!(RuntimeError::DivideByZero(__intrinsics_name!()))
+                }
+                (Value::Byte(l), Value::Byte(r)) => Value::Byte(l.wrapping_rem(r)),
+                (Value::Fixnum(l), Value::Fixnum(r)) => Value::Fixnum(l.wrapping_rem(r)),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                (Value::Fixnum(_), _) => type_error!("a fixnum", r),
+                _ => type_error!("a byte or fixnum", l),
             }
         }
 
         fn multiply[store, _k](l, r) {
             match (l, r) {
-                (Value::Byte(l), Value::Byte(r)) => Value::Byte(l * r),
-                (Value::Fixnum(l), Value::Fixnum(r)) => Value::Fixnum(l * r),
-                _ => panic!("TODO Math Type Error {} * {}", l.display(store, false), r.display(store, false)),
+                (Value::Byte(l), Value::Byte(r)) => Value::Byte(l.wrapping_mul(r)),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                _ if !is_number(l) => type_error!("a number", l),
+                _ if !is_number(r) => type_error!("a number", r),
+                (Value::Float(_), _) | (_, Value::Float(_)) => {
+                    try_runtime!(float_op(__intrinsics_name!(), l, r, store, |l, r| l * r))
+                }
+                _ => try_runtime!(integer_op(
+                    __intrinsics_name!(),
+                    l,
+                    r,
+                    store,
+                    isize::checked_mul,
+                    |l, r| l * r,
+                )),
+            }
+        }
+
+        fn multiply_checked[store, _k](l, r) {
+            let n = match (l, r) {
+                (Value::Byte(l), Value::Byte(r)) => l.checked_mul(r).map(Value::Byte),
+                (Value::Fixnum(l), Value::Fixnum(r)) => l.checked_mul(r).map(Value::Fixnum),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                (Value::Fixnum(_), _) => type_error!("a fixnum", r),
+                _ => type_error!("a byte or fixnum", l),
+            };
+            option(n, store)
+        }
+
+        fn multiply_saturating[_s, _k](l, r) {
+            match (l, r) {
+                (Value::Byte(l), Value::Byte(r)) => Value::Byte(l.saturating_mul(r)),
+                (Value::Fixnum(l), Value::Fixnum(r)) => Value::Fixnum(l.saturating_mul(r)),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                (Value::Fixnum(_), _) => type_error!("a fixnum", r),
+                _ => type_error!("a byte or fixnum", l),
+            }
+        }
+
+        fn multiply_wrapping[_s, _k](l, r) {
+            match (l, r) {
+                (Value::Byte(l), Value::Byte(r)) => Value::Byte(l.wrapping_mul(r)),
+                (Value::Fixnum(l), Value::Fixnum(r)) => Value::Fixnum(l.wrapping_mul(r)),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                (Value::Fixnum(_), _) => type_error!("a fixnum", r),
+                _ => type_error!("a byte or fixnum", l),
             }
         }
 
         fn subtract[store, _k](l, r) {
             match (l, r) {
-                (Value::Byte(l), Value::Byte(r)) => Value::Byte(l - r),
-                (Value::Fixnum(l), Value::Fixnum(r)) => Value::Fixnum(l - r),
-                _ => panic!("TODO Math Type Error {} - {}", l.display(store, false), r.display(store, false)),
+                (Value::Byte(l), Value::Byte(r)) => Value::Byte(l.wrapping_sub(r)),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                _ if !is_number(l) => type_error!("a number", l),
+                _ if !is_number(r) => type_error!("a number", r),
+                (Value::Float(_), _) | (_, Value::Float(_)) => {
+                    try_runtime!(float_op(__intrinsics_name!(), l, r, store, |l, r| l - r))
+                }
+                _ => try_runtime!(integer_op(
+                    __intrinsics_name!(),
+                    l,
+                    r,
+                    store,
+                    isize::checked_sub,
+                    |l, r| l - r,
+                )),
+            }
+        }
+
+        fn subtract_checked[store, _k](l, r) {
+            let n = match (l, r) {
+                (Value::Byte(l), Value::Byte(r)) => l.checked_sub(r).map(Value::Byte),
+                (Value::Fixnum(l), Value::Fixnum(r)) => l.checked_sub(r).map(Value::Fixnum),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                (Value::Fixnum(_), _) => type_error!("a fixnum", r),
+                _ => type_error!("a byte or fixnum", l),
+            };
+            option(n, store)
+        }
+
+        fn subtract_saturating[_s, _k](l, r) {
+            match (l, r) {
+                (Value::Byte(l), Value::Byte(r)) => Value::Byte(l.saturating_sub(r)),
+                (Value::Fixnum(l), Value::Fixnum(r)) => Value::Fixnum(l.saturating_sub(r)),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                (Value::Fixnum(_), _) => type_error!("a fixnum", r),
+                _ => type_error!("a byte or fixnum", l),
+            }
+        }
+
+        fn subtract_wrapping[_s, _k](l, r) {
+            match (l, r) {
+                (Value::Byte(l), Value::Byte(r)) => Value::Byte(l.wrapping_sub(r)),
+                (Value::Fixnum(l), Value::Fixnum(r)) => Value::Fixnum(l.wrapping_sub(r)),
+                (Value::Byte(_), _) => type_error!("a byte", r),
+                (Value::Fixnum(_), _) => type_error!("a fixnum", r),
+                _ => type_error!("a byte or fixnum", l),
             }
         }
     }
@@ -422,13 +1491,18 @@ intrinsics! {
     mod "obj" as obj {
         fn get_type[_s, _k](val) {
             let s = match val {
+                Value::Bignum(_) => "bignum",
                 Value::Byte(_) => "byte",
                 Value::Bytes(_,_) => "bytes",
-                Value::Closure(_) | Value::Intrinsic(_) => "function",
+                Value::Closure(_) | Value::Continuation(_) | Value::Intrinsic(_) => "function",
                 Value::Cons(_,_) => "cons",
                 Value::Fixnum(_) => "fixnum",
+                Value::Float(_) => "float",
+                Value::Handle(_) => "handle",
+                Value::Map(_, _) => "map",
                 Value::Nil => "nil",
                 Value::Object(ty, _) => ty.as_str(),
+                Value::Ref(_) => "ref",
                 Value::String(_, _) => "string",
                 Value::Symbol(_) => "symbol",
                 Value::Vector(_, _) => "vector",
@@ -439,7 +1513,10 @@ intrinsics! {
         fn make_object[store, _k](ty, val) {
             typeck_name!(ty as Value::Symbol(ty));
             if !ty.contains(':') {
-                panic!("Can't create object of reserved type {}", ty);
+                runtime_error!(RuntimeError::IntrinsicFailed(
+                    __intrinsics_name!(),
+                    format!("Can't create object of reserved type `{}'", ty),
+                ))
             }
             Value::Object(ty, store.store(val))
         }
@@ -449,12 +1526,9 @@ intrinsics! {
         fn read_dir[store, _k](path) {
             use std::fs::read_dir;
 
+            typeck_name!(path as Value::String(addr, len));
             let entries = {
-                let path = if let Value::String(addr, len) = path {
-                    store.get_str(addr, len)
-                } else {
-                    unimplemented!("TODO Type Error in read-dir")
-                };
+                let path = store.get_str(path.0, path.1);
 
                 let r = (|| {
                     read_dir(path)?
@@ -465,43 +1539,40 @@ intrinsics! {
                         }))
                         .collect::<Result<Vec<_>, _>>()
                 })();
-                match r {
-                    Ok(entries) => entries,
-                    Err(err) => panic!("(read_dir {:?}) -> {}", path, err),
-                }
+                r.map_err(|err| RuntimeError::IntrinsicFailed(
+                    __intrinsics_name!(),
+                    format!("Couldn't read directory {:?}: {}", path, err),
+                ))
+            };
+            let entries = match entries {
+                Ok(entries) => entries,
+                Err(err) => runtime_error!(err),
             };
 
             let entries = entries
                 .into_iter()
                 .map(|(p, t)| {
-                    let t = if t.is_dir() {
-                        "dir"
-                    } else if t.is_symlink() {
-                        "symlink"
-                    } else {
-                        "file"
-                    };
                     Literal::Cons(
                         Box::new(Literal::String(p.display().to_string())),
-                        Box::new(Literal::Symbol(t.into())))
+                        Box::new(Literal::Symbol(file_type_name(t).into())))
                 })
                 .collect();
             store.store_literal(&Literal::list(entries))
         }
 
         fn read_file[store, _k](path) {
+            typeck_name!(path as Value::String(addr, len));
             let data = {
-                let path = if let Value::String(addr, len) = path {
-                    store.get_str(addr, len)
-                } else {
-                    unimplemented!("TODO Type Error in read-file")
-                };
-                match parse_file(path) {
-                    Ok(data) => Literal::list(data),
-                    Err(err) => panic!("(read_file {:?}) -> {}", path, err),
-                }
+                let path = store.get_str(path.0, path.1);
+                parse_file(path).map_err(|err| RuntimeError::IntrinsicFailed(
+                    __intrinsics_name!(),
+                    format!("Couldn't read {:?}: {}", path, err),
+                ))
             };
-            store.store_literal(&data)
+            match data {
+                Ok(data) => store.store_literal(&Literal::list(data)),
+                Err(err) => runtime_error!(err),
+            }
         }
 
         fn write_file[store, _k](path, data) {
@@ -510,13 +1581,217 @@ intrinsics! {
             use std::path::Path;
 
             typeck_name!(path as Value::String(pa, pl), data as Value::Bytes(da, dl));
-            let path: &Path = store.get_str(path.0, path.1).as_ref();
-            let data = store.get_bytes(data.0, data.1);
-            if let Some(dir) = path.parent() {
-                create_dir_all(dir).ok();
+            let result = {
+                let path: &Path = store.get_str(path.0, path.1).as_ref();
+                let data = store.get_bytes(data.0, data.1);
+                if let Some(dir) = path.parent() {
+                    create_dir_all(dir).ok();
+                }
+                File::create(path)
+                    .and_then(|mut file| file.write_all(data))
+                    .map_err(|err| RuntimeError::IntrinsicFailed(
+                        __intrinsics_name!(),
+                        format!("Couldn't write to {:?}: {}", path, err),
+                    ))
+            };
+            match result {
+                Ok(()) => Value::Nil,
+                Err(err) => runtime_error!(err),
+            }
+        }
+    }
+
+    mod "process" as process {
+        fn cwd[store, _k]() {
+            use std::env::current_dir;
+
+            match current_dir() {
+                Ok(dir) => {
+                    let (a, l) = store.store_str(&dir.display().to_string());
+                    Value::String(a, l)
+                }
+                Err(err) => runtime_error!(RuntimeError::IntrinsicFailed(
+                    __intrinsics_name!(),
+                    format!("Couldn't get the current directory: {}", err),
+                )),
+            }
+        }
+
+        fn exit[_s, _k](code) {
+            typeck_name!(code as Value::Fixnum(code));
+            match exit_code(code) {
+                Some(code) => runtime_error!(RuntimeError::Exit(code)),
+                None => runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!("{} isn't a valid exit code", code),
+                )),
+            }
+        }
+
+        fn getenv[store, _k](name) {
+            use std::env::var;
+
+            typeck_name!(name as Value::String(addr, len));
+            // Variables whose values aren't valid UTF-8 are treated as unset.
+            let val = var(store.get_str(name.0, name.1)).ok();
+            let val = val.map(|val| {
+                let (a, l) = store.store_str(&val);
+                Value::String(a, l)
+            });
+            option(val, store)
+        }
+
+        fn program_name[store, _k]() {
+            let name = store.program_name().map(|name| name.to_string());
+            let name = name.map(|name| {
+                let (a, l) = store.store_str(&name);
+                Value::String(a, l)
+            });
+            option(name, store)
+        }
+
+        fn setenv[store, _k](name, val) {
+            use std::env::set_var;
+
+            typeck_name!(name as Value::String(na, nl), val as Value::String(va, vl));
+            let valid = {
+                let name = store.get_str(name.0, name.1);
+                !(name.is_empty() || name.contains('=') || name.contains('\0'))
+            };
+            if !valid {
+                type_error!("an environment variable name", Value::String(name.0, name.1))
             }
-            let mut file = File::create(path).expect("Couldn't open file for writing");
-            file.write_all(data).expect("Couldn't write to file");
+            set_var(store.get_str(name.0, name.1), store.get_str(val.0, val.1));
+            Value::Nil
+        }
+
+        fn spawn[store, _k](program, args) {
+            use std::process::{Command, Stdio};
+
+            typeck_name!(program as Value::String(addr, len));
+            let mut cmd = Command::new(store.get_str(program.0, program.1));
+            let mut l = args;
+            while let Value::Cons(h, t) = l {
+                let h = store.get(h);
+                typeck_name!(h as Value::String(addr, len));
+                cmd.arg(store.get_str(h.0, h.1));
+                l = store.get(t);
+            }
+            if l != Value::Nil {
+                type_error!("a list", args)
+            }
+            let child = cmd.stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|err| (
+                    err.kind(),
+                    format!("Couldn't spawn {:?}: {}", store.get_str(program.0, program.1), err),
+                ));
+            let child = child.map(|child| store.store_handle(HandleState::Process(child)));
+            io_result(child, store)
+        }
+
+        fn wait[store, _k](h) {
+            use std::mem::replace;
+
+            typeck_name!(h as Value::Handle(a));
+            let output = match replace(store.get_handle(h), HandleState::Closed) {
+                HandleState::Process(child) => child
+                    .wait_with_output()
+                    .map_err(|err| format!("Couldn't wait for the process: {}", err)),
+                HandleState::Closed => Err("The handle is closed".to_string()),
+                state => {
+                    *store.get_handle(h) = state;
+                    Err("The handle isn't to a process".to_string())
+                }
+            };
+            let output = match output {
+                Ok(output) => output,
+                Err(err) => runtime_error!(RuntimeError::IntrinsicFailed(__intrinsics_name!(), err)),
+            };
+
+            // A process killed by a signal has no exit status.
+            let status = output.status.code().map_or(Value::Nil, |n| Value::Fixnum(n as isize));
+            let (a, l) = store.store_bytes(&output.stdout);
+            let stdout = Value::Bytes(a, l);
+            let (a, l) = store.store_bytes(&output.stderr);
+            let stderr = Value::Bytes(a, l);
+            let fields = [("status", status), ("stderr", stderr), ("stdout", stdout)];
+            let mut map = store.store_map();
+            for &(k, v) in &fields {
+                map = store.map_insert(map, Value::Symbol(k.into()), v).0;
+            }
+            Value::Map(map, fields.len())
+        }
+    }
+
+    mod "random" as random {
+        fn bytes[store, _k](n) {
+            typeck_name!(n as Value::Fixnum(n));
+            if n < 0 {
+                runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!("can't make {} bytes", n),
+                ))
+            }
+            let mut buf = vec![0; n as usize];
+            store.rng().fill_bytes(&mut buf);
+            let (a, l) = store.store_bytes(&buf);
+            Value::Bytes(a, l)
+        }
+
+        fn fixnum[store, _k](lo, hi) {
+            typeck_name!(lo as Value::Fixnum(lo), hi as Value::Fixnum(hi));
+            if lo >= hi {
+                runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!("the range {}..{} is empty", lo, hi),
+                ))
+            }
+            // The width of the range may not fit in an isize, but always fits
+            // in a u64, and wrapping arithmetic gets it right.
+            let width = (hi as u64).wrapping_sub(lo as u64);
+            let n = store.rng().below(width);
+            Value::Fixnum(lo.wrapping_add(n as isize))
+        }
+
+        fn seed[store, _k](seed) {
+            use interpreter::Rng;
+
+            typeck_name!(seed as Value::Fixnum(seed));
+            store.set_rng(Rng::new(seed as u64));
+            Value::Nil
+        }
+
+        fn shuffle[store, _k](v) {
+            typeck_name!(v as Value::Vector(a, l));
+            let mut vals = store.get_vec(v.0, v.1);
+            for i in (1..vals.len()).rev() {
+                let j = store.rng().below(i as u64 + 1) as usize;
+                vals.swap(i, j);
+            }
+            let vals = vals.into_iter()
+                .map(|val| store.store(val))
+                .collect::<Vec<_>>();
+            let (a, l) = store.store_vec(&vals);
+            Value::Vector(a, l)
+        }
+    }
+
+    mod "ref" as refs {
+        fn get[store, _k](r) {
+            typeck_name!(r as Value::Ref(a));
+            store.get(r)
+        }
+
+        fn new[store, _k](val) {
+            Value::Ref(store.store_ref(val))
+        }
+
+        fn set[store, _k](r, val) {
+            typeck_name!(r as Value::Ref(a));
+            store.mutate(r, val);
             Value::Nil
         }
     }
@@ -524,12 +1799,8 @@ intrinsics! {
     mod "strings" as strings {
         fn append[store, _k](l, r) {
             // TODO if l is the last string on the heap, just extend it
-            let (la, ll, ra, rl) = match (l, r) {
-                (Value::String(la, ll), Value::String(ra, rl)) => {
-                    (la, ll, ra, rl)
-                },
-                _ => unimplemented!("TODO Type Error in string-append"),
-            };
+            typeck_name!(l as Value::String(la, ll), r as Value::String(ra, rl));
+            let ((la, ll), (ra, rl)) = (l, r);
 
             let lan: usize = la.into();
             let ran: usize = ra.into();
@@ -546,95 +1817,229 @@ intrinsics! {
             }
         }
 
+        fn compare[store, _k](l, r) {
+            typeck_name!(l as Value::String(a, l), r as Value::String(a, l));
+            let l = store.get_str(l.0, l.1);
+            let r = store.get_str(r.0, r.1);
+            match l.cmp(r) {
+                Ordering::Greater => Value::Symbol("gt".into()),
+                Ordering::Equal => Value::Symbol("eq".into()),
+                Ordering::Less => Value::Symbol("lt".into()),
+            }
+        }
+
+        fn downcase[store, _k](s) {
+            typeck_name!(s as Value::String(a, l));
+            let s = store.get_str(s.0, s.1).to_lowercase();
+            let (a, l) = store.store_str(&s);
+            Value::String(a, l)
+        }
+
+        fn find[store, _k](needle, haystack) {
+            typeck_name!(
+                needle as Value::String(a, l),
+                haystack as Value::String(a, l)
+            );
+            let n = {
+                let needle = store.get_str(needle.0, needle.1);
+                let haystack = store.get_str(haystack.0, haystack.1);
+                haystack
+                    .find(needle)
+                    .map(|i| Value::Fixnum(haystack[..i].chars().count() as isize))
+            };
+            option(n, store)
+        }
+
+        fn from_codepoints[store, _k](l) {
+            let mut lst = l;
+            let mut s = String::new();
+            while let Value::Cons(hd, tl) = lst {
+                match store.get(hd) {
+                    // Negative fixnums become too large when cast to usize.
+                    Value::Fixnum(n) => match ::std::char::from_u32(n as u32) {
+                        Some(ch) if n as usize <= 0x10ffff => s.push(ch),
+                        _ => runtime_error!(RuntimeError::OutOfBounds(
+                            __intrinsics_name!(),
+                            format!("{} is not a Unicode scalar value", n),
+                        )),
+                    },
+                    val => type_error!("a codepoint", val),
+                }
+                lst = store.get(tl);
+            }
+            if lst != Value::Nil {
+                type_error!("a list", l)
+            }
+            let (a, l) = store.store_str(&s);
+            Value::String(a, l)
+        }
+
         fn length[store, _k](s) {
-            if let Value::String(a, l) = s {
-                let s = store.get_str(a, l);
-                let n = s.chars().count();
-                Value::Fixnum(n as isize)
-            } else {
-                unimplemented!("TODO Type Error in string-length")
+            typeck_name!(s as Value::String(a, l));
+            let n = store.str_char_count(s.0, s.1);
+            Value::Fixnum(n as isize)
+        }
+
+        fn nth[store, _k](n, s) {
+            typeck_name!(n as Value::Fixnum(n), s as Value::String(a, l));
+            let ch = store.str_char_offset(s.0, s.1, n as usize).and_then(|i| {
+                store.get_str(s.0, s.1)[i..].chars().next()
+            });
+            match ch {
+                Some(ch) => Value::Fixnum(ch as u32 as isize),
+                None => runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!("index {} on string {:?}", n, store.get_str(s.0, s.1)),
+                )),
             }
         }
 
+        fn replace[store, _k](from, to, s) {
+            typeck_name!(
+                from as Value::String(a, l),
+                to as Value::String(a, l),
+                s as Value::String(a, l)
+            );
+            let s = {
+                let from = store.get_str(from.0, from.1);
+                let to = store.get_str(to.0, to.1);
+                store.get_str(s.0, s.1).replace(from, to)
+            };
+            let (a, l) = store.store_str(&s);
+            Value::String(a, l)
+        }
+
         fn slice[store, _k](start, end, s) {
-            let (addr, len) = if let (
-                Value::Fixnum(start_n),
-                Value::Fixnum(end_n),
-                Value::String(a, l),
-            ) = (start, end, s)
-            {
-                let s = store.get_str(a, l);
-                let start_n = start_n as usize;
-                let end_n = end_n as usize;
-                let start = s.char_indices().nth(start_n);
-                let end = s.char_indices().nth(end_n);
-                let a: usize = a.into();
-                match (start, end) {
-                    (Some((start, _)), Some((end, _))) => {
-                        (a + start, end - start)
-                    },
-                    (Some((start, _)), None) if end_n == s.chars().count() => {
-                        (a + start, end_n - start)
-                    },
-                    _ => unimplemented!(
-                        "TODO out of bounds in string-slice (bounds of [{}, {}) on string {:?})",
-                        start_n,
-                        end_n,
-                        s,
-                    ),
+            typeck_name!(
+                start as Value::Fixnum(start_n),
+                end as Value::Fixnum(end_n),
+                s as Value::String(a, l)
+            );
+            let (a, l) = s;
+            let start_i = store.str_char_offset(a, l, start as usize);
+            let end_i = store.str_char_offset(a, l, end as usize);
+            match (start_i, end_i) {
+                (Some(start_i), Some(end_i)) if start_i <= end_i => {
+                    let a: usize = a.into();
+                    Value::String((a + start_i).into(), end_i - start_i)
                 }
-            } else {
-                unimplemented!("TODO Type Error in string-slice")
+                _ => runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!(
+                        "bounds of [{}, {}) on string {:?}",
+                        start,
+                        end,
+                        store.get_str(a, l),
+                    ),
+                )),
+            }
+        }
+
+        fn split[store, _k](delim, s) {
+            typeck_name!(delim as Value::String(a, l), s as Value::String(a, l));
+            let parts = {
+                let delim = store.get_str(delim.0, delim.1);
+                let base = store.get_str(s.0, s.1);
+                let a: usize = s.0.into();
+                base.split(delim)
+                    .map(|part| {
+                        let offset = part.as_ptr() as usize - base.as_ptr() as usize;
+                        (a + offset, part.len())
+                    })
+                    .collect::<Vec<_>>()
             };
-            Value::String(addr.into(), len)
+            let mut lst = Value::Nil;
+            for (a, l) in parts.into_iter().rev() {
+                let hd = store.store(Value::String(a.into(), l));
+                let tl = store.store(lst);
+                lst = Value::Cons(hd, tl);
+            }
+            lst
+        }
+
+        fn to_codepoints[store, _k](s) {
+            typeck_name!(s as Value::String(a, l));
+            let chars = store.get_str(s.0, s.1).chars().collect::<Vec<_>>();
+            let mut lst = Value::Nil;
+            for ch in chars.into_iter().rev() {
+                let hd = store.store(Value::Fixnum(ch as u32 as isize));
+                let tl = store.store(lst);
+                lst = Value::Cons(hd, tl);
+            }
+            lst
+        }
+
+        fn upcase[store, _k](s) {
+            typeck_name!(s as Value::String(a, l));
+            let s = store.get_str(s.0, s.1).to_uppercase();
+            let (a, l) = store.store_str(&s);
+            Value::String(a, l)
+        }
+    }
+
+    mod "time" as time {
+        // Times are measured by the store's clock, which may be a fake clock.
+
+        fn monotonic_ns[store, _k]() {
+            let t = store.clock().monotonic();
+            Value::Fixnum((t.as_secs() * 1_000_000_000 + u64::from(t.subsec_nanos())) as isize)
+        }
+
+        fn sleep_ns[store, _k](ns) {
+            use std::time::Duration;
+
+            typeck_name!(ns as Value::Fixnum(ns));
+            if ns < 0 {
+                runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!("can't sleep for {}ns", ns),
+                ))
+            }
+            let ns = ns as u64;
+            let duration = Duration::new(ns / 1_000_000_000, (ns % 1_000_000_000) as u32);
+            store.clock().sleep(duration);
+            Value::Nil
+        }
+
+        fn unix_time[store, _k]() {
+            Value::Fixnum(store.clock().unix().as_secs() as isize)
         }
     }
 
     mod "types" as types {
-        fn is_byte    [_s, _k](x) { boolify(match x {
-            Value::Byte(_)      => true, _ => false }) }
-        fn is_bytes   [_s, _k](x) { boolify(match x {
-            Value::Bytes(_, _)  => true, _ => false }) }
-        fn is_cons    [_s, _k](x) { boolify(match x {
-            Value::Cons(_, _)   => true, _ => false }) }
-        fn is_fixnum  [_s, _k](x) { boolify(match x {
-            Value::Fixnum(_)    => true, _ => false }) }
-        fn is_function[_s, _k](x) { boolify(match x {
-            Value::Closure(_) => true, Value::Intrinsic(_) => true,
-            _ => false }) }
-        fn is_nil     [_s, _k](x) { boolify(match x {
-            Value::Nil          => true, _ => false }) }
-        fn is_string  [_s, _k](x) { boolify(match x {
-            Value::String(_, _) => true, _ => false }) }
-        fn is_symbol  [_s, _k](x) { boolify(match x {
-            Value::Symbol(_)    => true, _ => false }) }
-        fn is_vector  [_s, _k](x) { boolify(match x {
-            Value::Vector(_, _) => true, _ => false }) }
+        fn is_bignum  [_s, _k](x) { boolify(matches!(x, Value::Bignum(_))) }
+        fn is_byte    [_s, _k](x) { boolify(matches!(x, Value::Byte(_))) }
+        fn is_bytes   [_s, _k](x) { boolify(matches!(x, Value::Bytes(_, _))) }
+        fn is_cons    [_s, _k](x) { boolify(matches!(x, Value::Cons(_, _))) }
+        fn is_fixnum  [_s, _k](x) { boolify(matches!(x, Value::Fixnum(_))) }
+        fn is_float   [_s, _k](x) { boolify(matches!(x, Value::Float(_))) }
+        fn is_function[_s, _k](x) { boolify(matches!(x,
+            Value::Closure(_) | Value::Continuation(_) | Value::Intrinsic(_))) }
+        fn is_handle  [_s, _k](x) { boolify(matches!(x, Value::Handle(_))) }
+        fn is_map     [_s, _k](x) { boolify(matches!(x, Value::Map(_, _))) }
+        fn is_nil     [_s, _k](x) { boolify(matches!(x, Value::Nil)) }
+        fn is_ref     [_s, _k](x) { boolify(matches!(x, Value::Ref(_))) }
+        fn is_string  [_s, _k](x) { boolify(matches!(x, Value::String(_, _))) }
+        fn is_symbol  [_s, _k](x) { boolify(matches!(x, Value::Symbol(_))) }
+        fn is_vector  [_s, _k](x) { boolify(matches!(x, Value::Vector(_, _))) }
     }
 
     mod "vector" as vector {
         fn append[store, _k](l, r) {
             // TODO: Make this more optimized.
-            match (l, r) {
-                (Value::Vector(la, ll), Value::Vector(ra, rl)) => {
-                    let mut vals = store.get_vec(la, ll).into_iter()
-                        .map(|val| store.store(val))
-                        .collect::<Vec<_>>();
-                    vals.extend(store.get_vec(ra, rl).into_iter()
-                        .map(|val| store.store(val)));
-                    let (a, l) = store.store_vec(&vals);
-                    Value::Vector(a, l)
-                }
-                _ => unimplemented!("TODO type error in vector-append")
-            }
+            typeck_name!(l as Value::Vector(la, ll), r as Value::Vector(ra, rl));
+            let mut vals = store.get_vec(l.0, l.1).into_iter()
+                .map(|val| store.store(val))
+                .collect::<Vec<_>>();
+            vals.extend(store.get_vec(r.0, r.1).into_iter()
+                .map(|val| store.store(val)));
+            let (a, l) = store.store_vec(&vals);
+            Value::Vector(a, l)
         }
 
         fn length[_s, _k](s) {
-            if let Value::Vector(_, l) = s {
-                Value::Fixnum(l as isize)
-            } else {
-                unimplemented!("TODO Type Error in vector-length")
-            }
+            typeck_name!(s as Value::Vector(a, l));
+            Value::Fixnum(s.1 as isize)
         }
 
         fn make[store, konts](func, len) {
@@ -650,44 +2055,50 @@ intrinsics! {
         }
 
         fn nth[store, _k](n, s) {
-            if let (Value::Fixnum(n), Value::Vector(a, l)) = (n, s) {
-                let n = n as usize;
-                if n < l {
-                    let a: usize = a.into();
-                    store.get_vec_val(Addr::from(a + n))
-                } else {
-                    unimplemented!("TODO out of bounds in vector-nth")
-                }
+            typeck_name!(n as Value::Fixnum(n), s as Value::Vector(a, l));
+            let (a, l) = s;
+            if n >= 0 && (n as usize) < l {
+                let a: usize = a.into();
+                store.get_vec_val(Addr::from(a + n as usize))
             } else {
-                unimplemented!("TODO Type Error in vector-nth")
+                runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!("index {} on vector of length {}", n, l),
+                ))
             }
         }
 
-        fn slice[_s, _k](start, end, s) {
-            let (a, l) = if let (
-                Value::Fixnum(start),
-                Value::Fixnum(end),
-                Value::Vector(a, l),
-            ) = (start, end, s)
-            {
-                let start = start as usize;
-                let end = end as usize;
-                if start <= l && end <= l && start <= end {
-                    let a: usize = a.into();
-                    (a + start as usize, end - start)
-                } else {
-                    unimplemented!(
-                        "TODO out of bounds in vector-slice (bounds of [{}, {}) on vector of length {})",
-                        start,
-                        end,
-                        l,
-                    )
-                }
+        fn set[store, _k](n, val, s) {
+            typeck_name!(n as Value::Fixnum(n), s as Value::Vector(a, l));
+            let (a, l) = s;
+            if n >= 0 && (n as usize) < l {
+                let a: usize = a.into();
+                store.mutate_vec_val(Addr::from(a + n as usize), val);
+                Value::Nil
             } else {
-                unimplemented!("TODO Type Error in vector-slice")
-            };
+                runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!("index {} on vector of length {}", n, l),
+                ))
+            }
+        }
 
-            Value::Vector(a.into(), l)
+        fn slice[_s, _k](start, end, s) {
+            typeck_name!(
+                start as Value::Fixnum(start),
+                end as Value::Fixnum(end),
+                s as Value::Vector(a, l)
+            );
+            let (a, l) = s;
+            if 0 <= start && start <= end && end as usize <= l {
+                let a: usize = a.into();
+                Value::Vector((a + start as usize).into(), (end - start) as usize)
+            } else {
+                runtime_error!(RuntimeError::OutOfBounds(
+                    __intrinsics_name!(),
+                    format!("bounds of [{}, {}) on vector of length {}", start, end, l),
+                ))
+            }
         }
     }
 }
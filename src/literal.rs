@@ -1,16 +1,19 @@
 use std::fmt::{Display, Formatter, Result as FmtResult};
 
+use num::BigInt;
 use symbol::Symbol;
 
-use util::{escape_bytes, escape_str};
+use util::{escape_bytes, escape_str, write_float};
 
 /// A literal value.
 #[derive(Clone, Debug, PartialEq)]
 pub enum Literal {
+    Bignum(BigInt),
     Byte(u8),
     Bytes(Vec<u8>),
     Cons(Box<Literal>, Box<Literal>),
     Fixnum(isize),
+    Float(f64),
     Nil,
     String(String),
     Symbol(Symbol),
@@ -124,7 +127,8 @@ impl Literal {
 impl Display for Literal {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
         match *self {
-            Literal::Byte(n) => write!(fmt, "{}", n),
+            Literal::Bignum(ref n) => write!(fmt, "{}", n),
+            Literal::Byte(n) => write!(fmt, "{}B", n),
             Literal::Bytes(ref bs) => escape_bytes(bs, fmt),
             Literal::Cons(ref h, ref t) => {
                 write!(fmt, "({}", h)?;
@@ -145,6 +149,7 @@ impl Display for Literal {
                 write!(fmt, ")")
             }
             Literal::Fixnum(n) => write!(fmt, "{}", n),
+            Literal::Float(n) => write_float(n, fmt),
             Literal::Nil => write!(fmt, "()"),
             Literal::String(ref s) => escape_str(s, fmt),
             Literal::Symbol(s) => write!(fmt, "{}", s),
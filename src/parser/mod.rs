@@ -11,6 +11,8 @@ use literal::Literal;
 mod convert;
 mod symbolish;
 
+pub use self::symbolish::{parse_float, parse_integer, parse_integer_radix};
+
 #[cfg(test)]
 mod tests;
 
@@ -1,4 +1,4 @@
-use num::{BigInt, /*BigRational,*/ BigUint, ToPrimitive, Zero};
+use num::{BigInt, BigUint, ToPrimitive, Zero};
 use pest::iterators::{Pair, Pairs};
 use pest::Parser;
 
@@ -13,31 +13,88 @@ const _GRAMMAR: &'static str = include_str!("../symbolish.pest");
 pub struct SymbolishParser;
 
 enum Number {
-    //Byte(BigUint),
+    Byte(BigUint),
     Fixnum(BigInt),
-    //Float(BigRational),
+    Float(f64),
 }
 
 /// Converts a symbolish string into the appropriate literal.
 pub fn parse_symbolish(s: &str) -> Result<Literal, String> {
     match SymbolishParser::parse(Rule::root, s) {
         Ok(pairs) => match convert(pairs) {
-            //Number::Byte(n) => unimplemented!(),
+            Number::Byte(n) => if let Some(n) = n.to_u8() {
+                Ok(Literal::Byte(n))
+            } else {
+                Err(format!("Invalid byte: {}", n))
+            },
             Number::Fixnum(n) => if let Some(n) = n.to_isize() {
                 Ok(Literal::Fixnum(n))
             } else {
-                Err(format!("Invalid fixnum: {}", n))
+                Ok(Literal::Bignum(n))
             },
-            //Number::Float(n) => if let Some(n) = n.to_f64() {
-            //Ok(Literal::Float(n))
-            //} else {
-            //Err(format!("Invalid float: {}", n))
-            //},
+            Number::Float(n) => Ok(Literal::Float(n)),
         },
         Err(_) => Ok(Literal::Symbol(s.into())),
     }
 }
 
+/// Parses a string as an integer, using the same rules as the reader does for
+/// integer literals.
+pub fn parse_integer(s: &str) -> Result<BigInt, String> {
+    match SymbolishParser::parse(Rule::root, s) {
+        Ok(pairs) => match convert(pairs) {
+            Number::Fixnum(n) => Ok(n),
+            _ => Err(format!("Not an integer: {:?}", s)),
+        },
+        Err(_) => Err(format!("Not an integer: {:?}", s)),
+    }
+}
+
+/// Parses a string as a float, using the same rules as the reader does for
+/// float literals.
+pub fn parse_float(s: &str) -> Result<f64, String> {
+    match SymbolishParser::parse(Rule::root, s) {
+        Ok(pairs) => match convert(pairs) {
+            Number::Float(n) => Ok(n),
+            _ => Err(format!("Not a float: {:?}", s)),
+        },
+        Err(_) => Err(format!("Not a float: {:?}", s)),
+    }
+}
+
+/// Parses a string of digits in the given radix as an integer. As in integer
+/// literals, the digits may be preceded by a `-`, but not by a `+`. Radix
+/// prefixes such as `0x` are not accepted.
+///
+/// Panics if the radix is greater than 36.
+pub fn parse_integer_radix(s: &str, radix: u32) -> Result<BigInt, String> {
+    let (sign, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+    if digits.is_empty() {
+        return Err(format!("Not an integer: {:?}", s));
+    }
+
+    let mut n = BigUint::zero();
+    for ch in digits.chars() {
+        match ch.to_digit(radix) {
+            Some(d) => {
+                n *= radix;
+                n += d;
+            }
+            None => return Err(format!("Invalid digit in radix {}: {:?}", radix, s)),
+        }
+    }
+
+    let signed = BigInt::from(n);
+    if sign {
+        Ok(-signed)
+    } else {
+        Ok(signed)
+    }
+}
+
 fn convert(mut pairs: Pairs<Rule>) -> Number {
     let pair = pairs.next().unwrap();
     assert_eq!(pairs.next(), None);
@@ -55,13 +112,17 @@ fn convert_symbolish(mut pairs: Pairs<Rule>) -> Number {
     let pair = pairs.next().unwrap();
     assert_eq!(pairs.next(), None);
     match pair.as_rule() {
-        //Rule::byte => unimplemented!("{:#?}", pair),
+        Rule::byte => Number::Byte(convert_byte(pair.into_inner())),
         Rule::fixnum => Number::Fixnum(convert_fixnum(pair.into_inner())),
-        //Rule::float => unimplemented!("{:#?}", pair),
+        Rule::float => Number::Float(convert_float(pair)),
         r => panic!("Invalid rule: {:?}", r),
     }
 }
 
+fn convert_byte(pairs: Pairs<Rule>) -> BigUint {
+    convert_unsigned(pairs)
+}
+
 fn convert_fixnum(mut pairs: Pairs<Rule>) -> BigInt {
     let sign = pairs.next().unwrap();
     let unsigned = pairs.next().unwrap();
@@ -80,9 +141,13 @@ fn convert_fixnum(mut pairs: Pairs<Rule>) -> BigInt {
     }
 }
 
-//fn convert_float(mut pairs: Pairs<Rule>) -> BigRational {
-//unimplemented!("{:?}", pairs)
-//}
+fn convert_float(pair: Pair<Rule>) -> f64 {
+    // The grammar only accepts strings that Rust's float parser does, and it
+    // rounds correctly, so we don't bother converting the digits ourselves.
+    pair.as_str()
+        .parse()
+        .unwrap_or_else(|err| panic!("Invalid float {:?}: {}", pair.as_str(), err))
+}
 
 fn convert_sign(pair: Pair<Rule>) -> bool {
     match pair.as_str() {
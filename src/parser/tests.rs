@@ -1,7 +1,38 @@
 use pest::{Error as PestError, Position};
 
 use literal::Literal;
-use parser::{parse_program, Rule};
+use parser::{parse_float, parse_integer, parse_integer_radix, parse_program, Rule};
+
+#[test]
+fn bytes() {
+    let r = parse_program("255B 0xff_B 0b101B 0x1B 0xAB 0x1b_B 0xffB");
+    assert_eq!(
+        r,
+        Ok(vec![
+            Literal::Byte(255),
+            Literal::Byte(255),
+            Literal::Byte(5),
+            Literal::Fixnum(27),
+            Literal::Fixnum(0xab),
+            Literal::Byte(0x1b),
+            Literal::Fixnum(0xffb),
+        ])
+    );
+    assert_eq!(Literal::Byte(255).to_string(), "255B");
+
+    let src = "(256B)";
+    let r = parse_program(src);
+    assert_eq!(
+        r,
+        Err(PestError::CustomErrorSpan {
+            message: "Invalid byte: 256".to_string(),
+            span: Position::from_start(src)
+                .skip(1)
+                .unwrap()
+                .span(&Position::from_start(src).skip(5).unwrap()),
+        })
+    );
+}
 
 #[test]
 fn improper_list() {
@@ -15,6 +46,28 @@ fn improper_list() {
     );
 }
 
+#[test]
+fn integers_parse_like_literals() {
+    for src in &["42", "-42", "0x2a", "-0b101010", "123456789012345678901234567890"] {
+        let lit = match parse_program(src).unwrap()[0] {
+            Literal::Bignum(ref n) => n.clone(),
+            Literal::Fixnum(n) => n.into(),
+            ref lit => panic!("Not an integer: {}", lit),
+        };
+        assert_eq!(parse_integer(src), Ok(lit));
+    }
+    assert!(parse_integer("+42").is_err());
+    assert!(parse_integer("0x").is_err());
+    assert!(parse_integer("1.5").is_err());
+    assert!(parse_integer("255B").is_err());
+
+    assert_eq!(parse_integer_radix("-2a", 16), Ok((-42).into()));
+    assert_eq!(parse_integer_radix("z", 36), Ok(35.into()));
+    assert!(parse_integer_radix("0x2a", 16).is_err());
+    assert!(parse_integer_radix("2", 2).is_err());
+    assert!(parse_integer_radix("-", 10).is_err());
+}
+
 #[test]
 fn invalid_bytes_escape() {
     let src = r#"b"\x01\x23\u4567""#;
@@ -58,3 +111,40 @@ fn numbers() {
         ])
     );
 }
+
+#[test]
+fn floats() {
+    let r = parse_program("1.5 -2. 0.1 3");
+    assert_eq!(
+        r,
+        Ok(vec![
+            Literal::Float(1.5),
+            Literal::Float(-2.0),
+            Literal::Float(0.1),
+            Literal::Fixnum(3),
+        ])
+    );
+
+    let printed = r.unwrap()
+        .into_iter()
+        .map(|lit| lit.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(printed, vec!["1.5", "-2.0", "0.1", "3"]);
+}
+
+#[test]
+fn floats_parse_like_literals() {
+    for src in &["1.5", "-2.", "0.1", "12345678901234567890.5"] {
+        let lit = match parse_program(src).unwrap()[0] {
+            Literal::Float(n) => n,
+            ref lit => panic!("Not a float: {}", lit),
+        };
+        assert_eq!(parse_float(src), Ok(lit));
+    }
+    for src in &["inf", "-inf", "NaN", "1e5", "1.5e5", ".5", "+1.5", "3", "1.5B"] {
+        assert!(parse_float(src).is_err(), "{:?} parsed as a float", src);
+        if let Literal::Float(_) = parse_program(src).unwrap()[0] {
+            panic!("{:?} read as a float", src);
+        }
+    }
+}
@@ -1,5 +1,13 @@
 use std::fmt::{Formatter, Result as FmtResult};
 
+use num::{BigInt, ToPrimitive};
+
+/// Converts a bignum to the nearest float, or to an infinity if it's too large
+/// to represent.
+pub fn bignum_to_float(n: &BigInt) -> f64 {
+    n.to_f64().unwrap_or_else(|| n.to_string().parse().unwrap())
+}
+
 /// Converts a string containing a single hex digit to that digit, panicking if
 /// the string does not contain exactly one hex digit.
 pub fn convert_hex_digit(s: &str) -> u8 {
@@ -58,6 +66,16 @@ pub fn escape_str(s: &str, fmt: &mut Formatter) -> FmtResult {
     write!(fmt, "\"")
 }
 
+/// Writes a float to the given Formatter, such that it will be read back as a
+/// float (rather than a fixnum) if it is finite.
+pub fn write_float(n: f64, fmt: &mut Formatter) -> FmtResult {
+    if n.is_finite() && n.trunc() == n {
+        write!(fmt, "{:.1}", n)
+    } else {
+        write!(fmt, "{}", n)
+    }
+}
+
 /// Looks up an address in the symbol table, returning the name that corresponds to it if one could
 /// be found.
 #[cfg(feature = "elf")]